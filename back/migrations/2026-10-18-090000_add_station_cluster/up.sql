@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE "station" ADD COLUMN "cluster" INT4;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "merged_feature";
+DROP TABLE IF EXISTS "observation";
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+-- The monthly partitions of the observations are created by the history import
+CREATE TABLE "observation"(
+	"station_id" INT4 NOT NULL,
+	"timestamp" TIMESTAMPTZ NOT NULL,
+	"capacity" INT4 NOT NULL,
+	"bikes" INT4 NOT NULL,
+	"stands" INT4 NOT NULL,
+	PRIMARY KEY("station_id", "timestamp")
+) PARTITION BY RANGE ("timestamp");
+
+CREATE TABLE "merged_feature"(
+	"station_id" INT4 NOT NULL,
+	"timestamp" TIMESTAMPTZ NOT NULL,
+	"hour" INT2 NOT NULL,
+	"minute" INT2 NOT NULL,
+	"day" INT2 NOT NULL,
+	"month" INT2 NOT NULL,
+	"week_day" INT2 NOT NULL,
+	"holidays" BOOL NOT NULL,
+	"free_stands" INT4 NOT NULL,
+	"available_bikes" INT4 NOT NULL,
+	"precipitation" FLOAT4 NOT NULL,
+	"temperature" FLOAT4 NOT NULL,
+	"wind_speed" FLOAT4 NOT NULL,
+	"cluster" INT4,
+	"capacity" INT4 NOT NULL,
+	PRIMARY KEY("station_id", "timestamp")
+);
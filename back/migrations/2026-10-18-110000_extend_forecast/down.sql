@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "forecast" DROP COLUMN "computed_at";
+ALTER TABLE "forecast" DROP COLUMN "model_version";
+ALTER TABLE "forecast" DROP COLUMN "low_confidence";
+ALTER TABLE "forecast" DROP COLUMN "stands_p90";
+ALTER TABLE "forecast" DROP COLUMN "stands_p50";
+ALTER TABLE "forecast" DROP COLUMN "stands_p10";
+ALTER TABLE "forecast" DROP COLUMN "bikes_p90";
+ALTER TABLE "forecast" DROP COLUMN "bikes_p50";
+ALTER TABLE "forecast" DROP COLUMN "bikes_p10";
+ALTER TABLE "forecast" DROP COLUMN "free_stands";
+ALTER TABLE "forecast" ALTER COLUMN "timestamp" TYPE TIMESTAMP USING "timestamp" AT TIME ZONE 'UTC';
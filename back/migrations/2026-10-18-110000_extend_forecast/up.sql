@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE "forecast" ALTER COLUMN "timestamp" TYPE TIMESTAMPTZ USING "timestamp" AT TIME ZONE 'UTC';
+ALTER TABLE "forecast" ADD COLUMN "free_stands" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "forecast" ADD COLUMN "bikes_p10" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "forecast" ADD COLUMN "bikes_p50" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "forecast" ADD COLUMN "bikes_p90" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "forecast" ADD COLUMN "stands_p10" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "forecast" ADD COLUMN "stands_p50" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "forecast" ADD COLUMN "stands_p90" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "forecast" ADD COLUMN "low_confidence" BOOL NOT NULL DEFAULT FALSE;
+ALTER TABLE "forecast" ADD COLUMN "model_version" VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE "forecast" ADD COLUMN "computed_at" TIMESTAMPTZ NOT NULL DEFAULT NOW();
@@ -0,0 +1,20 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM "forecast" WHERE "network" <> 'lyon';
+ALTER TABLE "forecast" DROP CONSTRAINT "forecast_pkey";
+ALTER TABLE "forecast" DROP COLUMN "network";
+ALTER TABLE "forecast" ADD PRIMARY KEY ("id", "timestamp");
+
+DELETE FROM "merged_feature" WHERE "network" <> 'lyon';
+ALTER TABLE "merged_feature" DROP CONSTRAINT "merged_feature_pkey";
+ALTER TABLE "merged_feature" DROP COLUMN "network";
+ALTER TABLE "merged_feature" ADD PRIMARY KEY ("station_id", "timestamp");
+
+DELETE FROM "observation" WHERE "network" <> 'lyon';
+ALTER TABLE "observation" DROP CONSTRAINT "observation_pkey";
+ALTER TABLE "observation" DROP COLUMN "network";
+ALTER TABLE "observation" ADD PRIMARY KEY ("station_id", "timestamp");
+
+DELETE FROM "station" WHERE "network" <> 'lyon';
+ALTER TABLE "station" DROP CONSTRAINT "station_pkey";
+ALTER TABLE "station" DROP COLUMN "network";
+ALTER TABLE "station" ADD PRIMARY KEY ("id");
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- The rows written before the networks belong to the Lyon network
+ALTER TABLE "station" ADD COLUMN "network" VARCHAR NOT NULL DEFAULT 'lyon';
+ALTER TABLE "station" DROP CONSTRAINT "station_pkey";
+ALTER TABLE "station" ADD PRIMARY KEY ("network", "id");
+
+ALTER TABLE "observation" ADD COLUMN "network" VARCHAR NOT NULL DEFAULT 'lyon';
+ALTER TABLE "observation" DROP CONSTRAINT "observation_pkey";
+ALTER TABLE "observation" ADD PRIMARY KEY ("network", "station_id", "timestamp");
+
+ALTER TABLE "merged_feature" ADD COLUMN "network" VARCHAR NOT NULL DEFAULT 'lyon';
+ALTER TABLE "merged_feature" DROP CONSTRAINT "merged_feature_pkey";
+ALTER TABLE "merged_feature" ADD PRIMARY KEY ("network", "station_id", "timestamp");
+
+ALTER TABLE "forecast" ADD COLUMN "network" VARCHAR NOT NULL DEFAULT 'lyon';
+ALTER TABLE "forecast" DROP CONSTRAINT "forecast_pkey";
+ALTER TABLE "forecast" ADD PRIMARY KEY ("network", "id", "timestamp");
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- The observations imported before the status was recorded have no status
+ALTER TABLE "observation" ADD COLUMN "status" VARCHAR;
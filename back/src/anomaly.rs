@@ -0,0 +1,361 @@
+use crate::config::Network;
+use crate::dedup::{dedup_station, DedupPolicy, RankedSnapshot};
+use crate::downloader::Value;
+use crate::learning::{download_order, list_files, UsefulData};
+use crate::models::Observation;
+use crate::schema;
+use chrono::{DateTime, Duration, Utc};
+use diesel::{
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper,
+};
+use log::info;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// File where the detected anomalies are stored.
+pub const ANOMALIES_FILE: &str = "anomalies.json";
+/// Minimum duration of identical counts for a station to be considered stuck.
+const STUCK_HOURS: i64 = 12;
+/// Minimum duration of an empty or full station to be considered abnormal.
+const STREAK_HOURS: i64 = 24;
+/// Duration of the recent observations scanned for the ongoing anomalies.
+const LIVE_WINDOW_HOURS: i64 = 2 * STREAK_HOURS;
+
+/// Kind of anomaly detected on a station.
+///
+/// # Variants
+///
+/// * `StuckCounts` - The station reported exactly the same counts for many hours.
+/// * `CapacityMismatch` - The capacity of the station is not the sum of its bikes and stands.
+/// * `EmptyStreak` - The station stayed empty for an unusually long time.
+/// * `FullStreak` - The station stayed full for an unusually long time.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    StuckCounts,
+    CapacityMismatch,
+    EmptyStreak,
+    FullStreak,
+}
+
+/// A period during which a station behaved abnormally.
+///
+/// # Fields
+///
+/// * `id` - The ID of the station.
+/// * `kind` - The kind of anomaly.
+/// * `start` - The date of the first abnormal snapshot.
+/// * `end` - The date of the last abnormal snapshot.
+/// * `snapshots` - The number of abnormal snapshots.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Anomaly {
+    pub id: u32,
+    pub kind: AnomalyKind,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub snapshots: usize,
+}
+
+impl Anomaly {
+    /// Checks whether a date is within the anomaly period.
+    pub fn contains(&self, date: DateTime<Utc>) -> bool {
+        date >= self.start && date <= self.end
+    }
+}
+
+/// Detects the anomalies of the Velov snapshots and writes them to `anomalies.json`.
+///
+/// The raw snapshots are used rather than the filtered ones, so that the capacity mismatches
+/// which are dropped by `filter_velov_data` are also reported. The snapshots of a station sharing
+/// a horodate are resolved by the dedup policy, as `filter_velov_data` does, so that both keep the
+/// same snapshot whatever the order the files are read in.
+///
+/// # Arguments
+///
+/// * `network` - The network, whose data directory holds the data files.
+/// * `dedup_policy` - The policy resolving the snapshots sharing a horodate.
+///
+/// # Examples
+///
+/// ```rust
+/// detect_anomalies(&network, DedupPolicy::LatestDownload);
+/// ```
+pub fn detect_anomalies(network: &Network, dedup_policy: DedupPolicy) {
+    info!("🔎 Reading velov snapshots..");
+    let files = download_order(list_files(&network.data_dir.join("velov_datas")));
+    let snapshots: Vec<RankedSnapshot> = files
+        .par_iter()
+        .enumerate()
+        .flat_map(|(download, path)| {
+            let reader = BufReader::new(File::open(path).unwrap());
+            let values: Vec<Value> = serde_json::from_reader(reader).unwrap();
+            values
+                .iter()
+                .map(|value| RankedSnapshot {
+                    download,
+                    snapshot: UsefulData {
+                        id: value.number as u32,
+                        date: value.horodate,
+                        capacity: value.total_stands.capacity as u32,
+                        bikes: value.total_stands.availabilities.bikes as u32,
+                        stands: value.total_stands.availabilities.stands as u32,
+                    },
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut per_station: HashMap<u32, Vec<RankedSnapshot>> = HashMap::new();
+    for snapshot in snapshots {
+        per_station
+            .entry(snapshot.snapshot.id)
+            .or_default()
+            .push(snapshot);
+    }
+
+    info!("🔎 Detecting anomalies..");
+    let mut anomalies: Vec<Anomaly> = per_station
+        .into_par_iter()
+        .flat_map(|(id, station_snapshots)| {
+            let (station_snapshots, _) = dedup_station(id, station_snapshots, dedup_policy);
+            detect_station_anomalies(&station_snapshots)
+        })
+        .collect();
+    anomalies.sort_by_key(|a| (a.id, a.start));
+
+    for kind in [
+        AnomalyKind::StuckCounts,
+        AnomalyKind::CapacityMismatch,
+        AnomalyKind::EmptyStreak,
+        AnomalyKind::FullStreak,
+    ] {
+        info!(
+            "⚠️ {:?} : {} periods",
+            kind,
+            anomalies.iter().filter(|a| a.kind == kind).count()
+        );
+    }
+
+    let path = network.data_dir.join(ANOMALIES_FILE);
+    let writer = BufWriter::new(File::create(&path).unwrap());
+    serde_json::to_writer(writer, &anomalies).unwrap();
+    info!("✅ {} written!", path.display());
+}
+
+/// Detects the ongoing anomalies from the recent observations of the stations.
+///
+/// The observations are the live snapshots stored in the `observation` table, so that the
+/// stations which broke down since the last `--detect-anomalies` are also reported.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `network` - The ID of the network of the stations.
+/// * `now` - The current date.
+///
+/// # Returns
+///
+/// * The anomalies of the observations of the last `LIVE_WINDOW_HOURS` hours, or the query
+///   error.
+pub fn detect_live_anomalies(
+    connection: &mut PgConnection,
+    network: &str,
+    now: DateTime<Utc>,
+) -> QueryResult<Vec<Anomaly>> {
+    use schema::observation::dsl::{observation, station_id, timestamp};
+    let observations: Vec<Observation> = observation
+        .select(Observation::as_select())
+        .filter(schema::observation::network.eq(network))
+        .filter(timestamp.ge(now - Duration::hours(LIVE_WINDOW_HOURS)))
+        .order((station_id, timestamp))
+        .load(connection)?;
+
+    let snapshots: Vec<UsefulData> = observations
+        .iter()
+        .map(|o| UsefulData {
+            id: o.station_id as u32,
+            date: o.timestamp,
+            capacity: o.capacity as u32,
+            bikes: o.bikes as u32,
+            stands: o.stands as u32,
+        })
+        .collect();
+    Ok(snapshots
+        .chunk_by(|a, b| a.id == b.id)
+        .flat_map(detect_station_anomalies)
+        .collect())
+}
+
+/// Detects the anomalies of a single station.
+///
+/// # Arguments
+///
+/// * `snapshots` - The snapshots of the station, sorted by date.
+///
+/// # Returns
+///
+/// * The anomalies of the station.
+pub fn detect_station_anomalies(snapshots: &[UsefulData]) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+
+    let mut push_runs = |kind: AnomalyKind, min_duration: Duration, runs: Vec<(usize, usize)>| {
+        for (start, end) in runs {
+            let (first, last) = (&snapshots[start], &snapshots[end]);
+            if last.date - first.date >= min_duration {
+                anomalies.push(Anomaly {
+                    id: first.id,
+                    kind,
+                    start: first.date,
+                    end: last.date,
+                    snapshots: end - start + 1,
+                });
+            }
+        }
+    };
+
+    push_runs(
+        AnomalyKind::StuckCounts,
+        Duration::hours(STUCK_HOURS),
+        find_runs(snapshots, |s| Some((s.bikes, s.stands))),
+    );
+    push_runs(
+        AnomalyKind::CapacityMismatch,
+        Duration::zero(),
+        find_runs(snapshots, |s| {
+            (s.capacity != s.bikes + s.stands).then_some(())
+        }),
+    );
+    push_runs(
+        AnomalyKind::EmptyStreak,
+        Duration::hours(STREAK_HOURS),
+        find_runs(snapshots, |s| (s.bikes == 0).then_some(())),
+    );
+    push_runs(
+        AnomalyKind::FullStreak,
+        Duration::hours(STREAK_HOURS),
+        find_runs(snapshots, |s| {
+            (s.stands == 0 && s.capacity > 0).then_some(())
+        }),
+    );
+
+    anomalies
+}
+
+/// Finds the runs of consecutive snapshots sharing the same key.
+///
+/// # Arguments
+///
+/// * `snapshots` - The snapshots to scan.
+/// * `key` - The key of a snapshot, or `None` if the snapshot must not be part of a run.
+///
+/// # Returns
+///
+/// * The indices of the first and last snapshots of each run.
+fn find_runs<K: PartialEq>(
+    snapshots: &[UsefulData],
+    key: impl Fn(&UsefulData) -> Option<K>,
+) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut current: Option<(usize, K)> = None;
+
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let snapshot_key = key(snapshot);
+        if matches!((&current, &snapshot_key), (Some((_, run_key)), Some(k)) if run_key == k) {
+            continue;
+        }
+        if let Some((start, _)) = current.take() {
+            runs.push((start, i - 1));
+        }
+        current = snapshot_key.map(|k| (i, k));
+    }
+    if let Some((start, _)) = current {
+        runs.push((start, snapshots.len() - 1));
+    }
+
+    runs
+}
+
+/// Reads the anomalies from `anomalies.json`.
+///
+/// # Arguments
+///
+/// * `dir` - The data directory of the network.
+///
+/// # Returns
+///
+/// * The stored anomalies, or an empty vector if the anomalies have not been detected yet.
+pub fn read_anomalies(dir: &Path) -> Vec<Anomaly> {
+    let path = dir.join(ANOMALIES_FILE);
+    if !path.exists() {
+        return Vec::new();
+    }
+    let reader = BufReader::new(File::open(path).unwrap());
+    serde_json::from_reader(reader).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Snapshots of a station every hour from the counts of bikes and stands.
+    fn snapshots(counts: &[(u32, u32)]) -> Vec<UsefulData> {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        counts
+            .iter()
+            .enumerate()
+            .map(|(i, &(bikes, stands))| UsefulData {
+                id: 7055,
+                date: start + Duration::hours(i as i64),
+                capacity: 20,
+                bikes,
+                stands,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_runs_of_identical_counts() {
+        let data = snapshots(&[(5, 15), (5, 15), (6, 14), (6, 14), (6, 14), (7, 13)]);
+
+        let runs = find_runs(&data, |s| Some((s.bikes, s.stands)));
+        assert_eq!(runs, vec![(0, 1), (2, 4), (5, 5)]);
+    }
+
+    #[test]
+    fn finds_the_empty_and_full_runs() {
+        let data = snapshots(&[(0, 20), (0, 20), (3, 17), (20, 0), (20, 0), (0, 20)]);
+
+        let empty = find_runs(&data, |s| (s.bikes == 0).then_some(()));
+        assert_eq!(empty, vec![(0, 1), (5, 5)]);
+        let full = find_runs(&data, |s| (s.stands == 0).then_some(()));
+        assert_eq!(full, vec![(3, 4)]);
+        assert!(find_runs(&[], |s: &UsefulData| Some(s.bikes)).is_empty());
+    }
+
+    #[test]
+    fn reports_the_long_runs_only() {
+        let mut counts = vec![(4, 16); STUCK_HOURS as usize + 1];
+        counts.extend(vec![(0, 20); STREAK_HOURS as usize + 1]);
+        counts.extend([(3, 16), (2, 18), (0, 20), (0, 20)]);
+
+        let anomalies = detect_station_anomalies(&snapshots(&counts));
+        let kinds: Vec<(AnomalyKind, usize)> =
+            anomalies.iter().map(|a| (a.kind, a.snapshots)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (AnomalyKind::StuckCounts, STUCK_HOURS as usize + 1),
+                (AnomalyKind::StuckCounts, STREAK_HOURS as usize + 1),
+                (AnomalyKind::CapacityMismatch, 1),
+                (AnomalyKind::EmptyStreak, STREAK_HOURS as usize + 1),
+            ]
+        );
+    }
+}
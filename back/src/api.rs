@@ -1,31 +1,50 @@
 use std::collections::HashMap;
 
-use crate::downloader::download_weather_forecast;
-use crate::learning::MergedData;
-use crate::models::BasicStation;
-use crate::utils::distance;
+use crate::anomaly::{detect_live_anomalies, Anomaly, AnomalyKind};
+use crate::config::config;
+use crate::downloader::{download_weather_forecast, nearest_cell, WeatherCell, WeatherData};
+use crate::forecast::{fresh_forecast, fresh_forecasts};
+use crate::gbfs::{
+    published_discovery, published_station_information, published_station_status,
+    published_system_information,
+};
+use crate::learning::{MergedData, SchoolHolidays};
+use crate::models::{BasicStation, Observation};
+use crate::rebalancing::{plan_transfers, StationOutlook, Transfer};
+use crate::utils::{
+    haversine, k_nearest, local_time, start_of_day, LocalDateTime, Quantiles, NEIGHBOURS,
+};
 use crate::AppState;
 use crate::{models::DetailedStation, schema};
-use axum::extract::{Path, Query, State};
+use axum::extract::{OriginalUri, Path, Query, State};
 use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use chrono::{DateTime, Datelike, Duration, DurationRound, Timelike, Utc};
+use chrono_tz::Tz;
 use diesel::{ExpressionMethods, PgTextExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{info, warn};
 
-/// Fetches the weather forecast data asynchronously.
+/// Fetches the weather forecast data at the city centre asynchronously.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the network.
 ///
 /// # Returns
 ///
 /// * `StatusCode::OK` with the forecast data in JSON format if successful.
 /// * `StatusCode::INTERNAL_SERVER_ERROR` with an error message if the download fails.
-pub async fn get_weather_forecast() -> impl IntoResponse {
-    let forecast_data = download_weather_forecast().await;
+pub async fn get_weather_forecast(State(state): State<AppState>) -> impl IntoResponse {
+    let forecast_data = download_weather_forecast(&state.network).await;
     match forecast_data {
-        Ok(forecast) => (StatusCode::OK, Json(forecast)).into_response(),
+        Ok(forecast) => (
+            StatusCode::OK,
+            Json(nearest_cell(&forecast, state.network.weather.centre())),
+        )
+            .into_response(),
         Err(_) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             "Error downloading weather forecast".to_owned(),
@@ -34,6 +53,15 @@ pub async fn get_weather_forecast() -> impl IntoResponse {
     }
 }
 
+/// Lists the networks served by the API, each under `/networks/:network/`.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the networks in JSON format, the one served at the root first.
+pub async fn get_networks() -> impl IntoResponse {
+    (StatusCode::OK, Json(config().networks())).into_response()
+}
+
 /// Retrieves detailed information about all stations from the database.
 ///
 /// # Arguments
@@ -50,6 +78,7 @@ pub async fn get_detailed_stations(State(state): State<AppState>) -> impl IntoRe
     use schema::station::dsl::station;
     match station
         .select(DetailedStation::as_select())
+        .filter(schema::station::network.eq(&state.network.id))
         .load(&mut *connection)
     {
         Ok(stations) => (
@@ -86,6 +115,7 @@ pub async fn get_stations(State(state): State<AppState>) -> impl IntoResponse {
     use schema::station::dsl::station;
     match station
         .select(BasicStation::as_select())
+        .filter(schema::station::network.eq(&state.network.id))
         .load(&mut *connection)
     {
         Ok(stations) => (StatusCode::OK, Json(stations)).into_response(),
@@ -117,6 +147,7 @@ pub async fn get_detailed_station(
     use schema::station::dsl::station;
     match station
         .select(DetailedStation::as_select())
+        .filter(schema::station::network.eq(&state.network.id))
         .filter(schema::station::id.eq(id))
         .limit(1)
         .first(&mut *connection)
@@ -146,6 +177,7 @@ pub async fn search_station(
     use schema::station::dsl::station;
     match station
         .select(DetailedStation::as_select())
+        .filter(schema::station::network.eq(&state.network.id))
         .filter(schema::station::name.ilike("%".to_owned() + &name + "%"))
         .limit(5)
         .load(&mut *connection)
@@ -160,21 +192,21 @@ pub async fn search_station(
 /// # Fields
 ///
 /// * `id` - The ID of the station.
-/// * `date` - The date and time for which to predict availability.
+/// * `date` - The date and time for which to predict availability, as RFC 3339 or local time.
 #[derive(Deserialize)]
 pub struct PredictParams {
     id: u32,
-    date: NaiveDateTime,
+    date: LocalDateTime,
 }
 
 /// Parameters for the `predict` function, including station ID and date.
 ///
 /// # Fields
 ///
-/// * `date` - The date and time for which to predict availability.
+/// * `date` - The date and time for which to predict availability, as RFC 3339 or local time.
 #[derive(Deserialize)]
 pub struct PredictionsParams {
-    date: NaiveDateTime,
+    date: LocalDateTime,
 }
 
 /// Data structure representing the availability of bikes and free stands at a station.
@@ -184,95 +216,477 @@ pub struct PredictionsParams {
 /// * `id` - The ID of the station.
 /// * `free_stands` - The number of free stands available.
 /// * `available_bikes` - The number of bikes available.
+/// * `low_confidence` - Whether the prediction was borrowed from similar stations.
+/// * `bikes_quantiles` - The 10th, 50th and 90th percentiles of the available bikes.
+/// * `stands_quantiles` - The 10th, 50th and 90th percentiles of the free stands.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AvailabilityData {
+    pub id: u32,
+    pub free_stands: u32,
+    pub available_bikes: u32,
+    pub low_confidence: bool,
+    pub bikes_quantiles: Quantiles,
+    pub stands_quantiles: Quantiles,
+}
+
+impl AvailabilityData {
+    /// Builds the availability of a station from its nearest data points.
+    ///
+    /// The occupancy of each point is rescaled to the current capacity of the station. The
+    /// availability is the one of the nearest point, and the quantiles are computed over all
+    /// the points.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the station.
+    /// * `points` - The nearest data points, sorted from the nearest to the farthest.
+    /// * `capacity` - The current capacity of the station, or `None` to keep the capacity of
+    ///   each data point.
+    ///
+    /// # Returns
+    ///
+    /// * The availability, or `None` if there is no point with a known occupancy.
+    fn from_neighbours(id: u32, points: &[&MergedData], capacity: Option<u32>) -> Option<Self> {
+        let predicted: Vec<(u32, u32)> = points
+            .iter()
+            .filter_map(|p| {
+                let capacity = capacity.unwrap_or(p.size());
+                let bikes = p.rescaled_bikes(capacity)?;
+                Some((bikes, capacity - bikes))
+            })
+            .collect();
+        let (available_bikes, free_stands) = *predicted.first()?;
+
+        Some(AvailabilityData {
+            id,
+            free_stands,
+            available_bikes,
+            low_confidence: false,
+            bikes_quantiles: Quantiles::from_values(
+                &predicted
+                    .iter()
+                    .map(|(bikes, _)| *bikes)
+                    .collect::<Vec<_>>(),
+            ),
+            stands_quantiles: Quantiles::from_values(
+                &predicted
+                    .iter()
+                    .map(|(_, stands)| *stands)
+                    .collect::<Vec<_>>(),
+            ),
+        })
+    }
+
+    /// Builds the availability of a station from a recorded data point.
+    fn observed(point: &MergedData) -> Self {
+        AvailabilityData {
+            id: point.id,
+            free_stands: point.free_stands,
+            available_bikes: point.available_bikes,
+            low_confidence: false,
+            bikes_quantiles: Quantiles::exact(point.available_bikes),
+            stands_quantiles: Quantiles::exact(point.free_stands),
+        }
+    }
+}
+
+/// Number of similar stations whose history is borrowed to predict a station without history.
+const COLD_START_DONORS: usize = 3;
+
+/// Checks whether a date is during the school holidays.
+///
+/// # Arguments
+///
+/// * `holidays` - The school holidays.
+/// * `date` - The date to check.
+///
+/// # Returns
+///
+/// * `true` if the date is during the school holidays, `false` otherwise.
+fn is_holidays(holidays: &[SchoolHolidays], date: DateTime<Tz>) -> bool {
+    let date = date.date_naive();
+    holidays.par_iter().any(|holiday| holiday.contains(date))
+}
+
+/// Returns the beginning of the current local day of a network, before which dates are looked up
+/// in the history.
+fn start_of_today(timezone: Tz) -> DateTime<Tz> {
+    start_of_day(local_time(Utc::now(), timezone))
+}
+
+/// Downloads the weather forecast of a network for a prediction.
+///
+/// # Returns
+///
+/// * The forecast, or the `StatusCode::INTERNAL_SERVER_ERROR` response if the download fails.
+async fn prediction_forecast(data: &AppState) -> Result<Vec<WeatherCell>, Response> {
+    download_weather_forecast(&data.network).await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error downloading weather forecast".to_owned(),
+        )
+            .into_response()
+    })
+}
+
+/// Returns the key of the hourly weather forecast covering a date.
+fn weather_key(date: DateTime<Tz>) -> DateTime<Utc> {
+    date.with_timezone(&Utc)
+        .duration_trunc(Duration::hours(1))
+        .unwrap()
+}
+
+/// Builds the data point to look for in the history of a station.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the station.
+/// * `date` - The local date and time of the prediction.
+/// * `is_holidays` - Whether the date is during the school holidays.
+/// * `weather` - The weather forecast for the date.
+/// * `cluster` - The usage cluster of the station.
+///
+/// # Returns
+///
+/// * The wanted data point, with no availability.
+fn wanted_point(
     id: u32,
-    free_stands: u32,
-    available_bikes: u32,
+    date: DateTime<Tz>,
+    is_holidays: bool,
+    weather: &WeatherData,
+    cluster: Option<u32>,
+) -> MergedData {
+    MergedData {
+        id,
+        hour: date.time().hour(),
+        minute: date.time().minute(),
+        day: date.day(),
+        month: date.month(),
+        week_day: date.weekday().num_days_from_monday(),
+        holidays: is_holidays,
+        free_stands: 0,
+        available_bikes: 0,
+        precipitation: weather.precipitation,
+        temperature: weather.temperature_2m,
+        wind_speed: weather.wind_speed_10m,
+        cluster,
+        capacity: 0,
+        timestamp: date.with_timezone(&Utc),
+    }
 }
 
-pub async fn predictions(
-    State(data): State<AppState>,
-    Query(params): Query<PredictionsParams>,
-) -> impl IntoResponse {
-    let is_holidays = data
-        .holidays
-        .par_iter()
-        .any(|holiday| params.date.date() >= holiday.start && params.date.date() <= holiday.end);
+/// Returns the recorded data points of a station between two dates.
+///
+/// # Arguments
+///
+/// * `station_data` - The history of the station, sorted by timestamp.
+/// * `from` - The start of the range, included.
+/// * `to` - The end of the range, included.
+///
+/// # Returns
+///
+/// * The recorded data points within the range.
+fn history_range(
+    station_data: &[MergedData],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> &[MergedData] {
+    let start = station_data.partition_point(|d| d.timestamp < from);
+    let end = station_data.partition_point(|d| d.timestamp <= to);
+    &station_data[start..end.max(start)]
+}
 
-    let forecast = download_weather_forecast().await.unwrap();
-    let now = chrono::Utc::now().naive_utc();
-    let weather_data = match forecast.get(&params.date.with_minute(0).unwrap().and_utc()) {
-        Some(data) => data,
+/// Finds the recorded data point of a station for a past date.
+///
+/// # Arguments
+///
+/// * `station_data` - The history of the station, sorted by timestamp.
+/// * `date` - The past date and time, matched to the minute.
+///
+/// # Returns
+///
+/// * The recorded data point if there is one.
+fn past_point(station_data: &[MergedData], date: DateTime<Tz>) -> Option<&MergedData> {
+    let from = date
+        .with_timezone(&Utc)
+        .duration_trunc(Duration::minutes(1))
+        .unwrap();
+    history_range(
+        station_data,
+        from,
+        from + Duration::minutes(1) - Duration::nanoseconds(1),
+    )
+    .first()
+}
+
+/// Finds the recorded availability of every station for a past date.
+///
+/// # Arguments
+///
+/// * `data` - The history of each station, sorted by timestamp.
+/// * `date` - The past date and time, matched to the minute.
+///
+/// # Returns
+///
+/// * The recorded availability of each station having a data point at that date.
+fn observed_all(
+    data: &HashMap<u32, Vec<MergedData>>,
+    date: DateTime<Tz>,
+) -> HashMap<u32, AvailabilityData> {
+    data.par_iter()
+        .filter_map(|(id, station_data)| {
+            past_point(station_data, date).map(|point| (*id, AvailabilityData::observed(point)))
+        })
+        .collect()
+}
+
+/// Finds the data points of a station the closest to the wanted point.
+///
+/// # Arguments
+///
+/// * `station_data` - The history of the station.
+/// * `wanted_point` - The data point to look for.
+///
+/// # Returns
+///
+/// * The `NEIGHBOURS` nearest data points, sorted from the nearest to the farthest.
+fn nearest_points<'a>(
+    station_data: &'a [MergedData],
+    wanted_point: &MergedData,
+) -> Vec<&'a MergedData> {
+    k_nearest(station_data, wanted_point, NEIGHBOURS)
+}
+
+/// Finds the stations the most similar to a station, among the ones having a history.
+///
+/// Stations in the same usage cluster come first, then the ones in the same area, then the
+/// nearest ones geographically.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the database connection and the history.
+/// * `id` - The ID of the station.
+///
+/// # Returns
+///
+/// * The station and its most similar stations, or `None` if the station is not in the database.
+fn similar_stations(state: &AppState, id: u32) -> Option<(DetailedStation, Vec<DetailedStation>)> {
+    let mut connection = state.connection.lock().unwrap();
+
+    use schema::station::dsl::station;
+    let stations = station
+        .select(DetailedStation::as_select())
+        .filter(schema::station::network.eq(&state.network.id))
+        .load(&mut *connection)
+        .ok()?;
+    let target = stations.iter().find(|s| s.id == id as i32)?.clone();
+
+    let mut donors: Vec<DetailedStation> = stations
+        .into_iter()
+        .filter(|s| s.id != target.id && state.data.contains_key(&(s.id as u32)))
+        .collect();
+    donors.sort_by(|a, b| {
+        let key = |s: &DetailedStation| {
+            (
+                target.cluster.is_some() && s.cluster != target.cluster,
+                s.area != target.area,
+                haversine(
+                    (target.latitude, target.longitude),
+                    (s.latitude, s.longitude),
+                ),
+            )
+        };
+        key(a).partial_cmp(&key(b)).unwrap()
+    });
+    donors.truncate(COLD_START_DONORS);
+
+    Some((target, donors))
+}
+
+/// Predicts the availability of a station without history by borrowing the history of similar
+/// stations, scaled by the capacity of the station.
+///
+/// # Arguments
+///
+/// * `data` - The application state containing the prediction data and holidays.
+/// * `id` - The ID of the station.
+/// * `date` - The date and time for which to predict availability.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the low-confidence availability data in JSON format if successful.
+/// * `StatusCode::NOT_FOUND` with an error message if the station or weather data is not found.
+/// * `StatusCode::INTERNAL_SERVER_ERROR` with an error message if the weather forecast cannot be
+///   downloaded.
+async fn cold_start_predict(data: &AppState, id: u32, date: DateTime<Tz>) -> Response {
+    let (target, donors) = match similar_stations(data, id) {
+        Some(stations) if !stations.1.is_empty() => stations,
+        _ => return (StatusCode::NOT_FOUND, "Station not found".to_owned()).into_response(),
+    };
+    info!(
+        "🆕 No history for station {}, borrowing from stations {:?}",
+        id,
+        donors.iter().map(|d| d.id).collect::<Vec<_>>()
+    );
+
+    let is_past = date < start_of_today(data.network.timezone);
+    let is_holidays = is_holidays(&data.holidays, date);
+    let forecast = match prediction_forecast(data).await {
+        Ok(forecast) => forecast,
+        Err(response) => return response,
+    };
+    let weather = nearest_cell(&forecast, (target.latitude, target.longitude));
+    let weather_data = match weather.get(&weather_key(date)) {
+        Some(data) => Some(data),
+        None if is_past => None,
         None => {
             return (StatusCode::NOT_FOUND, "Weather data not found".to_owned()).into_response();
         }
     };
-    let precipitation = weather_data.precipitation;
-    let temperature = weather_data.temperature_2m;
-    let wind_speed = weather_data.wind_speed_10m;
 
-    let generated_data: Vec<Option<&MergedData>> = data
+    // The nearest point of each donor gives the prediction, all their neighbours the spread
+    let capacity = target.capacity.max(0) as u32;
+    let scaled = |point: &MergedData| point.rescaled_bikes(capacity);
+    let neighbours: Vec<Vec<u32>> = donors
+        .iter()
+        .filter_map(|donor| {
+            let station_data = data.data.get(&(donor.id as u32))?;
+            let points = match weather_data {
+                Some(weather) if !is_past => nearest_points(
+                    station_data,
+                    &wanted_point(
+                        donor.id as u32,
+                        date,
+                        is_holidays,
+                        weather,
+                        donor.cluster.map(|c| c as u32),
+                    ),
+                ),
+                _ => past_point(station_data, date).into_iter().collect(),
+            };
+            let bikes: Vec<u32> = points.into_iter().filter_map(scaled).collect();
+            (!bikes.is_empty()).then_some(bikes)
+        })
+        .collect();
+
+    if neighbours.is_empty() {
+        return (StatusCode::NOT_FOUND, "No data found".to_owned()).into_response();
+    }
+    let available_bikes = (neighbours.iter().map(|b| b[0] as f32).sum::<f32>()
+        / neighbours.len() as f32)
+        .round() as u32;
+    let all_bikes: Vec<u32> = neighbours.into_iter().flatten().collect();
+    let all_stands: Vec<u32> = all_bikes.iter().map(|b| capacity - b).collect();
+
+    (
+        StatusCode::OK,
+        Json(AvailabilityData {
+            id,
+            available_bikes,
+            free_stands: capacity - available_bikes,
+            low_confidence: true,
+            bikes_quantiles: Quantiles::from_values(&all_bikes),
+            stands_quantiles: Quantiles::from_values(&all_stands),
+        }),
+    )
+        .into_response()
+}
+
+/// Predicts the availability of every station with a history for a given date and time.
+///
+/// # Arguments
+///
+/// * `data` - The application state containing the prediction data and holidays.
+/// * `date` - The date and time of the predictions.
+///
+/// # Returns
+///
+/// * The availability of each station, or the error response if the weather data cannot be
+///   downloaded or is not found. The past dates are looked up in the history, without weather.
+pub(crate) async fn predict_all(
+    data: &AppState,
+    date: DateTime<Tz>,
+) -> Result<HashMap<u32, AvailabilityData>, Response> {
+    if date < start_of_today(data.network.timezone) {
+        return Ok(observed_all(&data.data, date));
+    }
+    let is_holidays = is_holidays(&data.holidays, date);
+
+    let forecast = prediction_forecast(data).await?;
+    let key = weather_key(date);
+    if !forecast.iter().all(|cell| cell.hourly.contains_key(&key)) {
+        return Err((StatusCode::NOT_FOUND, "Weather data not found".to_owned()).into_response());
+    }
+    let capacities = &data.capacities;
+    let locations = &data.locations;
+
+    let generated_data: Vec<Option<AvailabilityData>> = data
         .data
         .par_iter()
         .map(|(_, station_data)| {
-            if params.date
-                < now
-                    .with_hour(0)
-                    .unwrap()
-                    .with_minute(0)
-                    .unwrap()
-                    .with_second(0)
-                    .unwrap()
-            {
-                station_data.par_iter().find_first(|d| {
-                    d.month == params.date.month()
-                        && d.day == params.date.day()
-                        && d.hour == params.date.hour()
-                        && d.minute == params.date.minute()
-                        && d.week_day == params.date.weekday().num_days_from_monday()
-                })
-            } else {
-                let wanted_point = MergedData {
-                    id: station_data.first().unwrap().id,
-                    hour: params.date.time().hour(),
-                    minute: params.date.time().minute(),
-                    day: params.date.day(),
-                    month: params.date.month(),
-                    week_day: params.date.weekday().num_days_from_monday(),
-                    holidays: is_holidays,
-                    free_stands: 0,
-                    available_bikes: 0,
-                    precipitation,
-                    temperature,
-                    wind_speed,
-                };
-
-                let nearest_data = station_data.par_iter().min_by(|a, b| {
-                    distance(a, &wanted_point)
-                        .partial_cmp(&distance(b, &wanted_point))
-                        .unwrap()
-                });
-
-                nearest_data
-            }
+            // The storage readers may produce a station without any data point
+            let first = station_data.first()?;
+            let location = locations.get(&first.id).copied();
+            let weather =
+                nearest_cell(&forecast, location.unwrap_or(data.network.weather.centre()));
+            let wanted_point =
+                wanted_point(first.id, date, is_holidays, &weather[&key], first.cluster);
+
+            AvailabilityData::from_neighbours(
+                first.id,
+                &nearest_points(station_data, &wanted_point),
+                capacities.get(&first.id).copied(),
+            )
         })
         .collect();
 
     let mut response_data: HashMap<u32, AvailabilityData> = HashMap::new();
-    generated_data.iter().for_each(|data| {
-        if let Some(nearest) = data.as_ref() {
-            response_data.insert(
-                nearest.id,
-                AvailabilityData {
-                    id: nearest.id,
-                    available_bikes: nearest.available_bikes,
-                    free_stands: nearest.free_stands,
-                },
-            );
+    generated_data.into_iter().for_each(|data| {
+        if let Some(availability) = data {
+            response_data.insert(availability.id, availability);
         }
     });
 
+    Ok(response_data)
+}
+
+/// Predicts the availability of bikes and free stands at every station for a given date and time.
+///
+/// The fresh precomputed predictions are served if they cover every station with a history,
+/// otherwise the predictions are computed live.
+///
+/// # Arguments
+///
+/// * `data` - The application state containing the prediction data and holidays.
+/// * `params` - The parameters for the predictions, including the date.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the predicted availability of each station in JSON format if successful.
+/// * `StatusCode::BAD_REQUEST` with an error message if the date does not exist in the time zone.
+/// * `StatusCode::NOT_FOUND` with an error message if the weather data or any prediction is not found.
+/// * `StatusCode::INTERNAL_SERVER_ERROR` with an error message if the weather forecast cannot be
+///   downloaded.
+pub async fn predictions(
+    State(data): State<AppState>,
+    Query(params): Query<PredictionsParams>,
+) -> impl IntoResponse {
+    let date = match params.date.in_timezone(data.network.timezone) {
+        Ok(date) => date,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if date >= start_of_today(data.network.timezone) {
+        if let Some(precomputed) = fresh_forecasts(&data, date) {
+            info!("💾 Serving the precomputed predictions");
+            return (StatusCode::OK, Json(precomputed)).into_response();
+        }
+    }
+
+    let response_data = match predict_all(&data, date).await {
+        Ok(response_data) => response_data,
+        Err(response) => return response,
+    };
+
     if response_data.is_empty() {
         return (StatusCode::NOT_FOUND, "No data found".to_owned()).into_response();
     }
@@ -282,6 +696,11 @@ pub async fn predictions(
 
 /// Predicts the availability of bikes and free stands at a station for a given date and time.
 ///
+/// The prediction is modelled as an occupancy ratio, rescaled to the current capacity of the
+/// station. Stations without history, such as new stations, are predicted from similar stations
+/// and the response is marked as low-confidence. The fresh precomputed prediction of the station
+/// is served if there is one, otherwise the prediction is computed live.
+///
 /// # Arguments
 ///
 /// * `data` - The application state containing the prediction data and holidays.
@@ -290,41 +709,35 @@ pub async fn predictions(
 /// # Returns
 ///
 /// * `StatusCode::OK` with the predicted availability data in JSON format if successful.
+/// * `StatusCode::BAD_REQUEST` with an error message if the date does not exist in the time zone.
 /// * `StatusCode::NOT_FOUND` with an error message if the station or weather data is not found.
+/// * `StatusCode::INTERNAL_SERVER_ERROR` with an error message if the weather forecast cannot be
+///   downloaded.
 pub async fn predict(
     State(data): State<AppState>,
     Query(params): Query<PredictParams>,
 ) -> impl IntoResponse {
+    let date = match params.date.in_timezone(data.network.timezone) {
+        Ok(date) => date,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if date >= start_of_today(data.network.timezone) {
+        if let Some(availability) = fresh_forecast(&data, params.id, date) {
+            info!("💾 Serving the precomputed prediction of {}", params.id);
+            return (StatusCode::OK, Json(availability)).into_response();
+        }
+    }
+
     info!("🔍 Filter on the good station id");
     let station_data = match data.data.get(&params.id) {
         Some(data) => data,
-        None => return (StatusCode::NOT_FOUND, "Station not found".to_owned()).into_response(),
+        None => return cold_start_predict(&data, params.id, date).await,
     };
 
-    let is_holidays = data
-        .holidays
-        .par_iter()
-        .any(|holiday| params.date.date() >= holiday.start && params.date.date() <= holiday.end);
-
-    let forecast = download_weather_forecast().await.unwrap();
-    let now = chrono::Utc::now()
-        .naive_utc()
-        .with_hour(0)
-        .unwrap()
-        .with_minute(0)
-        .unwrap()
-        .with_second(0)
-        .unwrap();
-    if params.date < now {
-        match station_data.par_iter().find_first(|d| {
-            d.month == params.date.month()
-                && d.day == params.date.day()
-                && d.minute == params.date.minute()
-                && d.hour == params.date.hour()
-                && d.week_day == params.date.weekday().num_days_from_monday()
-        }) {
-            Some(data) => {
-                return (StatusCode::OK, Json(data)).into_response();
+    if date < start_of_today(data.network.timezone) {
+        match past_point(station_data, date) {
+            Some(point) => {
+                return (StatusCode::OK, Json(AvailabilityData::observed(point))).into_response();
             }
             None => {
                 return (StatusCode::NOT_FOUND, "Data not found".to_owned()).into_response();
@@ -332,52 +745,624 @@ pub async fn predict(
         }
     }
 
-    let weather_data = match forecast.get(&params.date.with_minute(0).unwrap().and_utc()) {
+    let is_holidays = is_holidays(&data.holidays, date);
+
+    let forecast = match prediction_forecast(&data).await {
+        Ok(forecast) => forecast,
+        Err(response) => return response,
+    };
+
+    let location = data
+        .locations
+        .get(&params.id)
+        .copied()
+        .unwrap_or(data.network.weather.centre());
+    let weather_data = match nearest_cell(&forecast, location).get(&weather_key(date)) {
         Some(data) => data,
         None => {
             return (StatusCode::NOT_FOUND, "Weather data not found".to_owned()).into_response();
         }
     };
-    let precipitation = weather_data.precipitation;
-    let temperature = weather_data.temperature_2m;
-    let wind_speed = weather_data.wind_speed_10m;
-
-    let mut wanted_point = MergedData {
-        id: params.id,
-        hour: params.date.time().hour(),
-        minute: params.date.time().minute(),
-        day: params.date.day(),
-        month: params.date.month(),
-        week_day: params.date.weekday().num_days_from_monday(),
-        holidays: is_holidays,
-        free_stands: 0,
-        available_bikes: 0,
-        precipitation,
-        temperature,
-        wind_speed,
-    };
 
-    let nearest_data = station_data.par_iter().min_by(|a, b| {
-        distance(a, &wanted_point)
-            .partial_cmp(&distance(b, &wanted_point))
-            .unwrap()
+    let wanted_point = wanted_point(
+        params.id,
+        date,
+        is_holidays,
+        weather_data,
+        station_data.first().and_then(|d| d.cluster),
+    );
+
+    let capacity = data.capacities.get(&params.id).copied();
+    let nearest_data = nearest_points(station_data, &wanted_point);
+    match AvailabilityData::from_neighbours(params.id, &nearest_data, capacity) {
+        Some(availability) => {
+            info!("🎯 Nearest point found: {:?}", nearest_data[0]);
+            (StatusCode::OK, Json(availability)).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "No data found".to_owned()).into_response(),
+    }
+}
+
+/// Parameters for the `get_anomalies` function.
+///
+/// # Fields
+///
+/// * `id` - The ID of the station to filter on, if any.
+/// * `kind` - The kind of anomaly to filter on, if any.
+#[derive(Deserialize)]
+pub struct AnomaliesParams {
+    id: Option<u32>,
+    kind: Option<AnomalyKind>,
+}
+
+/// Lists the periods during which stations behaved abnormally.
+///
+/// The anomalies detected in the history by `--detect-anomalies` are completed with the ongoing
+/// ones, detected on the recent observations of the stations.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the detected anomalies.
+/// * `params` - The optional station and kind filters.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the matching anomalies in JSON format.
+pub async fn get_anomalies(
+    State(state): State<AppState>,
+    Query(params): Query<AnomaliesParams>,
+) -> impl IntoResponse {
+    let live = {
+        let mut connection = state.connection.lock().unwrap();
+        detect_live_anomalies(&mut connection, &state.network.id, Utc::now())
+    }
+    .unwrap_or_else(|e| {
+        warn!("❌ Failed to detect the live anomalies: {}", e);
+        Vec::new()
+    });
+    // The live anomalies already in the history are only reported once
+    let live = live.iter().filter(|l| {
+        !state
+            .anomalies
+            .iter()
+            .any(|a| a.id == l.id && a.kind == l.kind && a.contains(l.start))
     });
 
-    if let Some(nearest) = nearest_data {
-        wanted_point.free_stands = nearest.free_stands;
-        wanted_point.available_bikes = nearest.available_bikes;
-        info!("🎯 Nearest point found: {:?}", nearest);
-    } else {
-        return (StatusCode::NOT_FOUND, "No data found".to_owned()).into_response();
+    let anomalies: Vec<&Anomaly> = state
+        .anomalies
+        .iter()
+        .chain(live)
+        .filter(|a| params.id.is_none_or(|id| a.id == id))
+        .filter(|a| params.kind.is_none_or(|kind| a.kind == kind))
+        .collect();
+
+    (StatusCode::OK, Json(anomalies)).into_response()
+}
+
+/// Parameters for the `get_station_history` function.
+///
+/// # Fields
+///
+/// * `from` - The start of the period, included, as RFC 3339 or local time.
+/// * `to` - The end of the period, included, as RFC 3339 or local time.
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    from: LocalDateTime,
+    to: LocalDateTime,
+}
+
+/// Retrieves the actual observations of a station over a period.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the history.
+/// * `id` - The ID of the station.
+/// * `params` - The period to retrieve.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the observations in JSON format if successful.
+/// * `StatusCode::NOT_FOUND` with an error message if the station has no history.
+pub async fn get_station_history(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    Query(params): Query<HistoryParams>,
+) -> impl IntoResponse {
+    let timezone = state.network.timezone;
+    let (from, to) = match (
+        params.from.in_timezone(timezone),
+        params.to.in_timezone(timezone),
+    ) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    match state.data.get(&id) {
+        Some(station_data) => (
+            StatusCode::OK,
+            Json(history_range(
+                station_data,
+                from.with_timezone(&Utc),
+                to.with_timezone(&Utc),
+            )),
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, "Station not found".to_owned()).into_response(),
+    }
+}
+
+/// Default duration of the rebalancing window, in hours.
+const REBALANCING_WINDOW_HOURS: u32 = 2;
+/// Time between two predictions of the rebalancing window, in minutes.
+const REBALANCING_STEP_MINUTES: i64 = 30;
+
+/// Parameters for the `rebalancing` function.
+///
+/// # Fields
+///
+/// * `date` - The start of the rebalancing window, as RFC 3339 or local time.
+/// * `window` - The duration of the window in hours, `REBALANCING_WINDOW_HOURS` by default and at
+///   most the forecast horizon.
+#[derive(Deserialize)]
+pub struct RebalancingParams {
+    date: LocalDateTime,
+    window: Option<u32>,
+}
+
+/// Rebalancing plan for the operators.
+///
+/// # Fields
+///
+/// * `date` - The start of the rebalancing window.
+/// * `window` - The duration of the window in hours.
+/// * `empty` - The stations expected to run out of bikes during the window.
+/// * `full` - The stations expected to run out of free stands during the window.
+/// * `transfers` - The proposed bike transfers.
+#[derive(Debug, Serialize, Clone)]
+pub struct RebalancingPlan {
+    date: DateTime<Tz>,
+    window: u32,
+    empty: Vec<StationOutlook>,
+    full: Vec<StationOutlook>,
+    transfers: Vec<Transfer>,
+}
+
+/// Proposes bike transfers between stations to prevent the predicted empty and full stations
+/// during a window.
+///
+/// # Arguments
+///
+/// * `data` - The application state containing the prediction data and the database connection.
+/// * `params` - The start and duration of the window.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the rebalancing plan in JSON format if successful.
+/// * `StatusCode::BAD_REQUEST` with an error message if the date does not exist in the time zone.
+/// * `StatusCode::BAD_REQUEST` with an error message if the window exceeds the forecast horizon.
+/// * `StatusCode::NOT_FOUND` with an error message if the weather data is not found.
+/// * `StatusCode::INTERNAL_SERVER_ERROR` with an error message if the weather forecast cannot be
+///   downloaded or the stations cannot be queried.
+pub async fn rebalancing(
+    State(data): State<AppState>,
+    Query(params): Query<RebalancingParams>,
+) -> impl IntoResponse {
+    let date = match params.date.in_timezone(data.network.timezone) {
+        Ok(date) => date,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let window = params.window.unwrap_or(REBALANCING_WINDOW_HOURS);
+    let horizon = config().forecast.horizon_hours;
+    if window > horizon {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "The window cannot exceed the forecast horizon of {} hours",
+                horizon
+            ),
+        )
+            .into_response();
+    }
+
+    info!("🔮 Predicting the network over {} hours..", window);
+    let mut lowest: HashMap<u32, (u32, u32)> = HashMap::new();
+    for step in 0..=(window as i64 * 60 / REBALANCING_STEP_MINUTES) {
+        let slot = date + Duration::minutes(step * REBALANCING_STEP_MINUTES);
+        let predictions = match predict_all(&data, slot).await {
+            Ok(predictions) => predictions,
+            Err(response) => return response,
+        };
+        for (id, availability) in predictions {
+            let entry = lowest
+                .entry(id)
+                .or_insert((availability.available_bikes, availability.free_stands));
+            entry.0 = entry.0.min(availability.available_bikes);
+            entry.1 = entry.1.min(availability.free_stands);
+        }
     }
 
+    let stations = {
+        let mut connection = data.connection.lock().unwrap();
+
+        use schema::station::dsl::station;
+        match station
+            .select(DetailedStation::as_select())
+            .filter(schema::station::network.eq(&data.network.id))
+            .load(&mut *connection)
+        {
+            Ok(stations) => stations,
+            Err(_) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Error querying database".to_owned(),
+                )
+                    .into_response()
+            }
+        }
+    };
+
+    let outlooks: Vec<StationOutlook> = stations
+        .iter()
+        .filter_map(|s| {
+            let (min_bikes, min_stands) = lowest.get(&(s.id as u32))?;
+            Some(StationOutlook {
+                id: s.id as u32,
+                latitude: s.latitude,
+                longitude: s.longitude,
+                capacity: s.capacity.max(0) as u32,
+                min_bikes: *min_bikes,
+                min_stands: *min_stands,
+            })
+        })
+        .collect();
+
+    info!("🚚 Planning transfers..");
+    let transfers = plan_transfers(&outlooks);
+
     (
         StatusCode::OK,
-        Json(AvailabilityData {
-            id: wanted_point.id,
-            available_bikes: wanted_point.available_bikes,
-            free_stands: wanted_point.free_stands,
+        Json(RebalancingPlan {
+            date,
+            window,
+            empty: outlooks
+                .iter()
+                .filter(|s| s.bikes_shortfall() > 0)
+                .cloned()
+                .collect(),
+            full: outlooks
+                .iter()
+                .filter(|s| s.stands_shortfall() > 0)
+                .cloned()
+                .collect(),
+            transfers,
         }),
     )
         .into_response()
 }
+
+/// Summary of an area (commune) of the network.
+///
+/// # Fields
+///
+/// * `area` - The name of the area.
+/// * `stations` - The number of stations in the area.
+/// * `capacity` - The total capacity of the stations in the area.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AreaSummary {
+    area: String,
+    stations: i64,
+    capacity: i64,
+}
+
+/// Lists the areas (communes) of the network with their station count and total capacity.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the database connection.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the areas in JSON format if successful.
+/// * `StatusCode::INTERNAL_SERVER_ERROR` with an error message if the query fails.
+pub async fn get_areas(State(state): State<AppState>) -> impl IntoResponse {
+    let mut connection = state.connection.lock().unwrap();
+
+    use diesel::dsl::count_star;
+    use schema::station::dsl::{area, capacity, network, station};
+    match station
+        .filter(network.eq(&state.network.id))
+        .group_by(area)
+        .select((area, count_star(), diesel::dsl::sum(capacity)))
+        .order(area)
+        .load::<(String, i64, Option<i64>)>(&mut *connection)
+    {
+        Ok(areas) => (
+            StatusCode::OK,
+            Json(
+                areas
+                    .into_iter()
+                    .map(|(name, stations, total)| AreaSummary {
+                        area: name,
+                        stations,
+                        capacity: total.unwrap_or(0),
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error querying database".to_owned(),
+        )
+            .into_response(),
+    }
+}
+
+/// Aggregated forecast of an area.
+///
+/// The totals only cover the predicted stations, so that `available_bikes + free_stands` is
+/// always equal to `capacity`.
+///
+/// # Fields
+///
+/// * `area` - The name of the area.
+/// * `date` - The date and time of the forecast.
+/// * `available_bikes` - The total number of bikes predicted in the area.
+/// * `free_stands` - The total number of free stands predicted in the area.
+/// * `capacity` - The total capacity of the predicted stations.
+/// * `stations` - The predictions of each station of the area.
+/// * `missing` - The IDs of the stations of the area which could not be predicted.
+#[derive(Debug, Serialize, Clone)]
+pub struct AreaForecast {
+    area: String,
+    date: DateTime<Tz>,
+    available_bikes: u32,
+    free_stands: u32,
+    capacity: u32,
+    stations: Vec<AvailabilityData>,
+    missing: Vec<i32>,
+}
+
+/// Forecasts the total availability of bikes and free stands across the stations of an area.
+///
+/// # Arguments
+///
+/// * `data` - The application state containing the prediction data and the database connection.
+/// * `name` - The name of the area.
+/// * `params` - The parameters for the forecast, including the date.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the area forecast in JSON format if successful.
+/// * `StatusCode::BAD_REQUEST` with an error message if the date does not exist in the time zone.
+/// * `StatusCode::NOT_FOUND` with an error message if the area or the weather data is not found.
+/// * `StatusCode::INTERNAL_SERVER_ERROR` with an error message if the weather forecast cannot be
+///   downloaded or the stations cannot be queried.
+pub async fn get_area_forecast(
+    State(data): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<PredictionsParams>,
+) -> impl IntoResponse {
+    let date = match params.date.in_timezone(data.network.timezone) {
+        Ok(date) => date,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let area_stations: Vec<i32> = {
+        let mut connection = data.connection.lock().unwrap();
+
+        use schema::station::dsl::{area, id, network, station};
+        match station
+            .select(id)
+            .filter(network.eq(&data.network.id))
+            .filter(area.eq(&name))
+            .order(id)
+            .load(&mut *connection)
+        {
+            Ok(area_stations) => area_stations,
+            Err(_) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Error querying database".to_owned(),
+                )
+                    .into_response()
+            }
+        }
+    };
+    if area_stations.is_empty() {
+        return (StatusCode::NOT_FOUND, "Area not found".to_owned()).into_response();
+    }
+
+    let mut predictions = match predict_all(&data, date).await {
+        Ok(predictions) => predictions,
+        Err(response) => return response,
+    };
+
+    let mut stations = Vec::new();
+    let mut missing = Vec::new();
+    for station_id in area_stations {
+        match predictions.remove(&(station_id as u32)) {
+            Some(availability) => stations.push(availability),
+            None => missing.push(station_id),
+        }
+    }
+    let available_bikes = stations.iter().map(|s| s.available_bikes).sum::<u32>();
+    let free_stands = stations.iter().map(|s| s.free_stands).sum::<u32>();
+
+    (
+        StatusCode::OK,
+        Json(AreaForecast {
+            area: name,
+            date,
+            available_bikes,
+            free_stands,
+            capacity: available_bikes + free_stands,
+            stations,
+            missing,
+        }),
+    )
+        .into_response()
+}
+
+/// Publishes the `gbfs.json` discovery file of the GBFS feeds of a network.
+///
+/// The URLs of the feeds are the configured public URL of the API followed by the path of the
+/// request, so that they point to the network the discovery file was requested from.
+///
+/// # Arguments
+///
+/// * `uri` - The URI of the request, before the nesting of the network routes.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the discovery file in JSON format.
+pub async fn get_gbfs(OriginalUri(uri): OriginalUri) -> impl IntoResponse {
+    let path = uri.path().trim_end_matches("gbfs.json");
+    let base_url = format!("{}{}", config().gbfs.base_url.trim_end_matches('/'), path);
+    (StatusCode::OK, Json(published_discovery(&base_url))).into_response()
+}
+
+/// Publishes the `system_information` GBFS feed of a network.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the network.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the feed in JSON format.
+pub async fn get_gbfs_system_information(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        Json(published_system_information(&state.network)),
+    )
+        .into_response()
+}
+
+/// Publishes the `station_information` GBFS feed of a network, from the `station` table.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the database connection.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the feed in JSON format if successful.
+/// * `StatusCode::INTERNAL_SERVER_ERROR` with an error message if the query fails.
+pub async fn get_gbfs_station_information(State(state): State<AppState>) -> impl IntoResponse {
+    let mut connection = state.connection.lock().unwrap();
+
+    match schema::station::table
+        .select(DetailedStation::as_select())
+        .filter(schema::station::network.eq(&state.network.id))
+        .order(schema::station::id)
+        .load(&mut *connection)
+    {
+        Ok(stations) => (
+            StatusCode::OK,
+            Json(published_station_information(&stations)),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error querying database".to_owned(),
+        )
+            .into_response(),
+    }
+}
+
+/// Age of the oldest observation published in the `station_status` GBFS feed, in hours.
+const GBFS_STATUS_WINDOW_HOURS: i64 = 24;
+
+/// Publishes the `station_status` GBFS feed of a network, from the latest observation of each
+/// station of the `station` table over the last `GBFS_STATUS_WINDOW_HOURS`.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the database connection.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` with the feed in JSON format if successful.
+/// * `StatusCode::INTERNAL_SERVER_ERROR` with an error message if the query fails.
+pub async fn get_gbfs_station_status(State(state): State<AppState>) -> impl IntoResponse {
+    let mut connection = state.connection.lock().unwrap();
+
+    let stations = schema::station::table
+        .select(schema::station::id)
+        .filter(schema::station::network.eq(&state.network.id));
+    match schema::observation::table
+        .select(Observation::as_select())
+        .filter(schema::observation::network.eq(&state.network.id))
+        .filter(schema::observation::station_id.eq_any(stations))
+        .filter(
+            schema::observation::timestamp
+                .ge(Utc::now() - Duration::hours(GBFS_STATUS_WINDOW_HOURS)),
+        )
+        .distinct_on(schema::observation::station_id)
+        .order((
+            schema::observation::station_id,
+            schema::observation::timestamp.desc(),
+        ))
+        .load(&mut *connection)
+    {
+        Ok(observations) => (
+            StatusCode::OK,
+            Json(published_station_status(&observations)),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error querying database".to_owned(),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Paris;
+
+    fn point(id: u32, timestamp: &str, available_bikes: u32) -> MergedData {
+        MergedData {
+            id,
+            hour: 0,
+            minute: 0,
+            day: 1,
+            month: 1,
+            week_day: 0,
+            holidays: false,
+            free_stands: 10 - available_bikes,
+            available_bikes,
+            precipitation: 0.0,
+            temperature: 0.0,
+            wind_speed: 0.0,
+            cluster: None,
+            capacity: 10,
+            timestamp: timestamp.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn looks_up_the_past_dates_in_the_history() {
+        let data = HashMap::from([
+            (
+                1,
+                vec![
+                    point(1, "2025-01-06T08:00:00Z", 3),
+                    point(1, "2025-01-06T08:15:00Z", 4),
+                ],
+            ),
+            (2, vec![point(2, "2025-01-06T08:00:00Z", 7)]),
+            (3, Vec::new()),
+        ]);
+
+        // 09:15 in Paris is 08:15 UTC, whatever the weather forecast covers
+        let date = Paris.with_ymd_and_hms(2025, 1, 6, 9, 15, 0).unwrap();
+        let observed = observed_all(&data, date);
+        assert_eq!(observed.len(), 1);
+        assert_eq!(observed[&1].available_bikes, 4);
+        assert_eq!(observed[&1].free_stands, 6);
+        assert!(!observed[&1].low_confidence);
+    }
+}
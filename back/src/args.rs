@@ -1,3 +1,6 @@
+use crate::config::CONFIG_FILE;
+use crate::dedup::DedupPolicy;
+use crate::storage::StorageFormat;
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -11,9 +14,28 @@ use clap::Parser;
 /// * `max_velov_features` - The maximum number of Velov features to process.
 /// * `velov_start` - The starting index for Velov data processing.
 /// * `download_weather_data` - A flag to indicate whether to download weather data.
+/// * `import_school_holidays` - A flag to indicate whether to import the school holidays from the official school calendar.
 /// * `filter_velov_data` - A flag to indicate whether to filter Velov data.
+/// * `resample_step` - The step of the grid the Velov data is resampled to, in minutes, at least 1.
+/// * `dedup_policy` - The policy resolving the Velov snapshots of a station sharing a horodate.
 /// * `merge_datas` - A flag to indicate whether to merge data sources.
 /// * `mock` - A flag to indicate whether to use mock data.
+/// * `benchmark` - A flag to indicate whether to benchmark the model.
+/// * `cluster` - A flag to indicate whether to cluster the stations by usage profile.
+/// * `clusters` - The number of clusters to build.
+/// * `detect_anomalies` - A flag to indicate whether to detect the anomalies of the Velov data.
+/// * `pipeline` - A flag to indicate whether to run the stages of the data pipeline which are not up to date.
+/// * `refresh` - A flag to indicate whether the pipeline should download the remote data again.
+/// * `config` - The path of the configuration file.
+/// * `http_timeout` - The timeout of the requests to the upstream sources, in seconds, overriding the configuration.
+/// * `http_retries` - The number of times a failed request to an upstream source is retried, overriding the configuration.
+/// * `http_rate_limit` - The maximum number of requests per second to the upstream sources, overriding the configuration.
+/// * `convert_merged_data` - The storage format to convert the merged data to.
+/// * `import_history` - A flag to indicate whether to import the Velov history and the merged data into the database.
+/// * `precompute_forecasts` - A flag to indicate whether to precompute the predictions of every station into the database.
+/// * `quality_report` - A flag to indicate whether to write a quality report of the data.
+/// * `gap_threshold` - The minimum duration of the gaps reported by the quality report, in minutes.
+/// * `network` - The network the commands work on, instead of the one of the `network` section of the configuration.
 pub struct Args {
     #[arg(short, long, default_value_t = false)]
     pub populate: bool,
@@ -25,9 +47,15 @@ pub struct Args {
     pub velov_start: u32,
     #[arg(long, default_value_t = false)]
     pub download_weather_data: bool,
+    #[arg(long, default_value_t = false)]
+    pub import_school_holidays: bool,
 
     #[arg(short, long, default_value_t = false)]
     pub filter_velov_data: bool,
+    #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u32).range(1..))]
+    pub resample_step: u32,
+    #[arg(long, value_enum, default_value_t = DedupPolicy::LatestDownload)]
+    pub dedup_policy: DedupPolicy,
     #[arg(short = 'M', long, default_value_t = false)]
     pub merge_datas: bool,
 
@@ -35,4 +63,40 @@ pub struct Args {
     pub mock: bool,
     #[arg(short, long, default_value_t = false)]
     pub benchmark: bool,
+
+    #[arg(short, long, default_value_t = false)]
+    pub cluster: bool,
+    #[arg(long, default_value_t = 3)]
+    pub clusters: u32,
+
+    #[arg(short = 'a', long, default_value_t = false)]
+    pub detect_anomalies: bool,
+
+    #[arg(short = 'P', long, default_value_t = false)]
+    pub pipeline: bool,
+    #[arg(long, default_value_t = false)]
+    pub refresh: bool,
+
+    #[arg(long, default_value_t = String::from(CONFIG_FILE))]
+    pub config: String,
+    #[arg(long)]
+    pub http_timeout: Option<u64>,
+    #[arg(long)]
+    pub http_retries: Option<u32>,
+    #[arg(long)]
+    pub http_rate_limit: Option<f64>,
+    #[arg(long, value_enum)]
+    pub convert_merged_data: Option<StorageFormat>,
+    #[arg(long, default_value_t = false)]
+    pub import_history: bool,
+    #[arg(long, default_value_t = false)]
+    pub precompute_forecasts: bool,
+
+    #[arg(long, default_value_t = false)]
+    pub quality_report: bool,
+    #[arg(long, default_value_t = 60)]
+    pub gap_threshold: u32,
+
+    #[arg(long)]
+    pub network: Option<String>,
 }
@@ -0,0 +1,228 @@
+use crate::config::Network;
+use crate::learning::{read_merged_data_from_file, write_merged_data_to_file, MergedData};
+use crate::{establish_connection, schema};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use log::{info, warn};
+use rayon::iter::{IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator};
+use std::collections::HashMap;
+
+/// Number of slots in a weekly profile: one per hour of each day of the week.
+const PROFILE_LEN: usize = 7 * 24;
+/// Maximum number of k-means iterations before giving up on convergence.
+const MAX_ITERATIONS: usize = 100;
+
+/// Clusters the stations by their weekly occupancy profile.
+///
+/// The profiles are computed from the merged data, clustered with k-means, then the label of
+/// each station is stored in the `station` table and in the merged data files so it can be
+/// used as a model feature. This must therefore be run after `merge_data`.
+///
+/// # Arguments
+///
+/// * `network` - The network, whose data directory holds the merged data.
+/// * `clusters` - The number of clusters to build.
+///
+/// # Examples
+///
+/// ```rust
+/// cluster(&network, 3);
+/// ```
+pub fn cluster(network: &Network, clusters: u32) {
+    let merged_dir = network.data_dir.join("merged_data");
+    let mut data = read_merged_data_from_file(&merged_dir);
+
+    info!("📈 Computing weekly occupancy profiles..");
+    let mut ids: Vec<u32> = data.keys().copied().collect();
+    ids.sort();
+    let profiles: Vec<Vec<f32>> = ids
+        .par_iter()
+        .map(|id| weekly_profile(data.get(id).unwrap()))
+        .collect();
+    info!("✅ {} profiles computed!", profiles.len());
+
+    info!("🧮 Clustering stations into {} clusters..", clusters);
+    let labels = kmeans(&profiles, clusters as usize);
+    let labels: HashMap<u32, u32> = ids
+        .iter()
+        .zip(labels.iter())
+        .map(|(id, label)| (*id, *label as u32))
+        .collect();
+    for label in 0..clusters {
+        info!(
+            "🏷️ Cluster {} : {} stations",
+            label,
+            labels.values().filter(|l| **l == label).count()
+        );
+    }
+
+    info!("💾 Storing the clusters in the database..");
+    let mut connection = establish_connection();
+    let network_id = &network.id;
+    for (id, label) in labels.iter() {
+        use schema::station::dsl::{cluster, network, station};
+        match diesel::update(
+            station
+                .filter(network.eq(network_id))
+                .filter(schema::station::id.eq(*id as i32)),
+        )
+        .set(cluster.eq(Some(*label as i32)))
+        .execute(&mut connection)
+        {
+            Ok(0) => warn!("❌ Station {} not found in the database", id),
+            Ok(_) => {}
+            Err(e) => warn!("❌ Failed to store the cluster of station {}: {}", id, e),
+        }
+    }
+
+    info!("💾 Storing the clusters in the merged data..");
+    data.par_iter_mut().for_each(|(id, station_data)| {
+        let label = labels.get(id).copied();
+        station_data.iter_mut().for_each(|d| d.cluster = label);
+    });
+    write_merged_data_to_file(&merged_dir, data);
+}
+
+/// Computes the normalised weekly occupancy profile of a station.
+///
+/// Each slot holds the mean occupancy of the station for one hour of one day of the week. The
+/// profile is then standardised so that stations are compared on the shape of their usage rather
+/// than on their fill level.
+///
+/// # Arguments
+///
+/// * `data` - The merged data of the station.
+///
+/// # Returns
+///
+/// * A vector of `PROFILE_LEN` values.
+fn weekly_profile(data: &[MergedData]) -> Vec<f32> {
+    let mut sums = vec![0.0; PROFILE_LEN];
+    let mut counts = vec![0u32; PROFILE_LEN];
+
+    for d in data {
+        let Some(occupancy) = d.occupancy() else {
+            continue;
+        };
+        let slot = (d.week_day % 7) as usize * 24 + d.hour as usize;
+        sums[slot] += occupancy;
+        counts[slot] += 1;
+    }
+
+    let filled: Vec<f32> = sums
+        .iter()
+        .zip(counts.iter())
+        .filter(|(_, count)| **count > 0)
+        .map(|(sum, count)| sum / *count as f32)
+        .collect();
+    let mean = if filled.is_empty() {
+        0.0
+    } else {
+        filled.iter().sum::<f32>() / filled.len() as f32
+    };
+
+    // Slots without any observation are considered average
+    let profile: Vec<f32> = sums
+        .iter()
+        .zip(counts.iter())
+        .map(|(sum, count)| {
+            if *count > 0 {
+                sum / *count as f32
+            } else {
+                mean
+            }
+        })
+        .collect();
+
+    let std = (profile.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / PROFILE_LEN as f32).sqrt();
+    profile
+        .iter()
+        .map(|v| if std > 0.0 { (v - mean) / std } else { 0.0 })
+        .collect()
+}
+
+/// Squared euclidean distance between two profiles.
+fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y).powi(2)).sum()
+}
+
+/// Clusters the profiles with the k-means algorithm.
+///
+/// The centroids are initialised deterministically with the farthest-first traversal, starting
+/// from the first profile, so that running the command twice gives the same labels.
+///
+/// # Arguments
+///
+/// * `profiles` - The profiles to cluster.
+/// * `k` - The number of clusters.
+///
+/// # Returns
+///
+/// * The cluster label of each profile, in the same order as `profiles`.
+fn kmeans(profiles: &[Vec<f32>], k: usize) -> Vec<usize> {
+    let k = k.min(profiles.len());
+    if k == 0 {
+        return vec![0; profiles.len()];
+    }
+
+    let mut centroids: Vec<Vec<f32>> = vec![profiles[0].clone()];
+    while centroids.len() < k {
+        let farthest = profiles
+            .iter()
+            .max_by(|a, b| {
+                let da = centroids
+                    .iter()
+                    .map(|c| squared_distance(a, c))
+                    .fold(f32::MAX, f32::min);
+                let db = centroids
+                    .iter()
+                    .map(|c| squared_distance(b, c))
+                    .fold(f32::MAX, f32::min);
+                da.partial_cmp(&db).unwrap()
+            })
+            .unwrap();
+        centroids.push(farthest.clone());
+    }
+
+    let mut labels = vec![usize::MAX; profiles.len()];
+    for iteration in 0..MAX_ITERATIONS {
+        let new_labels: Vec<usize> = profiles
+            .par_iter()
+            .map(|profile| {
+                centroids
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| {
+                        squared_distance(profile, a)
+                            .partial_cmp(&squared_distance(profile, b))
+                            .unwrap()
+                    })
+                    .map(|(label, _)| label)
+                    .unwrap()
+            })
+            .collect();
+
+        if new_labels == labels {
+            info!("✅ K-means converged after {} iterations", iteration);
+            break;
+        }
+        labels = new_labels;
+
+        for (label, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<&Vec<f32>> = profiles
+                .iter()
+                .zip(labels.iter())
+                .filter(|(_, l)| **l == label)
+                .map(|(p, _)| p)
+                .collect();
+            // An empty cluster keeps its previous centroid
+            if members.is_empty() {
+                continue;
+            }
+            *centroid = (0..PROFILE_LEN)
+                .map(|i| members.iter().map(|p| p[i]).sum::<f32>() / members.len() as f32)
+                .collect();
+        }
+    }
+
+    labels
+}
@@ -0,0 +1,794 @@
+use crate::http::{HttpConfig, HttpMode};
+use crate::storage::{DataSource, StorageFormat};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Default path of the configuration file.
+pub const CONFIG_FILE: &str = "velovite.toml";
+/// Prefix of the environment variables overriding the configuration.
+const ENV_PREFIX: &str = "VELOVITE";
+
+/// The configuration, initialised by `init_config`.
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Configuration of the application, read from `velovite.toml`.
+///
+/// Every value can be overridden by an environment variable named after its section and key,
+/// for instance `VELOVITE_WEATHER_END_DATE=2025-06-30`. Lists are given as comma-separated
+/// values.
+///
+/// # Fields
+///
+/// * `weather` - The weather source.
+/// * `velov` - The Velov sources.
+/// * `http` - The settings of the HTTP client.
+/// * `storage` - The storage of the merged data.
+/// * `forecast` - The precomputed predictions.
+/// * `holidays` - The school holidays source.
+/// * `gbfs` - The GBFS feeds published by the API.
+/// * `network` - The network of the weather, Velov and holidays sources, the one the commands
+///   work on.
+/// * `networks` - The other networks served by the API, by ID.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub weather: WeatherSource,
+    pub velov: VelovSource,
+    pub http: HttpSettings,
+    pub storage: StorageSettings,
+    pub forecast: ForecastSettings,
+    pub holidays: HolidaysSource,
+    pub gbfs: GbfsSettings,
+    pub network: NetworkInfo,
+    pub networks: BTreeMap<String, NetworkSettings>,
+}
+
+/// Weather source, an Open-Meteo compatible API.
+///
+/// # Fields
+///
+/// * `history_url` - The base URL of the weather history.
+/// * `forecast_url` - The base URL of the weather forecast.
+/// * `mode` - Whether the weather is fetched at a single point or on a grid.
+/// * `latitude` - The latitude of the weather point, the city centre in grid mode.
+/// * `longitude` - The longitude of the weather point, the city centre in grid mode.
+/// * `grid` - The grid of weather points, used in grid mode.
+/// * `start_date` - The first day of the weather history.
+/// * `end_date` - The last day of the weather history.
+/// * `history_variables` - The hourly variables of the weather history.
+/// * `forecast_variables` - The hourly variables of the weather forecast.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct WeatherSource {
+    pub history_url: String,
+    pub forecast_url: String,
+    pub mode: WeatherMode,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub history_variables: Vec<String>,
+    pub forecast_variables: Vec<String>,
+    pub grid: WeatherGrid,
+}
+
+/// Where the weather is fetched.
+///
+/// # Variants
+///
+/// * `Point` - At a single point, used for every station.
+/// * `Grid` - At the centre of each cell of a grid, each station using its nearest cell.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WeatherMode {
+    Point,
+    Grid,
+}
+
+/// Grid of weather points covering the stations.
+///
+/// # Fields
+///
+/// * `south` - The southern latitude of the grid.
+/// * `north` - The northern latitude of the grid.
+/// * `west` - The western longitude of the grid.
+/// * `east` - The eastern longitude of the grid.
+/// * `rows` - The number of cells from south to north.
+/// * `columns` - The number of cells from west to east.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct WeatherGrid {
+    pub south: f64,
+    pub north: f64,
+    pub west: f64,
+    pub east: f64,
+    pub rows: u32,
+    pub columns: u32,
+}
+
+impl Default for WeatherGrid {
+    fn default() -> Self {
+        WeatherGrid {
+            south: 45.68,
+            north: 45.82,
+            west: 4.77,
+            east: 4.98,
+            rows: 3,
+            columns: 3,
+        }
+    }
+}
+
+impl WeatherGrid {
+    /// Centres of the cells of the grid, row by row from the south-west.
+    pub fn points(&self) -> Vec<(f64, f64)> {
+        let height = (self.north - self.south) / self.rows.max(1) as f64;
+        let width = (self.east - self.west) / self.columns.max(1) as f64;
+        (0..self.rows.max(1))
+            .flat_map(|row| {
+                (0..self.columns.max(1)).map(move |column| {
+                    (
+                        self.south + (row as f64 + 0.5) * height,
+                        self.west + (column as f64 + 0.5) * width,
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+impl Default for WeatherSource {
+    fn default() -> Self {
+        WeatherSource {
+            history_url: "https://historical-forecast-api.open-meteo.com/v1/forecast".to_string(),
+            forecast_url: "https://api.open-meteo.com/v1/forecast".to_string(),
+            mode: WeatherMode::Grid,
+            latitude: 45.7485,
+            longitude: 4.8467,
+            start_date: NaiveDate::from_ymd_opt(2022, 1, 1).unwrap(),
+            end_date: NaiveDate::from_ymd_opt(2025, 1, 23).unwrap(),
+            history_variables: ["temperature_2m", "precipitation", "wind_speed_10m"]
+                .map(String::from)
+                .to_vec(),
+            forecast_variables: [
+                "temperature_2m",
+                "precipitation_probability",
+                "precipitation",
+                "weather_code",
+                "wind_speed_10m",
+            ]
+            .map(String::from)
+            .to_vec(),
+            grid: WeatherGrid::default(),
+        }
+    }
+}
+
+impl WeatherSource {
+    /// Points the weather is fetched at.
+    pub fn points(&self) -> Vec<(f64, f64)> {
+        match self.mode {
+            WeatherMode::Point => vec![self.centre()],
+            WeatherMode::Grid => self.grid.points(),
+        }
+    }
+
+    /// The city centre, whose weather is used for the stations without location.
+    pub fn centre(&self) -> (f64, f64) {
+        (self.latitude, self.longitude)
+    }
+
+    /// URL of the weather history of a point over the configured date range, in the time zone of
+    /// its network.
+    pub fn history_url(&self, (latitude, longitude): (f64, f64), timezone: Tz) -> String {
+        format!(
+            "{}?latitude={:.4}&longitude={:.4}&start_date={}&end_date={}&hourly={}&timeformat=unixtime&timezone={}",
+            self.history_url,
+            latitude,
+            longitude,
+            self.start_date,
+            self.end_date,
+            self.history_variables.join(","),
+            timezone.name().replace('/', "%2F")
+        )
+    }
+
+    /// URL of the weather forecast of a point, in the time zone of its network.
+    pub fn forecast_url(&self, (latitude, longitude): (f64, f64), timezone: Tz) -> String {
+        format!(
+            "{}?latitude={:.4}&longitude={:.4}&hourly={}&timeformat=unixtime&timezone={}",
+            self.forecast_url,
+            latitude,
+            longitude,
+            self.forecast_variables.join(","),
+            timezone.name().replace('/', "%2F")
+        )
+    }
+}
+
+/// Velov sources, from the Grand Lyon open data or from a GBFS feed.
+///
+/// # Fields
+///
+/// * `kind` - Whether the stations and availabilities come from the Grand Lyon open data or from
+///   a GBFS feed.
+/// * `history_url` - The URL of the Velov availability history, without pagination.
+/// * `stations_url` - The URL of the Velov stations.
+/// * `gbfs_url` - The URL of the `gbfs.json` discovery file of the GBFS feed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct VelovSource {
+    pub kind: VelovSourceKind,
+    pub history_url: String,
+    pub stations_url: String,
+    pub gbfs_url: String,
+}
+
+/// Kind of the Velov sources.
+///
+/// # Variants
+///
+/// * `GrandLyon` - The stations and the paginated availability history of the Grand Lyon open
+///   data.
+/// * `Gbfs` - The `station_information` and `station_status` feeds of a GBFS system, the
+///   availabilities being collected at each download.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum VelovSourceKind {
+    GrandLyon,
+    Gbfs,
+}
+
+impl VelovSource {
+    /// URL the stations are read from.
+    pub fn stations_source(&self) -> &str {
+        match self.kind {
+            VelovSourceKind::GrandLyon => &self.stations_url,
+            VelovSourceKind::Gbfs => &self.gbfs_url,
+        }
+    }
+
+    /// URL the availabilities are read from.
+    pub fn availabilities_source(&self) -> &str {
+        match self.kind {
+            VelovSourceKind::GrandLyon => &self.history_url,
+            VelovSourceKind::Gbfs => &self.gbfs_url,
+        }
+    }
+}
+
+impl Default for VelovSource {
+    fn default() -> Self {
+        VelovSource {
+            kind: VelovSourceKind::GrandLyon,
+            history_url: "https://data.grandlyon.com/fr/datapusher/ws/timeseries/jcd_jcdecaux.historiquevelov/all.json?filename=stations-velo-v-de-la-metropole-de-lyon---disponibilites-temps-reel".to_string(),
+            stations_url: "https://data.grandlyon.com/geoserver/metropole-de-lyon/ows?SERVICE=WFS&VERSION=2.0.0&request=GetFeature&typename=metropole-de-lyon:pvo_patrimoine_voirie.pvostationvelov&outputFormat=application/json&SRSNAME=EPSG:4171&sortBy=gid".to_string(),
+            gbfs_url: "https://transport.data.gouv.fr/gbfs/lyon/gbfs.json".to_string(),
+        }
+    }
+}
+
+/// Settings of the HTTP client used for the upstream sources.
+///
+/// # Fields
+///
+/// * `timeout` - The timeout of a request, in seconds.
+/// * `retries` - The number of times a failed request is retried.
+/// * `rate_limit` - The maximum number of requests per second.
+/// * `mode` - Whether the sources are reached live, recorded or replayed.
+/// * `fixtures_dir` - The directory of the recorded responses.
+/// * `cache_size` - The maximum total size of the responses kept for conditional requests, in
+///   megabytes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub timeout: u64,
+    pub retries: u32,
+    pub rate_limit: f64,
+    pub mode: HttpMode,
+    pub fixtures_dir: String,
+    pub cache_size: usize,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        let config = HttpConfig::default();
+        HttpSettings {
+            timeout: config.timeout.as_secs(),
+            retries: config.max_retries,
+            rate_limit: config.requests_per_second,
+            mode: config.mode,
+            fixtures_dir: config.fixtures_dir.to_string_lossy().to_string(),
+            cache_size: config.cache_capacity / (1024 * 1024),
+        }
+    }
+}
+
+impl HttpSettings {
+    /// Settings of the HTTP client.
+    pub fn http_config(&self) -> HttpConfig {
+        HttpConfig {
+            timeout: Duration::from_secs(self.timeout),
+            max_retries: self.retries,
+            requests_per_second: self.rate_limit,
+            mode: self.mode,
+            fixtures_dir: self.fixtures_dir.clone().into(),
+            cache_capacity: self.cache_size * 1024 * 1024,
+            ..HttpConfig::default()
+        }
+    }
+}
+
+/// Storage of the merged data.
+///
+/// # Fields
+///
+/// * `format` - The format the merged data is written in. Both formats are always readable.
+/// * `source` - The source the server reads the merged data from.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub format: StorageFormat,
+    pub source: DataSource,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            format: StorageFormat::Binary,
+            source: DataSource::Files,
+        }
+    }
+}
+
+/// Precomputed predictions, written to the `forecast` table by the `precompute-forecasts`
+/// command.
+///
+/// # Fields
+///
+/// * `horizon_hours` - The number of hours predicted ahead.
+/// * `step_minutes` - The time between two predictions of a station, in minutes.
+/// * `max_age_minutes` - The age after which the predictions are no longer served, in minutes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ForecastSettings {
+    pub horizon_hours: u32,
+    pub step_minutes: u32,
+    pub max_age_minutes: u32,
+}
+
+impl Default for ForecastSettings {
+    fn default() -> Self {
+        ForecastSettings {
+            horizon_hours: 48,
+            step_minutes: 15,
+            max_age_minutes: 90,
+        }
+    }
+}
+
+/// School holidays source, the official French school calendar of the open data of the
+/// Ministry of Education.
+///
+/// # Fields
+///
+/// * `calendar_url` - The URL of the JSON export of the school calendar.
+/// * `zone` - The school zone of the city, whose holidays are used by the model.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct HolidaysSource {
+    pub calendar_url: String,
+    pub zone: String,
+}
+
+impl Default for HolidaysSource {
+    fn default() -> Self {
+        HolidaysSource {
+            calendar_url: "https://data.education.gouv.fr/api/explore/v2.1/catalog/datasets/fr-en-calendrier-scolaire/exports/json".to_string(),
+            zone: "Zone A".to_string(),
+        }
+    }
+}
+
+/// Settings of the GBFS feeds published by the API.
+///
+/// # Fields
+///
+/// * `base_url` - The public URL of the API, the feed URLs of `gbfs.json` start with.
+/// * `ttl` - The number of seconds the feeds can be cached for.
+/// * `language` - The language of the feeds.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GbfsSettings {
+    pub base_url: String,
+    pub ttl: u32,
+    pub language: String,
+}
+
+impl Default for GbfsSettings {
+    fn default() -> Self {
+        GbfsSettings {
+            base_url: "http://localhost:8000".to_string(),
+            ttl: 60,
+            language: "fr".to_string(),
+        }
+    }
+}
+
+/// Bike-sharing network of the weather, Velov and holidays sections.
+///
+/// # Fields
+///
+/// * `id` - The ID of the network, used in the database and in the routes.
+/// * `name` - The name of the network.
+/// * `timezone` - The time zone of the network, in which the time features and the holidays are
+///   computed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct NetworkInfo {
+    pub id: String,
+    pub name: String,
+    pub timezone: String,
+}
+
+impl Default for NetworkInfo {
+    fn default() -> Self {
+        NetworkInfo {
+            id: "lyon".to_string(),
+            name: "Vélo'v".to_string(),
+            timezone: "Europe/Paris".to_string(),
+        }
+    }
+}
+
+/// Another bike-sharing network, with its own sources. The Velov sources of the network are
+/// those of a JCDecaux system with the same shape as Vélo'v.
+///
+/// The sources are required, so that a network missing one of them does not serve the data of
+/// the Lyon network under its own ID.
+///
+/// # Fields
+///
+/// * `name` - The name of the network.
+/// * `data_dir` - The directory of the data files of the network, `networks/<id>` if empty.
+/// * `timezone` - The time zone of the network, the one of the `network` section if empty.
+/// * `velov` - The stations and availability sources of the network.
+/// * `weather` - The weather source of the network.
+/// * `holidays` - The school holidays source of the network.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct NetworkSettings {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub data_dir: String,
+    #[serde(default)]
+    pub timezone: String,
+    pub velov: VelovSource,
+    pub weather: WeatherSource,
+    pub holidays: HolidaysSource,
+}
+
+/// A network served by the API.
+///
+/// # Fields
+///
+/// * `id` - The ID of the network.
+/// * `name` - The name of the network.
+/// * `data_dir` - The directory of the data files of the network.
+/// * `timezone` - The time zone of the network.
+/// * `velov` - The stations and availability sources of the network.
+/// * `weather` - The weather source of the network.
+/// * `holidays` - The school holidays source of the network.
+#[derive(Debug, Serialize, Clone)]
+pub struct Network {
+    pub id: String,
+    pub name: String,
+    #[serde(skip)]
+    pub data_dir: PathBuf,
+    #[serde(skip)]
+    pub timezone: Tz,
+    #[serde(skip)]
+    pub velov: VelovSource,
+    #[serde(skip)]
+    pub weather: WeatherSource,
+    #[serde(skip)]
+    pub holidays: HolidaysSource,
+}
+
+impl Config {
+    /// Reads the configuration file and applies the environment overrides.
+    ///
+    /// A missing file is not an error: the default configuration is used instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the configuration file.
+    ///
+    /// # Returns
+    ///
+    /// * The configuration, or an error message if the file or an override is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let config = Config::load("velovite.toml")?;
+    /// ```
+    pub fn load(path: &str) -> Result<Config, String> {
+        let config = if Path::new(path).exists() {
+            info!("⚙️ Reading configuration from {}", path);
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            toml::from_str::<Config>(&content)
+                .map_err(|e| format!("Invalid configuration {}: {}", path, e))?
+        } else {
+            warn!("⚙️ {} not found, using the default configuration", path);
+            Config::default()
+        };
+
+        // The overrides can also be set in the .env file
+        dotenvy::dotenv().ok();
+        let mut value = toml::Value::try_from(&config).unwrap();
+        if let toml::Value::Table(table) = &mut value {
+            apply_env_overrides(ENV_PREFIX, table);
+        }
+        let config: Config = value
+            .try_into()
+            .map_err(|e| format!("Invalid configuration override: {}", e))?;
+
+        let timezones = std::iter::once(&config.network.timezone)
+            .chain(config.networks.values().map(|settings| &settings.timezone));
+        for timezone in timezones.filter(|timezone| !timezone.is_empty()) {
+            timezone
+                .parse::<Tz>()
+                .map_err(|e| format!("Invalid time zone {}: {}", timezone, e))?;
+        }
+        Ok(config)
+    }
+
+    /// Network the commands work on.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the network, the one of the `network` section if `None`.
+    ///
+    /// # Returns
+    ///
+    /// * The network, or an error message if the network is unknown.
+    pub fn network(&self, id: Option<&str>) -> Result<Network, String> {
+        let networks = self.networks();
+        let Some(id) = id else {
+            return Ok(networks.into_iter().next().unwrap());
+        };
+        networks
+            .into_iter()
+            .find(|network| network.id == id)
+            .ok_or_else(|| format!("Unknown network {}", id))
+    }
+
+    /// Networks served by the API, the current one first.
+    pub fn networks(&self) -> Vec<Network> {
+        // The time zones are checked when the configuration is loaded
+        let timezone = |name: &str| name.parse().unwrap_or(chrono_tz::Europe::Paris);
+        let current_timezone = timezone(&self.network.timezone);
+        let current = Network {
+            id: self.network.id.clone(),
+            name: self.network.name.clone(),
+            data_dir: PathBuf::from("."),
+            timezone: current_timezone,
+            velov: self.velov.clone(),
+            weather: self.weather.clone(),
+            holidays: self.holidays.clone(),
+        };
+        let others = self.networks.iter().map(|(id, settings)| Network {
+            id: id.clone(),
+            name: settings.name.clone(),
+            data_dir: network_dir(id, settings),
+            timezone: if settings.timezone.is_empty() {
+                current_timezone
+            } else {
+                timezone(&settings.timezone)
+            },
+            velov: settings.velov.clone(),
+            weather: settings.weather.clone(),
+            holidays: settings.holidays.clone(),
+        });
+        std::iter::once(current).chain(others).collect()
+    }
+}
+
+/// Data directory of a network, `networks/<id>` if it is not configured.
+fn network_dir(id: &str, settings: &NetworkSettings) -> PathBuf {
+    if settings.data_dir.is_empty() {
+        Path::new("networks").join(id)
+    } else {
+        PathBuf::from(&settings.data_dir)
+    }
+}
+
+/// Replaces the values of a table by the environment variables named after their path.
+///
+/// # Arguments
+///
+/// * `prefix` - The name of the table, prefixing the names of the variables.
+/// * `table` - The table to override.
+fn apply_env_overrides(prefix: &str, table: &mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        let name = format!("{}_{}", prefix, key.to_uppercase());
+        if let toml::Value::Table(table) = value {
+            apply_env_overrides(&name, table);
+            continue;
+        }
+        let Ok(raw) = std::env::var(&name) else {
+            continue;
+        };
+        info!("⚙️ Using {} from the environment", name);
+        *value = match value {
+            toml::Value::Array(_) => toml::Value::Array(
+                raw.split(',')
+                    .map(|v| toml::Value::String(v.trim().to_string()))
+                    .collect(),
+            ),
+            toml::Value::String(_) => toml::Value::String(raw),
+            // Numbers and booleans are parsed as TOML values
+            _ => toml::from_str::<toml::Table>(&format!("value = {}", raw))
+                .ok()
+                .and_then(|mut t| t.remove("value"))
+                .unwrap_or(toml::Value::String(raw)),
+        };
+    }
+}
+
+/// Initialises the configuration.
+///
+/// # Arguments
+///
+/// * `config` - The configuration read by `Config::load`.
+pub fn init_config(config: Config) {
+    if CONFIG.set(config).is_err() {
+        warn!("❌ The configuration is already initialised");
+    }
+}
+
+/// Returns the configuration, or the default one if it has not been initialised.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> toml::Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn overrides_nested_keys() {
+        let mut values = table(
+            r#"
+            [weather]
+            mode = "point"
+            [weather.grid]
+            rows = 3
+            "#,
+        );
+        std::env::set_var("VELOVITE_TEST_NESTED_WEATHER_GRID_ROWS", "5");
+        std::env::set_var("VELOVITE_TEST_NESTED_WEATHER_MODE", "grid");
+
+        apply_env_overrides("VELOVITE_TEST_NESTED", &mut values);
+
+        assert_eq!(values["weather"]["grid"]["rows"].as_integer(), Some(5));
+        assert_eq!(values["weather"]["mode"].as_str(), Some("grid"));
+    }
+
+    #[test]
+    fn coerces_overrides_to_the_type_of_the_value() {
+        let mut values = table(
+            r#"
+            timeout = 60
+            rate_limit = 5.0
+            record = false
+            zone = "Zone A"
+            variables = ["temperature_2m"]
+            untouched = 1
+            invalid = 2
+            "#,
+        );
+        for (key, value) in [
+            ("TIMEOUT", "10"),
+            ("RATE_LIMIT", "0.5"),
+            ("RECORD", "true"),
+            ("ZONE", "42"),
+            ("VARIABLES", "temperature_2m, wind_speed_10m"),
+            ("INVALID", "two"),
+        ] {
+            std::env::set_var(format!("VELOVITE_TEST_TYPES_{}", key), value);
+        }
+
+        apply_env_overrides("VELOVITE_TEST_TYPES", &mut values);
+
+        assert_eq!(values["timeout"].as_integer(), Some(10));
+        assert_eq!(values["rate_limit"].as_float(), Some(0.5));
+        assert_eq!(values["record"].as_bool(), Some(true));
+        assert_eq!(values["zone"].as_str(), Some("42"));
+        assert_eq!(
+            values["variables"],
+            toml::Value::Array(vec![
+                toml::Value::String("temperature_2m".to_string()),
+                toml::Value::String("wind_speed_10m".to_string()),
+            ])
+        );
+        assert_eq!(values["untouched"].as_integer(), Some(1));
+        assert_eq!(values["invalid"].as_str(), Some("two"));
+    }
+
+    #[test]
+    fn overrides_the_configuration() {
+        let mut value = toml::Value::try_from(Config::default()).unwrap();
+        std::env::set_var("VELOVITE_TEST_CONFIG_HTTP_TIMEOUT", "5");
+        std::env::set_var("VELOVITE_TEST_CONFIG_HTTP_MODE", "replay");
+        std::env::set_var("VELOVITE_TEST_CONFIG_FORECAST_HORIZON_HOURS", "12");
+
+        apply_env_overrides("VELOVITE_TEST_CONFIG", value.as_table_mut().unwrap());
+        let config: Config = value.try_into().unwrap();
+
+        assert_eq!(config.http.timeout, 5);
+        assert_eq!(config.http.mode, HttpMode::Replay);
+        assert_eq!(config.forecast.horizon_hours, 12);
+    }
+
+    #[test]
+    fn requires_the_sources_of_the_other_networks() {
+        let network = r#"
+            [networks.toulouse]
+            name = "VélôToulouse"
+            [networks.toulouse.velov]
+            history_url = "https://example.com/history"
+            [networks.toulouse.weather]
+            mode = "point"
+            latitude = 43.6045
+            longitude = 1.444
+        "#;
+        let missing = toml::from_str::<Config>(network).unwrap_err();
+        assert!(missing.to_string().contains("missing field `holidays`"));
+
+        let complete = format!(
+            "{}\n[networks.toulouse.holidays]\nzone = \"Zone C\"",
+            network
+        );
+        let config: Config = toml::from_str(&complete).unwrap();
+        let toulouse = config.network(Some("toulouse")).unwrap();
+        assert_eq!(toulouse.holidays.zone, "Zone C");
+        assert_eq!(toulouse.weather.centre(), (43.6045, 1.444));
+    }
+
+    #[test]
+    fn weather_points_are_the_cell_centres() {
+        let grid = WeatherGrid {
+            south: 45.0,
+            north: 46.0,
+            west: 4.0,
+            east: 5.0,
+            rows: 2,
+            columns: 2,
+        };
+        assert_eq!(
+            grid.points(),
+            vec![(45.25, 4.25), (45.25, 4.75), (45.75, 4.25), (45.75, 4.75)]
+        );
+
+        let mut weather = WeatherSource {
+            grid,
+            ..WeatherSource::default()
+        };
+        assert_eq!(weather.points().len(), 4);
+        weather.mode = WeatherMode::Point;
+        assert_eq!(weather.points(), vec![weather.centre()]);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::learning::UsefulData;
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// File where the snapshots dropped by the dedup are reported.
+pub const DEDUP_REPORT_FILE: &str = "dedup_report.json";
+
+/// Policy resolving the conflicts between the snapshots of a station with the same horodate.
+///
+/// Identical snapshots are never a conflict: only one of them is kept.
+///
+/// # Variants
+///
+/// * `LatestDownload` - The snapshot of the most recently downloaded file wins.
+/// * `ConsistentCapacity` - A snapshot whose bikes and stands sum up to its capacity wins, the
+///   most recently downloaded one if there are several. If none is consistent, the most recently
+///   downloaded one is kept, and later dropped as non-compliant.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DedupPolicy {
+    LatestDownload,
+    ConsistentCapacity,
+}
+
+/// Reason why a conflicting snapshot was dropped by the dedup.
+///
+/// # Variants
+///
+/// * `OlderDownload` - The snapshot was downloaded before the kept one.
+/// * `InconsistentCapacity` - The bikes and stands of the snapshot do not sum up to its
+///   capacity, unlike the kept one.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    OlderDownload,
+    InconsistentCapacity,
+}
+
+/// A raw snapshot, with the rank of the file it was read from in the download order.
+///
+/// # Fields
+///
+/// * `download` - The rank of the file in the download order, the latest file being the highest.
+/// * `snapshot` - The snapshot.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RankedSnapshot {
+    pub download: usize,
+    #[serde(flatten)]
+    pub snapshot: UsefulData,
+}
+
+impl RankedSnapshot {
+    /// Whether the bikes and stands of the snapshot sum up to its capacity.
+    fn is_consistent(&self) -> bool {
+        self.snapshot.capacity == self.snapshot.bikes + self.snapshot.stands
+    }
+
+    /// Whether the snapshot has the same values as another one.
+    fn same_values(&self, other: &RankedSnapshot) -> bool {
+        (
+            self.snapshot.capacity,
+            self.snapshot.bikes,
+            self.snapshot.stands,
+        ) == (
+            other.snapshot.capacity,
+            other.snapshot.bikes,
+            other.snapshot.stands,
+        )
+    }
+}
+
+/// A conflicting snapshot dropped by the dedup.
+///
+/// # Fields
+///
+/// * `date` - The horodate of the snapshot.
+/// * `capacity`, `bikes`, `stands` - The values of the snapshot.
+/// * `download` - The rank of the file of the snapshot in the download order.
+/// * `reason` - The reason why the snapshot was dropped.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DroppedSnapshot {
+    pub date: DateTime<Utc>,
+    pub capacity: u32,
+    pub bikes: u32,
+    pub stands: u32,
+    pub download: usize,
+    pub reason: DropReason,
+}
+
+/// What the dedup dropped from the history of a station.
+///
+/// # Fields
+///
+/// * `id` - The ID of the station.
+/// * `identical` - The number of snapshots dropped as identical to the kept one.
+/// * `conflicts` - The number of horodates with snapshots of different values.
+/// * `dropped` - The conflicting snapshots dropped, with the reason why.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StationDedup {
+    pub id: u32,
+    pub identical: usize,
+    pub conflicts: usize,
+    pub dropped: Vec<DroppedSnapshot>,
+}
+
+/// Keeps a single snapshot per horodate of a station, following a conflict policy.
+///
+/// The result does not depend on the order of the snapshots: they are sorted by horodate and
+/// download rank, and the snapshots of a same file are kept in their order in the file.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the station.
+/// * `snapshots` - The raw snapshots of the station.
+/// * `policy` - The policy resolving the conflicts.
+///
+/// # Returns
+///
+/// * The deduped snapshots, sorted by horodate, and what was dropped.
+pub fn dedup_station(
+    id: u32,
+    mut snapshots: Vec<RankedSnapshot>,
+    policy: DedupPolicy,
+) -> (Vec<UsefulData>, StationDedup) {
+    snapshots.sort_by_key(|s| (s.snapshot.date, s.download));
+
+    let mut report = StationDedup {
+        id,
+        identical: 0,
+        conflicts: 0,
+        dropped: Vec::new(),
+    };
+    let mut kept = Vec::new();
+    for group in snapshots.chunk_by(|a, b| a.snapshot.date == b.snapshot.date) {
+        // The latest snapshot of the group comes last
+        let winner = match policy {
+            DedupPolicy::LatestDownload => group.last(),
+            DedupPolicy::ConsistentCapacity => group
+                .iter()
+                .rev()
+                .find(|s| s.is_consistent())
+                .or(group.last()),
+        }
+        .unwrap();
+
+        if group.iter().any(|s| !s.same_values(winner)) {
+            report.conflicts += 1;
+        }
+        for snapshot in group.iter().filter(|s| !std::ptr::eq(*s, winner)) {
+            if snapshot.same_values(winner) {
+                report.identical += 1;
+                continue;
+            }
+            let reason = if policy == DedupPolicy::ConsistentCapacity && !snapshot.is_consistent() {
+                DropReason::InconsistentCapacity
+            } else {
+                DropReason::OlderDownload
+            };
+            report.dropped.push(DroppedSnapshot {
+                date: snapshot.snapshot.date,
+                capacity: snapshot.snapshot.capacity,
+                bikes: snapshot.snapshot.bikes,
+                stands: snapshot.snapshot.stands,
+                download: snapshot.download,
+                reason,
+            });
+        }
+        kept.push(winner.snapshot.clone());
+    }
+
+    (kept, report)
+}
+
+/// Reports what the dedup dropped and writes it to `dedup_report.json`.
+///
+/// # Arguments
+///
+/// * `dir` - The data directory of the network.
+/// * `reports` - What was dropped from each station, sorted by station in place.
+/// * `policy` - The policy the conflicts were resolved with.
+///
+/// # Examples
+///
+/// ```rust
+/// write_dedup_report(Path::new("."), &mut reports, DedupPolicy::LatestDownload);
+/// ```
+pub fn write_dedup_report(dir: &Path, reports: &mut [StationDedup], policy: DedupPolicy) {
+    reports.sort_by_key(|r| r.id);
+
+    let identical: usize = reports.iter().map(|r| r.identical).sum();
+    let conflicts: usize = reports.iter().map(|r| r.conflicts).sum();
+    let count = |reason: DropReason| {
+        reports
+            .iter()
+            .flat_map(|r| &r.dropped)
+            .filter(|d| d.reason == reason)
+            .count()
+    };
+    info!(
+        "🗑️ Deduped with the {:?} policy: {} identical snapshots, {} conflicts ({} older downloads and {} inconsistent capacities dropped)",
+        policy,
+        identical,
+        conflicts,
+        count(DropReason::OlderDownload),
+        count(DropReason::InconsistentCapacity)
+    );
+
+    let path = dir.join(DEDUP_REPORT_FILE);
+    let writer = BufWriter::new(File::create(&path).unwrap());
+    serde_json::to_writer(writer, &reports).unwrap();
+    info!("✅ {} written!", path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(download: usize, minute: i64, bikes: u32, stands: u32) -> RankedSnapshot {
+        RankedSnapshot {
+            download,
+            snapshot: UsefulData {
+                id: 1001,
+                date: DateTime::from_timestamp(1_700_000_000 + 60 * minute, 0).unwrap(),
+                capacity: 20,
+                bikes,
+                stands,
+            },
+        }
+    }
+
+    fn values(kept: &[UsefulData]) -> Vec<(u32, u32)> {
+        kept.iter().map(|s| (s.bikes, s.stands)).collect()
+    }
+
+    #[test]
+    fn merges_identical_snapshots_with_both_policies() {
+        for policy in [DedupPolicy::LatestDownload, DedupPolicy::ConsistentCapacity] {
+            let snapshots = vec![
+                snapshot(1, 0, 5, 15),
+                snapshot(0, 0, 5, 15),
+                snapshot(0, 1, 6, 14),
+            ];
+            let (kept, report) = dedup_station(1001, snapshots, policy);
+
+            assert_eq!(values(&kept), vec![(5, 15), (6, 14)]);
+            assert_eq!(report.identical, 1);
+            assert_eq!(report.conflicts, 0);
+            assert!(report.dropped.is_empty());
+        }
+    }
+
+    #[test]
+    fn latest_download_wins_the_conflicts() {
+        let snapshots = vec![snapshot(2, 0, 3, 10), snapshot(1, 0, 5, 15)];
+        let (kept, report) = dedup_station(1001, snapshots, DedupPolicy::LatestDownload);
+
+        assert_eq!(values(&kept), vec![(3, 10)]);
+        assert_eq!(report.conflicts, 1);
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].download, 1);
+        assert_eq!(report.dropped[0].reason, DropReason::OlderDownload);
+    }
+
+    #[test]
+    fn consistent_capacity_wins_the_conflicts() {
+        let snapshots = vec![
+            snapshot(0, 0, 4, 16),
+            snapshot(1, 0, 5, 15),
+            snapshot(2, 0, 3, 10),
+            snapshot(0, 1, 1, 1),
+            snapshot(1, 1, 2, 2),
+        ];
+        let (kept, report) = dedup_station(1001, snapshots, DedupPolicy::ConsistentCapacity);
+
+        // Without any consistent snapshot, the latest download is kept
+        assert_eq!(values(&kept), vec![(5, 15), (2, 2)]);
+        assert_eq!(report.conflicts, 2);
+        let reasons: Vec<(usize, DropReason)> = report
+            .dropped
+            .iter()
+            .map(|d| (d.download, d.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (0, DropReason::OlderDownload),
+                (2, DropReason::InconsistentCapacity),
+                (0, DropReason::InconsistentCapacity),
+            ]
+        );
+    }
+}
@@ -1,136 +1,290 @@
+use crate::config::{config, Network, VelovSourceKind};
+use crate::establish_connection;
+use crate::gbfs::collect_gbfs;
+use crate::history::insert_observations;
+use crate::http::{get_text, HttpMode};
+use crate::utils::haversine;
 use ::serde::{Deserialize, Serialize};
 use chrono::{DateTime, Duration, NaiveDateTime, Utc};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use chrono_tz::Tz;
 use lazy_static::lazy_static;
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use tracing::{error, info};
 
-const WEATHER_FORECAST_URL: &str = "https://api.open-meteo.com/v1/forecast?latitude=45.7485&longitude=4.8467&hourly=temperature_2m,precipitation_probability,precipitation,weather_code,wind_speed_10m&timeformat=unixtime";
-const WEATHER_URL: &str = "https://historical-forecast-api.open-meteo.com/v1/forecast?latitude=45.7485&longitude=4.8467&start_date=2022-01-01&end_date=2025-01-23&hourly=temperature_2m,precipitation,wind_speed_10m&timeformat=unixtime&timezone=Europe%2FBerlin";
-const VELOV_URL: &str = "https://data.grandlyon.com/fr/datapusher/ws/timeseries/jcd_jcdecaux.historiquevelov/all.json?filename=stations-velo-v-de-la-metropole-de-lyon---disponibilites-temps-reel";
+/// The cached weather forecast of each network along with its expiration date.
+type StoredForecast = HashMap<String, (NaiveDateTime, Vec<WeatherCell>)>;
 
-lazy_static!(
-    static ref stored_forecast: Arc<Mutex<Option<(NaiveDateTime, HashMap<DateTime<Utc>, WeatherData>)>>> = Arc::new(Mutex::new(None));
-);
+lazy_static! {
+    static ref stored_forecast: Arc<Mutex<StoredForecast>> = Arc::new(Mutex::new(HashMap::new()));
+}
+
+/// File where the weather history is stored.
+pub const WEATHER_FILE: &str = "weather.json";
 
-/// Downloads weather forecast data from the specified URL and returns a `HashMap`
-/// where the keys are `DateTime<Utc>` and the values are `WeatherData`.
+/// Hourly weather at a point, by hour.
+pub type HourlyWeather = HashMap<DateTime<Utc>, WeatherData>;
+
+/// Weather at a point of the weather grid.
+///
+/// # Fields
+///
+/// * `latitude` - The latitude of the point.
+/// * `longitude` - The longitude of the point.
+/// * `hourly` - The hourly weather at the point.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeatherCell {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub hourly: HourlyWeather,
+}
+
+/// Content of the weather history file.
+///
+/// # Variants
+///
+/// * `Cells` - The weather of each point of the grid.
+/// * `Point` - The hourly weather of a single point, as written before the grid.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WeatherFile {
+    Cells(Vec<WeatherCell>),
+    Point(HourlyWeather),
+}
+
+/// Reads the weather history from `weather.json`, in the data directory of a network.
+///
+/// A history of a single point is read as a grid of one cell, at the configured city centre.
+///
+/// # Arguments
+///
+/// * `network` - The network, with its weather source.
+///
+/// # Returns
+///
+/// * The weather of each point of the grid.
+///
+/// # Panics
+///
+/// Panics if the file is missing or invalid.
+pub fn read_weather(network: &Network) -> Vec<WeatherCell> {
+    let content = std::fs::read_to_string(network.data_dir.join(WEATHER_FILE)).unwrap();
+    match serde_json::from_str(&content).unwrap() {
+        WeatherFile::Cells(cells) => cells,
+        WeatherFile::Point(hourly) => {
+            let (latitude, longitude) = network.weather.centre();
+            vec![WeatherCell {
+                latitude,
+                longitude,
+                hourly,
+            }]
+        }
+    }
+}
+
+/// Finds the hourly weather of the cell nearest to a location.
+///
+/// # Arguments
+///
+/// * `cells` - The weather of each point of the grid.
+/// * `location` - The latitude and longitude of the location.
+///
+/// # Returns
+///
+/// * The hourly weather of the nearest cell.
+///
+/// # Panics
+///
+/// Panics if there is no cell.
+pub fn nearest_cell(cells: &[WeatherCell], location: (f64, f64)) -> &HourlyWeather {
+    let distance = |cell: &WeatherCell| haversine(location, (cell.latitude, cell.longitude));
+    &cells
+        .iter()
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .expect("No weather cell")
+        .hourly
+}
+
+/// Downloads the weather forecast of each point of the weather grid of a network.
+///
+/// The forecast is cached for an hour.
+///
+/// # Arguments
+///
+/// * `network` - The network, with its weather source.
 ///
 /// # Errors
 ///
 /// This function will return an error if:
-/// - The HTTP request fails.
-/// - The response text cannot be read.
+/// - The HTTP request fails, after the retries of the shared client.
 /// - The JSON response cannot be parsed.
 ///
 /// # Returns
 ///
-/// A `Result` containing a `HashMap` with `DateTime<Utc>` as keys and `WeatherData` as values,
-/// or an error message if the operation fails.
+/// A `Result` containing the hourly forecast of each point of the grid, or an error message if
+/// the operation fails.
 ///
 /// # Example
 ///
 /// ```rust
-/// let weather_data = download_weather_forecast().await?;
+/// let weather_data = download_weather_forecast(&network).await?;
 /// ```
-pub async fn download_weather_forecast() -> Result<HashMap<DateTime<Utc>, WeatherData>, &'static str>
-{
-    if let Some(data) = check_stored_data() {
+pub async fn download_weather_forecast(
+    network: &Network,
+) -> Result<Vec<WeatherCell>, &'static str> {
+    if let Some(data) = check_stored_data(&network.id) {
         return Ok(data);
     }
 
-    info!("🌤️🚀 Downloading weather forecast data...");
-    let response = reqwest::get(WEATHER_FORECAST_URL)
-        .await
-        .map_err(|e| {
+    info!(
+        "🌤️🚀 Downloading weather forecast data of {}...",
+        network.id
+    );
+    let mut cells = Vec::new();
+    for (latitude, longitude) in network.weather.points() {
+        let url = network
+            .weather
+            .forecast_url((latitude, longitude), network.timezone);
+        let response = get_text(&url).await.map_err(|e| {
             error!("❌ Failed to download data: {}", e);
             "Failed to download data"
-        })?
-        .text()
-        .await
-        .map_err(|e| {
-            error!("❌ Failed to read response text: {}", e);
-            "Failed to read response text"
         })?;
 
-    info!("📥 Downloaded weather forecast data...");
-    let weather: WeatherRoot = serde_json::from_str(&response).map_err(|e| {
-        error!("❌ Failed to parse JSON: {}", e);
-        "Failed to parse JSON"
-    })?;
+        let weather: WeatherRoot = serde_json::from_str(&response).map_err(|e| {
+            error!("❌ Failed to parse JSON: {}", e);
+            "Failed to parse JSON"
+        })?;
+        let hourly = hourly_weather(weather);
 
-    let weather_mapped: HashMap<DateTime<Utc>, WeatherData> = weather
-        .hourly
+        // A replayed forecast is moved to today, keeping its hours, so that it covers the coming days
+        let hourly = if config().http.mode == HttpMode::Replay {
+            shift_to_today(hourly, network.timezone, Utc::now())
+        } else {
+            hourly
+        };
+        cells.push(WeatherCell {
+            latitude,
+            longitude,
+            hourly,
+        });
+    }
+    info!(
+        "📥 Downloaded weather forecast data for {} points...",
+        cells.len()
+    );
+
+    stored_forecast.lock().unwrap().insert(
+        network.id.clone(),
+        (Utc::now().naive_utc() + Duration::hours(1), cells.clone()),
+    );
+
+    Ok(cells)
+}
+
+/// Maps the hourly values of a weather forecast or history by hour.
+///
+/// Only the forecasts have weather codes and precipitation probabilities, left empty otherwise.
+///
+/// # Arguments
+///
+/// * `weather` - The weather forecast or history.
+///
+/// # Returns
+///
+/// * The hourly weather.
+fn hourly_weather(weather: WeatherRoot) -> HourlyWeather {
+    let hourly = weather.hourly;
+    let weather_codes = hourly.weather_code.unwrap_or_default();
+    let precipitation_probabilities = hourly.precipitation_probability.unwrap_or_default();
+    hourly
         .time
         .iter()
         .zip(
-            weather.hourly.temperature_2m.iter().zip(
-                weather.hourly.precipitation.iter().zip(
-                    weather.hourly.weather_code.unwrap().iter().zip(
-                        weather
-                            .hourly
-                            .precipitation_probability
-                            .unwrap()
-                            .iter()
-                            .zip(weather.hourly.wind_speed_10m.iter()),
-                    ),
-                ),
+            hourly.temperature_2m.iter().zip(
+                hourly
+                    .precipitation
+                    .iter()
+                    .zip(hourly.wind_speed_10m.iter()),
             ),
         )
-        .map(
-            |(
-                time,
-                (
-                    temperature,
-                    (precipitation, (weather_code, (precipitation_probability, wind_speed))),
-                ),
-            )| {
-                (
-                    *time,
-                    WeatherData {
-                        temperature_2m: *temperature,
-                        precipitation_probability: Some(*precipitation_probability),
-                        precipitation: *precipitation,
-                        weather_code: Some(*weather_code),
-                        wind_speed_10m: *wind_speed,
-                    },
-                )
-            },
-        )
-        .collect();
-
-    stored_forecast.lock().as_mut().unwrap().replace((Utc::now().naive_utc() + Duration::hours(1), weather_mapped.clone()));
-
-    Ok(weather_mapped)
+        .enumerate()
+        .map(|(i, (time, (temperature, (precipitation, wind_speed))))| {
+            (
+                *time,
+                WeatherData {
+                    temperature_2m: *temperature,
+                    precipitation_probability: precipitation_probabilities.get(i).copied(),
+                    precipitation: *precipitation,
+                    weather_code: weather_codes.get(i).copied(),
+                    wind_speed_10m: *wind_speed,
+                },
+            )
+        })
+        .collect()
 }
 
+/// Shifts a forecast by whole days so that it starts today, in the time zone of the network.
+///
+/// The days are counted between local dates: a forecast starting at local midnight, which is
+/// still the previous day in UTC east of Greenwich, is moved to the current local midnight.
+///
+/// # Arguments
+///
+/// * `forecast` - The hourly forecast.
+/// * `timezone` - The time zone of the network.
+/// * `now` - The current date.
+///
+/// # Returns
+///
+/// * The forecast, starting on the current local day at the same hour.
+fn shift_to_today(forecast: HourlyWeather, timezone: Tz, now: DateTime<Utc>) -> HourlyWeather {
+    let Some(first) = forecast.keys().min() else {
+        return forecast;
+    };
+    let local_date = |date: &DateTime<Utc>| date.with_timezone(&timezone).date_naive();
+    let days = Duration::days((local_date(&now) - local_date(first)).num_days());
+    forecast
+        .into_iter()
+        .map(|(time, data)| (time + days, data))
+        .collect()
+}
 
-/// Checks if there is stored weather forecast data that is still valid.
+/// Checks if there is stored weather forecast data of a network that is still valid.
+///
+/// # Arguments
+///
+/// * `network` - The ID of the network.
 ///
 /// # Returns
 ///
-/// An `Option` containing a `HashMap` with `DateTime<Utc>` as keys and `WeatherData` as values,
-/// if the stored data is still valid. Otherwise, returns `None`.
-fn check_stored_data() -> Option<HashMap<DateTime<Utc>, WeatherData>> {
+/// An `Option` containing the hourly forecast of each point of the grid, if the stored data is
+/// still valid. Otherwise, returns `None`.
+fn check_stored_data(network: &str) -> Option<Vec<WeatherCell>> {
     let stored = stored_forecast.lock().unwrap();
 
-    if let Some((timestamp, forecast)) = stored.as_ref() {
+    if let Some((timestamp, forecast)) = stored.get(network) {
         if timestamp > &Utc::now().naive_utc() {
             info!("📦🚀 Using cached weather forecast data...");
             return Some(forecast.clone());
         }
     }
-    
+
     None
 }
 
-/// Downloads historical weather data from the specified URL and stores it in a JSON file.
+/// Downloads the weather history of each point of the weather grid of a network and stores it
+/// in `weather.json`, in the data directory of the network.
+///
+/// # Arguments
+///
+/// * `network` - The network, with its weather source.
 ///
 /// # Errors
 ///
-/// This function will log an error and return early if:
-/// - The HTTP request fails.
-/// - The response text cannot be read.
+/// This function will log an error and return an error message if:
+/// - The HTTP request fails, after the retries of the shared client.
 /// - The JSON response cannot be parsed.
 /// - The data cannot be serialized to JSON.
 /// - The JSON data cannot be written to a file.
@@ -138,166 +292,376 @@ fn check_stored_data() -> Option<HashMap<DateTime<Utc>, WeatherData>> {
 /// # Example
 ///
 /// ```rust
-/// download_weather().await;
+/// download_weather(&network).await?;
 /// ```
-pub async fn download_weather() {
+pub async fn download_weather(network: &Network) -> Result<(), &'static str> {
     info!("🌤️🚀 Downloading weather data...");
-    let response = match reqwest::get(WEATHER_URL).await {
-        Ok(resp) => match resp.text().await {
+    let mut cells = Vec::new();
+    for (latitude, longitude) in network.weather.points() {
+        let url = network
+            .weather
+            .history_url((latitude, longitude), network.timezone);
+        let response = match get_text(&url).await {
             Ok(text) => text,
             Err(e) => {
-                error!("❌ Failed to read response text: {}", e);
-                return;
+                error!("❌ Failed to download data: {}", e);
+                return Err("Failed to download data");
             }
-        },
-        Err(e) => {
-            error!("❌ Failed to download data: {}", e);
-            return;
-        }
-    };
+        };
 
-    info!("📥 Downloaded weather data...");
-    let weather: WeatherRoot = match serde_json::from_str(&response) {
-        Ok(weather) => weather,
-        Err(e) => {
-            error!("❌ Failed to parse JSON: {}", e);
-            return;
-        }
-    };
+        info!(
+            "📥 Downloaded weather data at ({}, {})...",
+            latitude, longitude
+        );
+        let weather: WeatherRoot = match serde_json::from_str(&response) {
+            Ok(weather) => weather,
+            Err(e) => {
+                error!("❌ Failed to parse JSON: {}", e);
+                return Err("Failed to parse JSON");
+            }
+        };
 
-    let weather_mapped: HashMap<DateTime<Utc>, WeatherData> = weather
-        .hourly
-        .time
-        .iter()
-        .zip(
-            weather.hourly.temperature_2m.iter().zip(
-                weather
-                    .hourly
-                    .precipitation
-                    .iter()
-                    .zip(weather.hourly.wind_speed_10m.iter()),
-            ),
-        )
-        .map(|(time, (temperature, (precipitation, wind_speed)))| {
-            (
-                *time,
-                WeatherData {
-                    temperature_2m: *temperature,
-                    precipitation_probability: None,
-                    precipitation: *precipitation,
-                    weather_code: None,
-                    wind_speed_10m: *wind_speed,
-                },
-            )
-        })
-        .collect();
+        let hourly = hourly_weather(weather);
+        cells.push(WeatherCell {
+            latitude,
+            longitude,
+            hourly,
+        });
+    }
 
     // store the data in a json file
-    let json = match serde_json::to_string(&weather_mapped) {
+    let json = match serde_json::to_string(&cells) {
         Ok(json) => json,
         Err(e) => {
             error!("❌ Failed to serialize data to JSON: {}", e);
-            return;
+            return Err("Failed to serialize data to JSON");
         }
     };
-    if let Err(e) = std::fs::write("weather.json", json) {
+    let path = network.data_dir.join(WEATHER_FILE);
+    if let Err(e) = std::fs::write(&path, json) {
         error!("❌ Failed to write data to file: {}", e);
-    } else {
-        info!("✅ Data successfully written to weather.json");
+        return Err("Failed to write data to file");
     }
+    info!("✅ Data successfully written to {}", path.display());
+    Ok(())
+}
+
+/// File where the position of the Velov download is stored.
+pub const VELOV_CURSOR_FILE: &str = "velov_cursor.json";
+
+/// Position of the Velov download, stored in `velov_cursor.json` after each written page.
+///
+/// # Fields
+///
+/// * `index` - The index of the next page to download.
+/// * `url` - The URL of the next page to download. Once the history is exhausted, this is the
+///   URL of the last page, which is downloaded again to get the newer records.
+/// * `latest` - The latest horodate downloaded so far.
+/// * `latest_stations` - The stations already downloaded at the `latest` horodate, as the records
+///   of a horodate can be split across two pages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VelovCursor {
+    pub index: u32,
+    pub url: String,
+    pub latest: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub latest_stations: BTreeSet<u16>,
+}
+
+impl VelovCursor {
+    /// Whether a record has not been downloaded yet.
+    fn is_new(&self, value: &Value) -> bool {
+        self.latest.is_none_or(|latest| {
+            value.horodate > latest
+                || (value.horodate == latest && !self.latest_stations.contains(&value.number))
+        })
+    }
+
+    /// Keeps the records of a page which have not been downloaded yet, and moves the cursor
+    /// past them.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - The records of the page.
+    ///
+    /// # Returns
+    ///
+    /// * The records not downloaded yet.
+    fn take_new(&mut self, values: Vec<Value>) -> Vec<Value> {
+        let new_values: Vec<Value> = values.into_iter().filter(|v| self.is_new(v)).collect();
+        for value in &new_values {
+            if self.latest.is_none_or(|latest| value.horodate > latest) {
+                self.latest = Some(value.horodate);
+                self.latest_stations.clear();
+            }
+            if self.latest == Some(value.horodate) {
+                self.latest_stations.insert(value.number);
+            }
+        }
+        new_values
+    }
+}
+
+/// Reads the cursor of the Velov download.
+///
+/// # Arguments
+///
+/// * `dir` - The data directory of the network.
+///
+/// # Returns
+///
+/// * The stored cursor, or `None` if there is no download to resume.
+fn read_velov_cursor(dir: &Path) -> Option<VelovCursor> {
+    let json = std::fs::read_to_string(dir.join(VELOV_CURSOR_FILE)).ok()?;
+    serde_json::from_str(&json)
+        .map_err(|e| error!("❌ Invalid {}, ignoring it: {}", VELOV_CURSOR_FILE, e))
+        .ok()
+}
+
+/// Writes the cursor of the Velov download to the data directory of the network.
+fn write_velov_cursor(dir: &Path, cursor: &VelovCursor) -> Result<(), &'static str> {
+    let json = serde_json::to_string(cursor).map_err(|e| {
+        error!("❌ Failed to serialize the velov cursor: {}", e);
+        "Failed to serialize the velov cursor"
+    })?;
+    std::fs::write(dir.join(VELOV_CURSOR_FILE), json).map_err(|e| {
+        error!("❌ Failed to write {}: {}", VELOV_CURSOR_FILE, e);
+        "Failed to write the velov cursor"
+    })
+}
+
+/// Finds the latest horodate of the Velov data already downloaded in `velov_datas/`, and the
+/// stations downloaded at that horodate.
+///
+/// # Arguments
+///
+/// * `dir` - The data directory of the network.
+///
+/// # Returns
+///
+/// * The latest horodate, or `None` if no data has been downloaded yet, and its stations.
+fn latest_velov_records(dir: &Path) -> (Option<DateTime<Utc>>, BTreeSet<u16>) {
+    let Ok(entries) = std::fs::read_dir(dir.join("velov_datas")) else {
+        return (None, BTreeSet::new());
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name() != ".gitkeep")
+        .par_bridge()
+        .filter_map(|entry| {
+            let json = std::fs::read_to_string(entry.path()).ok()?;
+            let values: Vec<Value> = serde_json::from_str(&json).ok()?;
+            let latest = values.iter().map(|value| value.horodate).max()?;
+            let stations = values
+                .iter()
+                .filter(|value| value.horodate == latest)
+                .map(|value| value.number)
+                .collect::<BTreeSet<u16>>();
+            Some((Some(latest), stations))
+        })
+        .reduce(
+            || (None, BTreeSet::new()),
+            |a, b| match a.0.cmp(&b.0) {
+                std::cmp::Ordering::Less => b,
+                std::cmp::Ordering::Greater => a,
+                std::cmp::Ordering::Equal => (a.0, a.1.into_iter().chain(b.1).collect()),
+            },
+        )
+}
+
+/// Writes a page of Velov data to `velov_datas/`.
+///
+/// If the page has already been written by a previous download, the newer records are written
+/// to a separate file so that the previous ones are kept.
+///
+/// # Arguments
+///
+/// * `dir` - The data directory of the network.
+/// * `index` - The index of the page.
+/// * `max_velov_features` - The number of features per page.
+/// * `values` - The records of the page to write.
+fn write_velov_page(
+    dir: &Path,
+    index: u32,
+    max_velov_features: u32,
+    values: &[Value],
+) -> Result<(), &'static str> {
+    let json = serde_json::to_string(values).map_err(|e| {
+        error!("❌ Failed to serialize data to JSON: {}", e);
+        "Failed to serialize data to JSON"
+    })?;
+
+    let name = format!(
+        "data-{}-{}",
+        (index - 1) * max_velov_features,
+        index * max_velov_features
+    );
+    let mut path = dir.join(format!("velov_datas/{}.json", name));
+    if path.exists() {
+        let first = values.iter().map(|value| value.horodate).min().unwrap();
+        path = dir.join(format!("velov_datas/{}-{}.json", name, first.timestamp()));
+    }
+
+    std::fs::write(&path, json).map_err(|e| {
+        error!("❌ Failed to write data to file: {}", e);
+        "Failed to write data to file"
+    })?;
+    info!("✅ Data successfully written to {}", path.display());
+    Ok(())
+}
+
+/// A page being written to `velov_datas/`, along with the cursor to store once it is written.
+type PendingPage = (
+    tokio::task::JoinHandle<Result<(), &'static str>>,
+    VelovCursor,
+);
+
+/// Waits for a page to be written, then stores the cursor following it in the data directory of
+/// the network.
+async fn finish_velov_page(dir: &Path, pending: Option<PendingPage>) -> Result<(), &'static str> {
+    let Some((handle, cursor)) = pending else {
+        return Ok(());
+    };
+    handle.await.map_err(|e| {
+        error!("❌ Failed to write data to file: {}", e);
+        "Failed to write data to file"
+    })??;
+    write_velov_cursor(dir, &cursor)
 }
 
 /// Downloads Velov bike-sharing data from the specified URL in a paginated manner and stores each page in a separate JSON file.
 ///
+/// The download is incremental: only the records not downloaded yet, newer than the latest
+/// horodate already downloaded or of another station at that horodate, are stored, and the position of the download is stored in `velov_cursor.json`
+/// after each written page, so that an interrupted download resumes where it stopped and a
+/// finished one resumes from its last page. The history being ordered by horodate, the pages
+/// before the cursor are not downloaded again. Delete `velov_cursor.json` to download the
+/// history from `velov_start` again.
+///
+/// Each page is written in a separate task while the next one is downloaded, and the download
+/// only reports success once every page has been written. The new records of each page are also
+/// recorded in the `observation` table, from which the API publishes its `station_status` feed.
+///
+/// With a GBFS source, the current status of the stations is collected instead, the pagination
+/// being ignored.
+///
 /// # Arguments
 ///
+/// * `network` - The network, with its Velov sources.
 /// * `max_velov_features` - The maximum number of features to download per request.
-/// * `velov_start` - The starting index for pagination.
+/// * `velov_start` - The starting index for pagination, if there is no download to resume.
 ///
 /// # Errors
 ///
-/// This function will log an error and break the loop if:
-/// - The HTTP request fails.
-/// - The response text cannot be read.
+/// This function will log an error and return an error message if:
+/// - The HTTP request fails, after the retries of the shared client.
 /// - The JSON response cannot be parsed.
 /// - The data cannot be serialized to JSON.
 /// - The JSON data cannot be written to a file.
+/// - The records cannot be recorded in the database.
 ///
 /// # Example
 ///
 /// ```rust
-/// download_velov(100, 0).await;
+/// download_velov(&network, 100, 0).await?;
 /// ```
-pub async fn download_velov(max_velov_features: u32, velov_start: u32) {
+pub async fn download_velov(
+    network: &Network,
+    max_velov_features: u32,
+    velov_start: u32,
+) -> Result<(), &'static str> {
+    if network.velov.kind == VelovSourceKind::Gbfs {
+        return collect_gbfs(network).await;
+    }
     info!("🚴‍♂️🚀 Downloading velov data...");
-    let mut index = velov_start;
-    let mut url = format!(
-        "{}?maxfeatures={}&start={}",
-        VELOV_URL,
-        max_velov_features,
-        index * max_velov_features + 1
-    );
+    let dir = network.data_dir.as_path();
+    let mut cursor = match read_velov_cursor(dir) {
+        Some(cursor) => {
+            info!("⏩ Resuming velov download from page {}", cursor.index);
+            cursor
+        }
+        None => {
+            let (latest, latest_stations) = latest_velov_records(dir);
+            VelovCursor {
+                index: velov_start,
+                url: format!(
+                    "{}&maxfeatures={}&start={}",
+                    network.velov.history_url,
+                    max_velov_features,
+                    velov_start * max_velov_features + 1
+                ),
+                latest,
+                latest_stations,
+            }
+        }
+    };
+    if let Some(latest) = cursor.latest {
+        info!("📅 Downloading velov data from {}", latest);
+    }
+    // The pages are written one after the other, along with the months of their partitions
+    let recorder = Arc::new(Mutex::new((establish_connection(), HashSet::new())));
+
+    let mut pending: Option<PendingPage> = None;
     loop {
-        info!("🚴‍♂️⏳ Downloading velov data... {}", index);
-        let response = match reqwest::get(url).await {
-            Ok(resp) => match resp.text().await {
-                Ok(text) => text,
-                Err(e) => {
-                    error!("❌ Failed to read response text: {}", e);
-                    break;
-                }
-            },
+        info!("🚴‍♂️⏳ Downloading velov data... {}", cursor.index);
+        let page = match get_text(&cursor.url).await {
+            Ok(text) => serde_json::from_str::<VelovRoot>(&text).map_err(|e| {
+                error!("❌ Failed to parse JSON: {}", e);
+                "Failed to parse JSON"
+            }),
             Err(e) => {
                 error!("❌ Failed to download data: {}", e);
-                break;
+                Err("Failed to download data")
             }
         };
-        info!("📥 Downloaded velov data... {}", index);
-        let raw_stations: VelovRoot = match serde_json::from_str(&response) {
-            Ok(stations) => stations,
+        let raw_stations = match page {
+            Ok(raw_stations) => raw_stations,
             Err(e) => {
-                error!("❌ Failed to parse JSON: {}", e);
-                break;
+                // Keep the pages already downloaded so the next run resumes after them
+                finish_velov_page(dir, pending).await?;
+                return Err(e);
             }
         };
+        info!("📥 Downloaded velov data... {}", cursor.index);
+
+        let mut next_cursor = cursor.clone();
+        let new_values = next_cursor.take_new(raw_stations.values);
+        if let Some(next) = raw_stations.next.as_ref() {
+            next_cursor.index += 1;
+            next_cursor.url = next.clone();
+        }
 
+        finish_velov_page(dir, pending.take()).await?;
         // store the data in a json file in a separate thread
-        // let data_clone = data.last().cloned();
-        tokio::spawn(async move {
-            let json = match serde_json::to_string(&raw_stations.values) {
-                Ok(json) => json,
-                Err(e) => {
-                    error!("❌ Failed to serialize data to JSON: {}", e);
-                    return;
-                }
-            };
-            if let Err(e) = std::fs::write(
-                format!(
-                    "velov_datas/data-{}-{}.json",
-                    (index - 1) * max_velov_features,
-                    index * max_velov_features
-                ),
-                json,
-            ) {
-                error!("❌ Failed to write data to file: {}", e);
-            } else {
-                info!(
-                    "✅ Data successfully written to data-{}-{}.json",
-                    (index - 1) * max_velov_features,
-                    index * max_velov_features
-                );
+        let index = cursor.index;
+        let page_dir = dir.to_path_buf();
+        let network_id = network.id.clone();
+        let recorder = recorder.clone();
+        let handle = tokio::task::spawn_blocking(move || {
+            if new_values.is_empty() {
+                info!("⏭️ No new velov data in page {}", index);
+                return Ok(());
             }
+            write_velov_page(&page_dir, index, max_velov_features, &new_values)?;
+            let (connection, partitions) = &mut *recorder.lock().unwrap();
+            insert_observations(connection, &network_id, &new_values, partitions)
+                .map(|_| ())
+                .map_err(|e| {
+                    error!("❌ {}", e);
+                    "Failed to record the observations"
+                })
         });
+        pending = Some((handle, next_cursor.clone()));
 
         if raw_stations.next.is_none() {
             break;
         }
-
-        url = raw_stations.next.unwrap();
-        index += 1;
+        cursor = next_cursor;
     }
 
+    finish_velov_page(dir, pending).await?;
     info!("📥 Downloaded velov data ✅");
+    Ok(())
 }
 
 /// Represents the root structure of weather data.
@@ -698,3 +1062,154 @@ mod list_unix_time {
         Ok(dt)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DurationRound;
+
+    fn value(number: u16, horodate: &str) -> Value {
+        Value {
+            horodate: horodate.parse().unwrap(),
+            number,
+            ..Default::default()
+        }
+    }
+
+    fn cursor(latest: Option<&str>, latest_stations: &[u16]) -> VelovCursor {
+        VelovCursor {
+            index: 1,
+            url: String::new(),
+            latest: latest.map(|latest| latest.parse().unwrap()),
+            latest_stations: latest_stations.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn cursor_resumes_in_the_middle_of_a_horodate() {
+        let mut cursor = cursor(Some("2024-06-01T10:00:00Z"), &[1, 2]);
+
+        let new_values = cursor.take_new(vec![
+            value(1, "2024-06-01T09:55:00Z"),
+            value(2, "2024-06-01T10:00:00Z"),
+            value(3, "2024-06-01T10:00:00Z"),
+            value(1, "2024-06-01T10:05:00Z"),
+            value(2, "2024-06-01T10:05:00Z"),
+        ]);
+
+        let keys: Vec<(u16, String)> = new_values
+            .iter()
+            .map(|v| (v.number, v.horodate.format("%H:%M").to_string()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (3, "10:00".to_owned()),
+                (1, "10:05".to_owned()),
+                (2, "10:05".to_owned())
+            ]
+        );
+        assert_eq!(cursor.latest, Some("2024-06-01T10:05:00Z".parse().unwrap()));
+        assert_eq!(cursor.latest_stations, BTreeSet::from([1, 2]));
+    }
+
+    #[test]
+    fn cursor_extends_the_stations_of_a_split_horodate() {
+        let mut cursor = cursor(Some("2024-06-01T10:00:00Z"), &[1]);
+
+        let new_values = cursor.take_new(vec![
+            value(1, "2024-06-01T10:00:00Z"),
+            value(2, "2024-06-01T10:00:00Z"),
+        ]);
+
+        assert_eq!(new_values.len(), 1);
+        assert_eq!(new_values[0].number, 2);
+        assert_eq!(cursor.latest, Some("2024-06-01T10:00:00Z".parse().unwrap()));
+        assert_eq!(cursor.latest_stations, BTreeSet::from([1, 2]));
+        let again = cursor.take_new(vec![value(2, "2024-06-01T10:00:00Z")]);
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn cursor_keeps_everything_without_previous_download() {
+        let mut cursor = cursor(None, &[]);
+
+        let new_values = cursor.take_new(vec![
+            value(1, "2024-06-01T10:05:00Z"),
+            value(2, "2024-06-01T10:00:00Z"),
+        ]);
+
+        assert_eq!(new_values.len(), 2);
+        assert_eq!(cursor.latest, Some("2024-06-01T10:05:00Z".parse().unwrap()));
+        assert_eq!(cursor.latest_stations, BTreeSet::from([1]));
+    }
+
+    fn cell(latitude: f64, longitude: f64, temperature: f32) -> WeatherCell {
+        let time = "2024-04-12T08:00:00Z".parse().unwrap();
+        WeatherCell {
+            latitude,
+            longitude,
+            hourly: HashMap::from([(
+                time,
+                WeatherData {
+                    temperature_2m: temperature,
+                    ..Default::default()
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn uses_the_weather_of_the_nearest_cell() {
+        let cells = [cell(45.7, 4.8, 10.0), cell(45.8, 4.9, 20.0)];
+        let temperature = |location| {
+            let hourly = nearest_cell(&cells, location);
+            hourly.values().next().unwrap().temperature_2m
+        };
+
+        assert_eq!(temperature((45.71, 4.81)), 10.0);
+        assert_eq!(temperature((45.79, 4.88)), 20.0);
+        assert_eq!(temperature((46.5, 5.5)), 20.0);
+    }
+
+    #[test]
+    fn maps_the_history_without_forecast_values() {
+        let weather = WeatherRoot {
+            hourly: Hourly {
+                time: vec![
+                    DateTime::from_timestamp(1_712_908_800, 0).unwrap(),
+                    DateTime::from_timestamp(1_712_912_400, 0).unwrap(),
+                ],
+                temperature_2m: vec![12.5, 13.0],
+                precipitation: vec![0.0, 0.4],
+                wind_speed_10m: vec![8.0, 9.5],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let hourly = hourly_weather(weather);
+
+        let time = DateTime::from_timestamp(1_712_912_400, 0).unwrap();
+        assert_eq!(hourly.len(), 2);
+        assert_eq!(hourly[&time].precipitation, 0.4);
+        assert_eq!(hourly[&time].weather_code, None);
+        assert_eq!(hourly[&time].precipitation_probability, None);
+    }
+
+    #[test]
+    fn replayed_forecast_covers_the_current_local_hour() {
+        // The recorded forecast starts at 2025-01-19T23:00Z, local midnight in Paris
+        let weather: WeatherRoot = serde_json::from_str(include_str!(
+            "../fixtures/api.open-meteo.com-f5c6bdbe7711e895.json"
+        ))
+        .unwrap();
+        let hourly = hourly_weather(weather);
+
+        for now in ["2026-01-10T12:34:00Z", "2026-01-10T23:30:00Z"] {
+            let now: DateTime<Utc> = now.parse().unwrap();
+            let shifted = shift_to_today(hourly.clone(), chrono_tz::Europe::Paris, now);
+            let hour = now.duration_trunc(Duration::hours(1)).unwrap();
+            assert!(shifted.contains_key(&hour), "{} is not covered", hour);
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use crate::api::{predict_all, AvailabilityData};
+use crate::config::config;
+use crate::models::Forecast;
+use crate::schema::forecast;
+use crate::utils::{local_time, Quantiles, NEIGHBOURS};
+use crate::AppState;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use chrono_tz::Tz;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use std::collections::HashMap;
+use tracing::{error, info, warn};
+
+/// Number of rows inserted by a single statement, below the 65535 parameters of Postgres.
+const INSERT_CHUNK_SIZE: usize = 4000;
+
+/// Version of the prediction model, stored with the precomputed predictions.
+///
+/// The predictions of another version are never served, so that a new model does not serve the
+/// predictions of the previous one.
+pub fn model_version() -> String {
+    format!("knn{}-{}", NEIGHBOURS, env!("CARGO_PKG_VERSION"))
+}
+
+/// Returns the time between two precomputed predictions of a station.
+fn forecast_step() -> Duration {
+    Duration::minutes(config().forecast.step_minutes.max(1) as i64)
+}
+
+/// Returns the precomputed slot serving a date, the nearest one on the grid of predictions.
+fn forecast_slot(date: DateTime<Tz>) -> DateTime<Utc> {
+    date.with_timezone(&Utc)
+        .duration_round(forecast_step())
+        .unwrap()
+}
+
+/// Returns the oldest computation date of the predictions which can still be served.
+fn fresh_since() -> DateTime<Utc> {
+    Utc::now() - Duration::minutes(config().forecast.max_age_minutes as i64)
+}
+
+impl From<Forecast> for AvailabilityData {
+    fn from(forecast: Forecast) -> Self {
+        AvailabilityData {
+            id: forecast.id as u32,
+            free_stands: forecast.free_stands as u32,
+            available_bikes: forecast.available as u32,
+            low_confidence: forecast.low_confidence,
+            bikes_quantiles: Quantiles {
+                p10: forecast.bikes_p10 as u32,
+                p50: forecast.bikes_p50 as u32,
+                p90: forecast.bikes_p90 as u32,
+            },
+            stands_quantiles: Quantiles {
+                p10: forecast.stands_p10 as u32,
+                p50: forecast.stands_p50 as u32,
+                p90: forecast.stands_p90 as u32,
+            },
+        }
+    }
+}
+
+impl Forecast {
+    /// Builds the row of a prediction.
+    ///
+    /// # Arguments
+    ///
+    /// * `availability` - The predicted availability of the station.
+    /// * `timestamp` - The date and time of the prediction.
+    /// * `model_version` - The version of the model.
+    /// * `computed_at` - The date and time the prediction was computed.
+    /// * `network` - The ID of the network of the station.
+    fn new(
+        availability: &AvailabilityData,
+        timestamp: DateTime<Utc>,
+        model_version: &str,
+        computed_at: DateTime<Utc>,
+        network: &str,
+    ) -> Self {
+        Forecast {
+            id: availability.id as i32,
+            timestamp,
+            available: availability.available_bikes as i32,
+            free_stands: availability.free_stands as i32,
+            bikes_p10: availability.bikes_quantiles.p10 as i32,
+            bikes_p50: availability.bikes_quantiles.p50 as i32,
+            bikes_p90: availability.bikes_quantiles.p90 as i32,
+            stands_p10: availability.stands_quantiles.p10 as i32,
+            stands_p50: availability.stands_quantiles.p50 as i32,
+            stands_p90: availability.stands_quantiles.p90 as i32,
+            low_confidence: availability.low_confidence,
+            model_version: model_version.to_string(),
+            computed_at,
+            network: network.to_string(),
+        }
+    }
+}
+
+/// Keeps the precomputed predictions which can be served: computed by a model version less than
+/// `max_age_minutes` ago.
+///
+/// # Arguments
+///
+/// * `forecasts` - The precomputed predictions of a slot.
+/// * `version` - The current model version.
+/// * `since` - The oldest computation date which can still be served.
+///
+/// # Returns
+///
+/// * The fresh precomputed availability of each station.
+fn fresh_of(
+    forecasts: Vec<Forecast>,
+    version: &str,
+    since: DateTime<Utc>,
+) -> HashMap<u32, AvailabilityData> {
+    forecasts
+        .into_iter()
+        .filter(|forecast| forecast.model_version == version && forecast.computed_at >= since)
+        .map(|forecast| (forecast.id as u32, AvailabilityData::from(forecast)))
+        .collect()
+}
+
+/// Loads the precomputed predictions of the network for the slot serving a date.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the database connection.
+/// * `id` - The ID of the station, or `None` for every station.
+/// * `date` - The date and time of the predictions.
+fn slot_forecasts(state: &AppState, id: Option<u32>, date: DateTime<Tz>) -> Vec<Forecast> {
+    let mut connection = state.connection.lock().unwrap();
+
+    let mut query = forecast::table
+        .filter(forecast::network.eq(&state.network.id))
+        .filter(forecast::timestamp.eq(forecast_slot(date)))
+        .select(Forecast::as_select())
+        .into_boxed();
+    if let Some(id) = id {
+        query = query.filter(forecast::id.eq(id as i32));
+    }
+    query.load(&mut *connection).unwrap_or_else(|e| {
+        error!("❌ Failed to read the forecasts: {}", e);
+        Vec::new()
+    })
+}
+
+/// Returns the fresh precomputed prediction of a station, if any.
+///
+/// The prediction of the nearest slot of the grid is served, computed by the current model
+/// version less than `max_age_minutes` ago.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the database connection.
+/// * `id` - The ID of the station.
+/// * `date` - The date and time of the prediction.
+///
+/// # Returns
+///
+/// * The precomputed availability, or `None` if there is no fresh one.
+pub fn fresh_forecast(state: &AppState, id: u32, date: DateTime<Tz>) -> Option<AvailabilityData> {
+    let forecasts = slot_forecasts(state, Some(id), date);
+    fresh_of(forecasts, &model_version(), fresh_since()).remove(&id)
+}
+
+/// Returns the fresh precomputed predictions of every station, if they cover them all.
+///
+/// A partial precomputation, interrupted or run before new stations got a history, is not
+/// served, so that no station is dropped from the predictions.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the database connection.
+/// * `date` - The date and time of the predictions.
+///
+/// # Returns
+///
+/// * The precomputed availability of each station with a history, or `None` if one of them has
+///   no fresh prediction.
+pub fn fresh_forecasts(
+    state: &AppState,
+    date: DateTime<Tz>,
+) -> Option<HashMap<u32, AvailabilityData>> {
+    let forecasts = slot_forecasts(state, None, date);
+    let fresh = fresh_of(forecasts, &model_version(), fresh_since());
+    covering(fresh, state.data.keys())
+}
+
+/// Returns the predictions if they cover every station.
+fn covering<'a>(
+    forecasts: HashMap<u32, AvailabilityData>,
+    mut stations: impl Iterator<Item = &'a u32>,
+) -> Option<HashMap<u32, AvailabilityData>> {
+    let missing = stations.find(|id| !forecasts.contains_key(id));
+    if let Some(id) = missing {
+        info!("⏭️ No fresh precomputed prediction of {}", id);
+        return None;
+    }
+    (!forecasts.is_empty()).then_some(forecasts)
+}
+
+/// Precomputes the predictions of every station with a history over the forecast horizon, and
+/// stores them in the `forecast` table.
+///
+/// The predictions are computed on a grid of `step_minutes`, from the current slot to
+/// `horizon_hours` ahead, or to the end of the weather forecast if it is shorter. The past
+/// predictions and the ones of other model versions are removed.
+///
+/// # Arguments
+///
+/// * `state` - The application state containing the prediction data and holidays.
+///
+/// # Returns
+///
+/// * An error message if no prediction could be computed or stored.
+///
+/// # Examples
+///
+/// ```rust
+/// precompute_forecasts(&app_state).await?;
+/// ```
+pub async fn precompute_forecasts(state: &AppState) -> Result<(), &'static str> {
+    let step = forecast_step();
+    let start = Utc::now().duration_trunc(step).unwrap();
+    let end = start + Duration::hours(config().forecast.horizon_hours as i64);
+    let version = model_version();
+    let computed_at = Utc::now();
+    info!(
+        "🔮 Precomputing the predictions from {} to {} ({})..",
+        start, end, version
+    );
+
+    let mut forecasts = Vec::new();
+    let mut slot = start;
+    while slot <= end {
+        match predict_all(state, local_time(slot, state.network.timezone)).await {
+            Ok(predictions) => forecasts.extend(predictions.values().map(|availability| {
+                Forecast::new(availability, slot, &version, computed_at, &state.network.id)
+            })),
+            Err(_) => {
+                warn!("🌦️ No weather forecast from {}, stopping there", slot);
+                break;
+            }
+        }
+        slot += step;
+    }
+    if forecasts.is_empty() {
+        error!("❌ No prediction to store");
+        return Err("No prediction to store");
+    }
+
+    let mut connection = state.connection.lock().unwrap();
+    store_forecasts(
+        &mut connection,
+        &state.network.id,
+        &forecasts,
+        start,
+        &version,
+    )
+    .map_err(|e| {
+        error!("❌ Failed to store the predictions: {}", e);
+        "Failed to store the predictions"
+    })?;
+
+    info!("✅ {} predictions stored!", forecasts.len());
+    Ok(())
+}
+
+/// Replaces the stored predictions by new ones, in a single transaction.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `network` - The ID of the network of the predictions, whose predictions are replaced.
+/// * `forecasts` - The new predictions.
+/// * `start` - The first slot of the new predictions, before which the predictions are removed.
+/// * `version` - The version of the model, the predictions of other versions are removed.
+fn store_forecasts(
+    connection: &mut PgConnection,
+    network: &str,
+    forecasts: &[Forecast],
+    start: DateTime<Utc>,
+    version: &str,
+) -> QueryResult<()> {
+    connection.transaction(|connection| {
+        diesel::delete(
+            forecast::table
+                .filter(forecast::network.eq(network))
+                .filter(
+                    forecast::timestamp
+                        .lt(start)
+                        .or(forecast::model_version.ne(version)),
+                ),
+        )
+        .execute(connection)?;
+
+        for chunk in forecasts.chunks(INSERT_CHUNK_SIZE) {
+            diesel::insert_into(forecast::table)
+                .values(chunk)
+                .on_conflict((forecast::network, forecast::id, forecast::timestamp))
+                .do_update()
+                .set((
+                    forecast::available.eq(excluded(forecast::available)),
+                    forecast::free_stands.eq(excluded(forecast::free_stands)),
+                    forecast::bikes_p10.eq(excluded(forecast::bikes_p10)),
+                    forecast::bikes_p50.eq(excluded(forecast::bikes_p50)),
+                    forecast::bikes_p90.eq(excluded(forecast::bikes_p90)),
+                    forecast::stands_p10.eq(excluded(forecast::stands_p10)),
+                    forecast::stands_p50.eq(excluded(forecast::stands_p50)),
+                    forecast::stands_p90.eq(excluded(forecast::stands_p90)),
+                    forecast::low_confidence.eq(excluded(forecast::low_confidence)),
+                    forecast::model_version.eq(excluded(forecast::model_version)),
+                    forecast::computed_at.eq(excluded(forecast::computed_at)),
+                ))
+                .execute(connection)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forecast(id: u32, version: &str, computed_at: DateTime<Utc>) -> Forecast {
+        let availability = AvailabilityData {
+            id,
+            free_stands: 4,
+            available_bikes: 6,
+            low_confidence: false,
+            bikes_quantiles: Quantiles::exact(6),
+            stands_quantiles: Quantiles::exact(4),
+        };
+        Forecast::new(&availability, Utc::now(), version, computed_at, "lyon")
+    }
+
+    #[test]
+    fn serves_only_fresh_predictions_of_the_current_version() {
+        let now = Utc::now();
+        let since = now - Duration::minutes(90);
+
+        let fresh = fresh_of(
+            vec![
+                forecast(1, "v2", now - Duration::minutes(10)),
+                forecast(2, "v1", now - Duration::minutes(10)),
+                forecast(3, "v2", now - Duration::minutes(120)),
+            ],
+            "v2",
+            since,
+        );
+
+        assert_eq!(fresh.keys().copied().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(fresh[&1].available_bikes, 6);
+    }
+
+    #[test]
+    fn falls_back_when_a_station_is_not_covered() {
+        let now = Utc::now();
+        let fresh = || {
+            fresh_of(
+                vec![forecast(1, "v2", now), forecast(2, "v1", now)],
+                "v2",
+                now - Duration::minutes(90),
+            )
+        };
+
+        assert!(covering(fresh(), [1, 2].iter()).is_none());
+        assert_eq!(covering(fresh(), [1].iter()).map(|f| f.len()), Some(1));
+        assert!(covering(HashMap::new(), std::iter::empty()).is_none());
+    }
+}
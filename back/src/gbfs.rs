@@ -0,0 +1,745 @@
+use crate::config::{config, Network};
+use crate::downloader::{Availabilities, MainStands, TotalStands, Value};
+use crate::establish_connection;
+use crate::history::insert_observations;
+use crate::http::get_text;
+use crate::models::{DetailedStation, Observation};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tracing::{error, info, warn};
+
+/// Version of the GBFS feeds published by the API.
+pub const GBFS_VERSION: &str = "2.3";
+/// Feeds published by the API, along with the `gbfs.json` discovery file.
+pub const PUBLISHED_FEEDS: [&str; 3] = [
+    "system_information",
+    "station_information",
+    "station_status",
+];
+
+/// A GBFS file: every feed shares this envelope.
+///
+/// # Fields
+///
+/// * `last_updated` - The last time the data of the feed was updated.
+/// * `ttl` - The number of seconds before the data of the feed is updated again.
+/// * `version` - The GBFS version of the feed, missing before GBFS 1.1.
+/// * `data` - The data of the feed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GbfsFile<T> {
+    pub last_updated: GbfsTimestamp,
+    pub ttl: u32,
+    #[serde(default)]
+    pub version: String,
+    pub data: T,
+}
+
+impl<T> GbfsFile<T> {
+    /// Wraps the data of a feed published by the API.
+    pub fn new(last_updated: DateTime<Utc>, data: T) -> Self {
+        GbfsFile {
+            last_updated: GbfsTimestamp::Posix(last_updated.timestamp()),
+            ttl: config().gbfs.ttl,
+            version: GBFS_VERSION.to_string(),
+            data,
+        }
+    }
+}
+
+/// A GBFS timestamp.
+///
+/// # Variants
+///
+/// * `Posix` - A POSIX timestamp, up to GBFS 2.3.
+/// * `Rfc3339` - A RFC 3339 date and time, from GBFS 3.0.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum GbfsTimestamp {
+    Posix(i64),
+    Rfc3339(DateTime<Utc>),
+}
+
+impl GbfsTimestamp {
+    /// Converts the timestamp to a UTC date and time.
+    pub fn to_utc(self) -> Option<DateTime<Utc>> {
+        match self {
+            GbfsTimestamp::Posix(seconds) => DateTime::from_timestamp(seconds, 0),
+            GbfsTimestamp::Rfc3339(date) => Some(date),
+        }
+    }
+}
+
+/// A GBFS text.
+///
+/// # Variants
+///
+/// * `Plain` - A plain text, up to GBFS 2.3.
+/// * `Localized` - The translations of the text, from GBFS 3.0.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum GbfsText {
+    Plain(String),
+    Localized(Vec<LocalizedText>),
+}
+
+/// A translation of a GBFS text.
+///
+/// # Fields
+///
+/// * `text` - The translated text.
+/// * `language` - The language of the translation.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LocalizedText {
+    pub text: String,
+    pub language: String,
+}
+
+impl GbfsText {
+    /// Text in the configured language, or in the first available one.
+    pub fn text(&self) -> String {
+        match self {
+            GbfsText::Plain(text) => text.clone(),
+            GbfsText::Localized(translations) => translations
+                .iter()
+                .find(|t| t.language == config().gbfs.language)
+                .or(translations.first())
+                .map(|t| t.text.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Data of the `gbfs.json` discovery file.
+///
+/// # Variants
+///
+/// * `Feeds` - The feeds of the system, from GBFS 3.0.
+/// * `Languages` - The feeds of the system in each language, up to GBFS 2.3.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum GbfsDiscovery {
+    Feeds(GbfsFeeds),
+    Languages(BTreeMap<String, GbfsFeeds>),
+}
+
+/// Feeds listed by the `gbfs.json` discovery file.
+///
+/// # Fields
+///
+/// * `feeds` - The feeds.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GbfsFeeds {
+    pub feeds: Vec<GbfsFeed>,
+}
+
+/// A feed listed by the `gbfs.json` discovery file.
+///
+/// # Fields
+///
+/// * `name` - The name of the feed, for instance `station_status`.
+/// * `url` - The URL of the feed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GbfsFeed {
+    pub name: String,
+    pub url: String,
+}
+
+impl GbfsDiscovery {
+    /// URL of a feed, in the configured language if the feeds are translated.
+    pub fn feed_url(&self, name: &str) -> Option<&str> {
+        fn find<'a>(feeds: &'a GbfsFeeds, name: &str) -> Option<&'a str> {
+            feeds
+                .feeds
+                .iter()
+                .find(|feed| feed.name == name)
+                .map(|feed| feed.url.as_str())
+        }
+        match self {
+            GbfsDiscovery::Feeds(feeds) => find(feeds, name),
+            GbfsDiscovery::Languages(languages) => languages
+                .get(&config().gbfs.language)
+                .and_then(|feeds| find(feeds, name))
+                .or_else(|| languages.values().find_map(|feeds| find(feeds, name))),
+        }
+    }
+}
+
+/// Data of the `system_information` feed.
+///
+/// # Fields
+///
+/// * `system_id` - The ID of the system, the ID of the network.
+/// * `language` - The language of the feeds.
+/// * `name` - The name of the system.
+/// * `timezone` - The timezone of the system.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SystemInformation {
+    pub system_id: String,
+    pub language: String,
+    pub name: String,
+    pub timezone: String,
+}
+
+/// Data of the `station_information` feed.
+///
+/// # Fields
+///
+/// * `stations` - The stations of the system.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StationInformation {
+    pub stations: Vec<GbfsStation>,
+}
+
+/// A station of the `station_information` feed.
+///
+/// # Fields
+///
+/// * `station_id` - The ID of the station.
+/// * `name` - The name of the station.
+/// * `lat`, `lon` - The location of the station.
+/// * `address` - The address of the station.
+/// * `region_id` - The ID of the region of the station, listed by the `system_regions` feed.
+/// * `capacity` - The number of docks of the station.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GbfsStation {
+    pub station_id: String,
+    pub name: GbfsText,
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<u32>,
+}
+
+/// Data of the `system_regions` feed.
+///
+/// # Fields
+///
+/// * `regions` - The regions of the system.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SystemRegions {
+    pub regions: Vec<GbfsRegion>,
+}
+
+/// A region of the `system_regions` feed.
+///
+/// # Fields
+///
+/// * `region_id` - The ID of the region.
+/// * `name` - The name of the region.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GbfsRegion {
+    pub region_id: String,
+    pub name: GbfsText,
+}
+
+/// Data of the `station_status` feed.
+///
+/// # Fields
+///
+/// * `stations` - The status of the stations of the system.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StationStatus {
+    pub stations: Vec<GbfsStationStatus>,
+}
+
+/// The status of a station of the `station_status` feed.
+///
+/// # Fields
+///
+/// * `station_id` - The ID of the station.
+/// * `num_bikes_available` - The number of available bikes, `num_vehicles_available` from GBFS
+///   3.0.
+/// * `num_bikes_available_types` - The number of available bikes of each type, an extension of
+///   some systems.
+/// * `num_docks_available` - The number of free docks.
+/// * `is_installed` - Whether the station is installed on the street.
+/// * `is_renting` - Whether bikes can be rented at the station.
+/// * `is_returning` - Whether bikes can be returned to the station.
+/// * `last_reported` - The last time the station reported its status.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GbfsStationStatus {
+    pub station_id: String,
+    #[serde(alias = "num_vehicles_available")]
+    pub num_bikes_available: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_bikes_available_types: Option<BikeTypes>,
+    #[serde(default)]
+    pub num_docks_available: u32,
+    pub is_installed: bool,
+    pub is_renting: bool,
+    pub is_returning: bool,
+    pub last_reported: GbfsTimestamp,
+}
+
+/// The number of available bikes of each type.
+///
+/// # Fields
+///
+/// * `mechanical` - The number of mechanical bikes.
+/// * `ebike` - The number of electrical bikes.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct BikeTypes {
+    #[serde(default)]
+    pub mechanical: u32,
+    #[serde(default)]
+    pub ebike: u32,
+}
+
+/// Downloads and parses a GBFS file.
+///
+/// # Arguments
+///
+/// * `url` - The URL of the file.
+///
+/// # Returns
+///
+/// * The parsed file, or an error message.
+async fn fetch_gbfs<T: DeserializeOwned>(url: &str) -> Result<GbfsFile<T>, &'static str> {
+    let response = get_text(url).await.map_err(|e| {
+        error!("❌ Failed to download {}: {}", url, e);
+        "Failed to download the GBFS feed"
+    })?;
+    serde_json::from_str(&response).map_err(|e| {
+        error!("❌ Failed to parse {}: {}", url, e);
+        "Failed to parse the GBFS feed"
+    })
+}
+
+/// Downloads a feed of the configured GBFS system.
+///
+/// # Arguments
+///
+/// * `discovery` - The `gbfs.json` discovery file of the system.
+/// * `name` - The name of the feed.
+/// * `gbfs_url` - The URL of the discovery file.
+///
+/// # Returns
+///
+/// * The feed, or an error message if it is not listed or cannot be downloaded.
+async fn fetch_feed<T: DeserializeOwned>(
+    discovery: &GbfsDiscovery,
+    name: &str,
+    gbfs_url: &str,
+) -> Result<GbfsFile<T>, &'static str> {
+    let Some(url) = discovery.feed_url(name) else {
+        error!("❌ No {} feed in {}", name, gbfs_url);
+        return Err("Missing GBFS feed");
+    };
+    fetch_gbfs(url).await
+}
+
+/// Converts the stations of a `station_information` feed to database stations.
+///
+/// The stations whose ID is not numeric are skipped.
+///
+/// # Arguments
+///
+/// * `information` - The stations of the feed.
+/// * `regions` - The names of the regions by ID, the area of their stations.
+/// * `network` - The ID of the network of the stations.
+///
+/// # Returns
+///
+/// * The stations of the network.
+pub fn gbfs_stations(
+    information: &StationInformation,
+    regions: &HashMap<String, String>,
+    network: &Network,
+) -> Vec<DetailedStation> {
+    let stations: Vec<DetailedStation> = information
+        .stations
+        .iter()
+        .filter_map(|station| {
+            let area = station
+                .region_id
+                .as_ref()
+                .and_then(|id| regions.get(id))
+                .unwrap_or(&network.name);
+            Some(DetailedStation {
+                id: station.station_id.parse().ok()?,
+                name: station.name.text(),
+                latitude: station.lat,
+                longitude: station.lon,
+                adress: station.address.clone().unwrap_or_default(),
+                area: area.clone(),
+                capacity: station.capacity.unwrap_or(0) as i32,
+                cluster: None,
+                network: network.id.clone(),
+            })
+        })
+        .collect();
+    if stations.len() < information.stations.len() {
+        warn!(
+            "⚠️ {} stations without a numeric ID skipped",
+            information.stations.len() - stations.len()
+        );
+    }
+    stations
+}
+
+/// Converts the statuses of a `station_status` feed to Velov records, as stored in
+/// `velov_datas/`.
+///
+/// The stations whose ID is not numeric are skipped. The capacity of a station is the one of its
+/// `station_information`, or its available bikes and free docks if it is unknown.
+///
+/// # Arguments
+///
+/// * `status` - The statuses of the feed.
+/// * `capacities` - The capacities of the stations by ID.
+///
+/// # Returns
+///
+/// * The Velov records of the stations.
+pub fn gbfs_values(status: &StationStatus, capacities: &HashMap<String, u32>) -> Vec<Value> {
+    status
+        .stations
+        .iter()
+        .filter_map(|station| {
+            let bikes = station.num_bikes_available;
+            let stands = station.num_docks_available;
+            let capacity = capacities
+                .get(&station.station_id)
+                .copied()
+                .unwrap_or(bikes + stands);
+            let (mechanical, electrical) = match station.num_bikes_available_types {
+                Some(types) => (types.mechanical, types.ebike),
+                None => (bikes, 0),
+            };
+            let availabilities = Availabilities {
+                bikes: bikes as u16,
+                electrical_bikes: electrical as u16,
+                electrical_internal_battery_bikes: 0,
+                electrical_removable_battery_bikes: 0,
+                mechanical_bikes: mechanical as u16,
+                stands: stands as u16,
+            };
+            let status = if station.is_installed && station.is_renting {
+                "OPEN"
+            } else {
+                "CLOSED"
+            };
+            Some(Value {
+                horodate: station.last_reported.to_utc()?,
+                main_stands: MainStands {
+                    availabilities: availabilities.clone(),
+                    capacity: capacity as u16,
+                },
+                number: station.station_id.parse().ok()?,
+                overflow_stands: None,
+                status: status.to_string(),
+                total_stands: TotalStands {
+                    availabilities,
+                    capacity: capacity as u16,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Downloads the stations of the GBFS system of a network, from its `station_information` feed.
+///
+/// The area of a station is the name of its region if the system has a `system_regions` feed,
+/// otherwise the name of the network.
+///
+/// # Arguments
+///
+/// * `network` - The network of the stations, with its GBFS source.
+///
+/// # Errors
+///
+/// This function will return an error if the discovery file or the `station_information` feed
+/// cannot be downloaded or parsed.
+pub async fn download_gbfs_stations(
+    network: &Network,
+) -> Result<Vec<DetailedStation>, &'static str> {
+    let gbfs_url = &network.velov.gbfs_url;
+    let discovery: GbfsFile<GbfsDiscovery> = fetch_gbfs(gbfs_url).await?;
+    let information: GbfsFile<StationInformation> =
+        fetch_feed(&discovery.data, "station_information", gbfs_url).await?;
+
+    let mut regions = HashMap::new();
+    if discovery.data.feed_url("system_regions").is_some() {
+        let system_regions: GbfsFile<SystemRegions> =
+            fetch_feed(&discovery.data, "system_regions", gbfs_url).await?;
+        for region in system_regions.data.regions {
+            regions.insert(region.region_id, region.name.text());
+        }
+    }
+
+    let stations = gbfs_stations(&information.data, &regions, network);
+    info!("🚉 {} stations read from the GBFS feed", stations.len());
+    Ok(stations)
+}
+
+/// Collects the status of the stations of the GBFS system of a network, and writes it to
+/// `velov_datas/`, in the data directory of the network, as Velov records.
+///
+/// Each collection is written to its own file, named after the update date of the feed, so that
+/// the previous collections are kept. The stations whose status did not change since the
+/// previous collection are written again, and later deduped by `--filter-velov-data`. The
+/// collection is also recorded in the `observation` table, from which the API publishes its
+/// `station_status` feed.
+///
+/// # Arguments
+///
+/// * `network` - The network, with its GBFS source.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The discovery file or a feed cannot be downloaded or parsed.
+/// - The records cannot be written or recorded in the database.
+///
+/// # Examples
+///
+/// ```rust
+/// collect_gbfs(&network).await?;
+/// ```
+pub async fn collect_gbfs(network: &Network) -> Result<(), &'static str> {
+    info!("🚴‍♂️🚀 Collecting the GBFS station status...");
+    let gbfs_url = &network.velov.gbfs_url;
+    let discovery: GbfsFile<GbfsDiscovery> = fetch_gbfs(gbfs_url).await?;
+    let information: GbfsFile<StationInformation> =
+        fetch_feed(&discovery.data, "station_information", gbfs_url).await?;
+    let status: GbfsFile<StationStatus> =
+        fetch_feed(&discovery.data, "station_status", gbfs_url).await?;
+
+    let capacities: HashMap<String, u32> = information
+        .data
+        .stations
+        .iter()
+        .filter_map(|station| Some((station.station_id.clone(), station.capacity?)))
+        .collect();
+    let values = gbfs_values(&status.data, &capacities);
+    if values.is_empty() {
+        info!("⏭️ No station status in the GBFS feed");
+        return Ok(());
+    }
+
+    let updated = status.last_updated.to_utc().unwrap_or_else(Utc::now);
+    let dir = network.data_dir.join("velov_datas");
+    let path = dir.join(format!("gbfs-{}.json", updated.timestamp()));
+    if path.exists() {
+        info!(
+            "⏭️ GBFS station status already collected in {}",
+            path.display()
+        );
+        return Ok(());
+    }
+    let json = serde_json::to_string(&values).map_err(|e| {
+        error!("❌ Failed to serialize data to JSON: {}", e);
+        "Failed to serialize data to JSON"
+    })?;
+    std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(&path, json))
+        .map_err(|e| {
+            error!("❌ Failed to write data to file: {}", e);
+            "Failed to write data to file"
+        })?;
+    info!(
+        "✅ Status of {} stations written to {}",
+        values.len(),
+        path.display()
+    );
+    insert_observations(
+        &mut establish_connection(),
+        &network.id,
+        &values,
+        &mut HashSet::new(),
+    )
+    .map_err(|e| {
+        error!("❌ {}", e);
+        "Failed to record the observations"
+    })?;
+    Ok(())
+}
+
+/// Builds the `gbfs.json` discovery file published for a network.
+///
+/// # Arguments
+///
+/// * `base_url` - The URL the feeds of the network are published under.
+///
+/// # Returns
+///
+/// * The feeds published by the API, in the configured language.
+pub fn published_discovery(base_url: &str) -> GbfsFile<GbfsDiscovery> {
+    let feeds = std::iter::once("gbfs")
+        .chain(PUBLISHED_FEEDS)
+        .map(|name| GbfsFeed {
+            name: name.to_string(),
+            url: format!("{}/{}.json", base_url.trim_end_matches('/'), name),
+        })
+        .collect();
+    let languages = BTreeMap::from([(config().gbfs.language.clone(), GbfsFeeds { feeds })]);
+    GbfsFile::new(Utc::now(), GbfsDiscovery::Languages(languages))
+}
+
+/// Builds the `system_information` feed published for a network.
+pub fn published_system_information(network: &Network) -> GbfsFile<SystemInformation> {
+    GbfsFile::new(
+        Utc::now(),
+        SystemInformation {
+            system_id: network.id.clone(),
+            language: config().gbfs.language.clone(),
+            name: network.name.clone(),
+            timezone: network.timezone.name().to_string(),
+        },
+    )
+}
+
+/// Builds the `station_information` feed published for a network.
+///
+/// # Arguments
+///
+/// * `stations` - The stations of the network, from the `station` table.
+pub fn published_station_information(stations: &[DetailedStation]) -> GbfsFile<StationInformation> {
+    let stations = stations
+        .iter()
+        .map(|station| GbfsStation {
+            station_id: station.id.to_string(),
+            name: GbfsText::Plain(station.name.clone()),
+            lat: station.latitude,
+            lon: station.longitude,
+            address: Some(station.adress.clone()).filter(|a| !a.is_empty()),
+            region_id: None,
+            capacity: Some(station.capacity.max(0) as u32),
+        })
+        .collect();
+    GbfsFile::new(Utc::now(), StationInformation { stations })
+}
+
+/// Builds the `station_status` feed published for a network.
+///
+/// The stations without any recent observation are not listed. Every listed station is reported
+/// as installed, and as renting and returning unless its latest observation is `CLOSED`: the
+/// observations imported before the status was recorded are reported as open.
+///
+/// # Arguments
+///
+/// * `observations` - The latest observation of each station of the network.
+pub fn published_station_status(observations: &[Observation]) -> GbfsFile<StationStatus> {
+    let stations = observations
+        .iter()
+        .map(|observation| {
+            let is_open = observation.status.as_deref() != Some("CLOSED");
+            GbfsStationStatus {
+                station_id: observation.station_id.to_string(),
+                num_bikes_available: observation.bikes.max(0) as u32,
+                num_bikes_available_types: None,
+                num_docks_available: observation.stands.max(0) as u32,
+                is_installed: true,
+                is_renting: is_open,
+                is_returning: is_open,
+                last_reported: GbfsTimestamp::Posix(observation.timestamp.timestamp()),
+            }
+        })
+        .collect();
+    let last_updated = observations
+        .iter()
+        .map(|observation| observation.timestamp)
+        .max()
+        .unwrap_or_else(Utc::now);
+    GbfsFile::new(last_updated, StationStatus { stations })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// An extract of the GBFS 2.3 feeds of a system.
+    const STATION_INFORMATION: &str =
+        include_str!("../tests/fixtures/gbfs/station_information.json");
+    const STATION_STATUS: &str = include_str!("../tests/fixtures/gbfs/station_status.json");
+
+    #[test]
+    fn converts_the_station_information() {
+        let information: GbfsFile<StationInformation> =
+            serde_json::from_str(STATION_INFORMATION).unwrap();
+        let regions = HashMap::from([("69387".to_string(), "Lyon 7 ème".to_string())]);
+
+        let network = Config::default().network(None).unwrap();
+        let stations = gbfs_stations(&information.data, &regions, &network);
+        assert_eq!(stations.len(), 2);
+        assert_eq!(stations[0].id, 7055);
+        assert_eq!(stations[0].name, "7055 - JAURÈS / THIBAUDIÈRE");
+        assert_eq!(stations[0].area, "Lyon 7 ème");
+        assert_eq!(stations[0].capacity, 16);
+        assert_eq!(stations[1].area, "Vélo'v");
+        assert!(stations.iter().all(|s| s.network == "lyon"));
+    }
+
+    #[test]
+    fn converts_the_station_status() {
+        let status: GbfsFile<StationStatus> = serde_json::from_str(STATION_STATUS).unwrap();
+        let capacities = HashMap::from([("7055".to_string(), 16)]);
+
+        let values = gbfs_values(&status.data, &capacities);
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].number, 7055);
+        assert_eq!(values[0].total_stands.capacity, 16);
+        assert_eq!(values[0].total_stands.availabilities.bikes, 5);
+        assert_eq!(values[0].total_stands.availabilities.electrical_bikes, 2);
+        assert_eq!(values[0].horodate.timestamp(), 1711836240);
+        assert_eq!(values[1].total_stands.capacity, 20);
+        assert_eq!(values[1].status, "CLOSED");
+    }
+
+    #[test]
+    fn reads_the_gbfs_3_feeds() {
+        let json = r#"{
+            "last_updated": "2024-03-30T22:04:00+01:00",
+            "ttl": 60,
+            "version": "3.0",
+            "data": {"stations": [{
+                "station_id": "8052",
+                "num_vehicles_available": 3,
+                "num_docks_available": 13,
+                "is_installed": true,
+                "is_renting": true,
+                "is_returning": true,
+                "last_reported": "2024-03-30T22:03:00+01:00"
+            }]}
+        }"#;
+        let status: GbfsFile<StationStatus> = serde_json::from_str(json).unwrap();
+
+        let values = gbfs_values(&status.data, &HashMap::new());
+        assert_eq!(values[0].total_stands.availabilities.bikes, 3);
+        assert_eq!(values[0].total_stands.capacity, 16);
+        assert_eq!(values[0].horodate.to_rfc3339(), "2024-03-30T21:03:00+00:00");
+    }
+
+    #[test]
+    fn publishes_the_recorded_status() {
+        let observation = |station_id, status: Option<&str>| Observation {
+            station_id,
+            timestamp: "2024-03-30T21:03:00Z".parse().unwrap(),
+            capacity: 16,
+            bikes: 3,
+            stands: 13,
+            network: "lyon".to_string(),
+            status: status.map(str::to_string),
+        };
+        let observations = [
+            observation(7055, Some("OPEN")),
+            observation(7056, Some("CLOSED")),
+            observation(7057, None),
+        ];
+
+        let status = published_station_status(&observations).data.stations;
+        let renting: Vec<_> = status
+            .iter()
+            .map(|s| (s.is_renting, s.is_returning))
+            .collect();
+        assert_eq!(renting, [(true, true), (false, false), (true, true)]);
+        assert_eq!(status[1].num_bikes_available, 3);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::config::Network;
+use crate::downloader::Value;
+use crate::establish_connection;
+use crate::learning::{list_files, MergedData};
+use crate::models::{MergedFeature, Observation};
+use crate::schema::{merged_feature, observation};
+use crate::storage::read_station;
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use log::{error, info};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Number of raw Velov files read at once by `import_observations`.
+const IMPORT_BATCH_SIZE: usize = 64;
+/// Number of rows inserted by a single statement, below the 65535 parameters of Postgres.
+const INSERT_CHUNK_SIZE: usize = 4000;
+
+/// Imports the Velov history and the merged data into the database.
+///
+/// The raw snapshots of `velov_datas` are imported into the `observation` table, partitioned by
+/// month, and the merged data of `merged_data` into the `merged_feature` table. The import can be
+/// run again: the known observations are skipped and the merged data of each station is replaced.
+///
+/// # Arguments
+///
+/// * `network` - The network the rows belong to, whose data directory holds the data files.
+///
+/// # Returns
+///
+/// * An error message if the import failed.
+///
+/// # Examples
+///
+/// ```rust
+/// import_history(&network)?;
+/// ```
+pub fn import_history(network: &Network) -> Result<(), String> {
+    let mut connection = establish_connection();
+    let dir = network.data_dir.as_path();
+    import_observations(&mut connection, &network.id, &dir.join("velov_datas"))?;
+    import_merged_features(&mut connection, &network.id, &dir.join("merged_data"))?;
+    info!("✅ History imported!");
+    Ok(())
+}
+
+/// Imports the raw Velov snapshots into the `observation` table.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `network` - The ID of the network of the snapshots.
+/// * `path` - The directory of the raw Velov files.
+///
+/// # Returns
+///
+/// * An error message if a file could not be read or the insertion failed.
+fn import_observations(
+    connection: &mut PgConnection,
+    network: &str,
+    path: &Path,
+) -> Result<(), String> {
+    if !path.exists() {
+        info!(
+            "⏭️ No {} directory, skipping the observations",
+            path.display()
+        );
+        return Ok(());
+    }
+    let files = list_files(path);
+    let mut partitions = HashSet::new();
+    let mut imported = 0;
+
+    for (batch_index, batch) in files.chunks(IMPORT_BATCH_SIZE).enumerate() {
+        info!(
+            "📥 {}% Importing {} files",
+            ((batch_index * IMPORT_BATCH_SIZE) as f32 / files.len() as f32 * 10000.0).round()
+                / 100.0,
+            batch.len()
+        );
+
+        let values = batch
+            .par_iter()
+            .map(|file| {
+                let reader = BufReader::new(
+                    File::open(file).map_err(|e| format!("{}: {}", file.display(), e))?,
+                );
+                serde_json::from_reader::<_, Vec<Value>>(reader)
+                    .map_err(|e| format!("{}: {}", file.display(), e))
+            })
+            .collect::<Result<Vec<_>, String>>()?
+            .concat();
+        imported += insert_observations(connection, network, &values, &mut partitions)?;
+    }
+
+    info!("✅ {} new observations imported", imported);
+    Ok(())
+}
+
+/// Inserts raw Velov snapshots into the `observation` table, creating the partitions of their
+/// months. The known observations are skipped.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `network` - The ID of the network of the snapshots.
+/// * `values` - The raw Velov snapshots.
+/// * `partitions` - The months whose partition was already created by the caller.
+///
+/// # Returns
+///
+/// * The number of new observations, or an error message if the insertion failed.
+///
+/// # Examples
+///
+/// ```rust
+/// let inserted = insert_observations(&mut connection, "lyon", &values, &mut HashSet::new())?;
+/// ```
+pub fn insert_observations(
+    connection: &mut PgConnection,
+    network: &str,
+    values: &[Value],
+    partitions: &mut HashSet<NaiveDate>,
+) -> Result<usize, String> {
+    let observations: Vec<Observation> = values
+        .iter()
+        .map(|value| Observation {
+            station_id: value.number as i32,
+            timestamp: value.horodate,
+            capacity: value.total_stands.capacity as i32,
+            bikes: value.total_stands.availabilities.bikes as i32,
+            stands: value.total_stands.availabilities.stands as i32,
+            network: network.to_string(),
+            status: Some(value.status.clone()),
+        })
+        .collect();
+
+    for month in observations.iter().map(|o| month_start(o.timestamp)) {
+        if partitions.insert(month) {
+            create_observation_partition(connection, month)
+                .map_err(|e| format!("Failed to create the partition of {}: {}", month, e))?;
+        }
+    }
+
+    let mut inserted = 0;
+    for chunk in observations.chunks(INSERT_CHUNK_SIZE) {
+        inserted += diesel::insert_into(observation::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(connection)
+            .map_err(|e| format!("Failed to insert the observations: {}", e))?;
+    }
+    Ok(inserted)
+}
+
+/// Returns the first day of the month of a date.
+fn month_start(date: DateTime<Utc>) -> NaiveDate {
+    NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap()
+}
+
+/// Creates the partition of the `observation` table holding a month, if it does not exist.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `month` - The first day of the month.
+fn create_observation_partition(
+    connection: &mut PgConnection,
+    month: NaiveDate,
+) -> QueryResult<usize> {
+    diesel::sql_query(format!(
+        "CREATE TABLE IF NOT EXISTS \"observation_{}\" PARTITION OF \"observation\" \
+         FOR VALUES FROM ('{} 00:00:00+00') TO ('{} 00:00:00+00')",
+        month.format("%Y_%m"),
+        month,
+        month + Months::new(1)
+    ))
+    .execute(connection)
+}
+
+/// Imports the merged data into the `merged_feature` table, replacing the data of each station.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `network` - The ID of the network of the stations.
+/// * `path` - The directory of the merged data, in any storage format.
+///
+/// # Returns
+///
+/// * An error message if a file could not be read or the insertion failed.
+fn import_merged_features(
+    connection: &mut PgConnection,
+    network: &str,
+    path: &Path,
+) -> Result<(), String> {
+    if !path.exists() {
+        info!(
+            "⏭️ No {} directory, skipping the merged data",
+            path.display()
+        );
+        return Ok(());
+    }
+    let files = list_files(path);
+
+    for file in files {
+        let Some((id, data)) = read_station(&file)? else {
+            continue;
+        };
+        let features: Vec<MergedFeature> = data
+            .iter()
+            .map(|d| MergedFeature::new(network, d))
+            .collect();
+
+        connection
+            .transaction::<_, diesel::result::Error, _>(|connection| {
+                diesel::delete(
+                    merged_feature::table
+                        .filter(merged_feature::network.eq(network))
+                        .filter(merged_feature::station_id.eq(id as i32)),
+                )
+                .execute(connection)?;
+                for chunk in features.chunks(INSERT_CHUNK_SIZE) {
+                    diesel::insert_into(merged_feature::table)
+                        .values(chunk)
+                        .execute(connection)?;
+                }
+                Ok(())
+            })
+            .map_err(|e| format!("Failed to import the merged data of {}: {}", id, e))?;
+        info!(
+            "✅ {} merged rows imported for station {}",
+            features.len(),
+            id
+        );
+    }
+    Ok(())
+}
+
+/// Loads the merged data of a network from the `merged_feature` table, one station at a time.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `network` - The ID of the network.
+///
+/// # Returns
+///
+/// * A hashmap containing the merged data of each station, sorted by date. The stations whose
+///   data cannot be read are left out.
+///
+/// # Examples
+///
+/// ```rust
+/// let data = load_merged_data_from_db(&mut connection, "lyon");
+/// ```
+pub fn load_merged_data_from_db(
+    connection: &mut PgConnection,
+    network: &str,
+) -> HashMap<u32, Vec<MergedData>> {
+    info!("📖 Reading data of {} from the database..", network);
+    let stations: Vec<i32> = merged_feature::table
+        .filter(merged_feature::network.eq(network))
+        .select(merged_feature::station_id)
+        .distinct()
+        .load(connection)
+        .unwrap_or_else(|e| {
+            error!("❌ Failed to list the stations of the merged data: {}", e);
+            Vec::new()
+        });
+
+    let mut data = HashMap::new();
+    for id in stations {
+        let features: Vec<MergedFeature> = match merged_feature::table
+            .filter(merged_feature::network.eq(network))
+            .filter(merged_feature::station_id.eq(id))
+            .order(merged_feature::timestamp.asc())
+            .select(MergedFeature::as_select())
+            .load(connection)
+        {
+            Ok(features) => features,
+            Err(e) => {
+                // The station is left out, as the unreadable files of the file storage
+                error!("❌ Failed to read the merged data of {}: {}", id, e);
+                continue;
+            }
+        };
+        data.insert(
+            id as u32,
+            features.into_iter().map(MergedData::from).collect(),
+        );
+    }
+    info!("✅ Data read from the database!");
+    data
+}
@@ -0,0 +1,245 @@
+use crate::config::Network;
+use crate::http::get_text;
+use crate::learning::SchoolHolidays;
+use chrono::{DateTime, Days, FixedOffset};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::{error, info};
+
+/// File where the school holidays are stored.
+pub const SCHOOL_HOLIDAYS_FILE: &str = "school_holidays.json";
+/// Population of the calendar records only concerning the teachers.
+const TEACHERS_POPULATION: &str = "Enseignants";
+
+/// A record of the official French school calendar, as exported by the open data of the
+/// Ministry of Education.
+///
+/// # Fields
+///
+/// * `description` - The name of the period, for instance `Vacances de Noël`.
+/// * `population` - The population concerned: `-` for everyone, `Élèves` or `Enseignants`.
+/// * `start_date` - The beginning of the period, the local midnight after the last school day.
+/// * `end_date` - The end of the period, the local midnight before school resumes. Missing for
+///   single events like the start of the school year.
+/// * `zones` - The school zone of the record.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CalendarRecord {
+    pub description: String,
+    pub population: Option<String>,
+    pub start_date: DateTime<FixedOffset>,
+    pub end_date: Option<DateTime<FixedOffset>>,
+    pub zones: String,
+}
+
+impl CalendarRecord {
+    /// Converts the record to school holidays, if it is a holiday period for the pupils.
+    ///
+    /// # Arguments
+    ///
+    /// * `timezone` - The time zone of the network, in which the dates of the period are taken.
+    fn holidays(&self, timezone: Tz) -> Option<SchoolHolidays> {
+        if self.population.as_deref() == Some(TEACHERS_POPULATION) {
+            return None;
+        }
+        let start = self.start_date.with_timezone(&timezone).date_naive();
+        // School resumes on the day the period ends
+        let end = self.end_date?.with_timezone(&timezone).date_naive() - Days::new(1);
+        Some(SchoolHolidays {
+            start,
+            end: end.max(start),
+            zone: Some(self.zones.clone()),
+            description: Some(self.description.clone()),
+        })
+    }
+}
+
+/// Parses the official school calendar into school holidays.
+///
+/// The calendar has a record per academy: the holidays shared by the academies of a zone are
+/// only kept once.
+///
+/// # Arguments
+///
+/// * `json` - The JSON export of the school calendar.
+/// * `timezone` - The time zone of the network.
+///
+/// # Returns
+///
+/// * The school holidays of every zone, sorted by start date, or the parsing error.
+pub fn parse_school_calendar(
+    json: &str,
+    timezone: Tz,
+) -> Result<Vec<SchoolHolidays>, serde_json::Error> {
+    let records: Vec<CalendarRecord> = serde_json::from_str(json)?;
+
+    let mut holidays: Vec<SchoolHolidays> = records
+        .iter()
+        .filter_map(|r| r.holidays(timezone))
+        .collect();
+    holidays.sort_by(|a, b| (a.start, &a.zone, a.end).cmp(&(b.start, &b.zone, b.end)));
+    holidays.dedup_by(|a, b| (a.start, &a.zone, a.end) == (b.start, &b.zone, b.end));
+    Ok(holidays)
+}
+
+/// Keeps the school holidays of a zone.
+///
+/// # Arguments
+///
+/// * `holidays` - The school holidays.
+/// * `zone` - The school zone.
+///
+/// # Returns
+///
+/// * The holidays of the zone, along with the holidays without zone which apply to every zone.
+pub fn holidays_of_zone(holidays: Vec<SchoolHolidays>, zone: &str) -> Vec<SchoolHolidays> {
+    holidays
+        .into_iter()
+        .filter(|h| h.zone.as_deref().is_none_or(|z| z == zone))
+        .collect()
+}
+
+/// Reads the school holidays of a zone from `school_holidays.json`.
+///
+/// # Arguments
+///
+/// * `dir` - The data directory of the network.
+/// * `zone` - The school zone of the network.
+///
+/// # Returns
+///
+/// * The school holidays of the zone.
+///
+/// # Panics
+///
+/// Panics if the file is missing or invalid.
+pub fn read_school_holidays(dir: &Path, zone: &str) -> Vec<SchoolHolidays> {
+    let holidays: Vec<SchoolHolidays> =
+        serde_json::from_str(&std::fs::read_to_string(dir.join(SCHOOL_HOLIDAYS_FILE)).unwrap())
+            .unwrap();
+    holidays_of_zone(holidays, zone)
+}
+
+/// Downloads the official school calendar and writes the school holidays of every zone to
+/// `school_holidays.json`, in the data directory of the network.
+///
+/// # Arguments
+///
+/// * `network` - The network, with its holidays source.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The HTTP request fails, after the retries of the shared client.
+/// - The JSON response cannot be parsed.
+/// - The file cannot be written.
+///
+/// # Examples
+///
+/// ```rust
+/// import_school_holidays(&network).await?;
+/// ```
+pub async fn import_school_holidays(network: &Network) -> Result<(), &'static str> {
+    info!("🏫🚀 Downloading the school calendar...");
+    let response = match get_text(&network.holidays.calendar_url).await {
+        Ok(text) => text,
+        Err(e) => {
+            error!("❌ Failed to download the school calendar: {}", e);
+            return Err("Failed to download the school calendar");
+        }
+    };
+
+    let holidays = match parse_school_calendar(&response, network.timezone) {
+        Ok(holidays) => holidays,
+        Err(e) => {
+            error!("❌ Failed to parse the school calendar: {}", e);
+            return Err("Failed to parse the school calendar");
+        }
+    };
+    let mut zones: BTreeMap<&str, usize> = BTreeMap::new();
+    for zone in holidays.iter().filter_map(|h| h.zone.as_deref()) {
+        *zones.entry(zone).or_default() += 1;
+    }
+    info!("📅 Holidays per zone: {:?}", zones);
+    if !zones.contains_key(network.holidays.zone.as_str()) {
+        error!(
+            "❌ No holidays for the configured zone {}",
+            network.holidays.zone
+        );
+        return Err("No holidays for the configured zone");
+    }
+
+    let json = match serde_json::to_string(&holidays) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("❌ Failed to serialize the school holidays: {}", e);
+            return Err("Failed to serialize the school holidays");
+        }
+    };
+    let path = network.data_dir.join(SCHOOL_HOLIDAYS_FILE);
+    if let Err(e) = std::fs::write(&path, json) {
+        error!("❌ Failed to write the school holidays: {}", e);
+        return Err("Failed to write the school holidays");
+    }
+    info!("✅ School holidays written to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    /// An extract of the official school calendar.
+    const CALENDAR: &str = include_str!("../tests/fixtures/calendrier_scolaire.json");
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn parses_the_holidays_of_a_zone() {
+        let holidays = holidays_of_zone(
+            parse_school_calendar(CALENDAR, chrono_tz::Europe::Paris).unwrap(),
+            "Zone A",
+        );
+
+        let periods: Vec<(NaiveDate, NaiveDate, &str)> = holidays
+            .iter()
+            .map(|h| (h.start, h.end, h.description.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            periods,
+            vec![
+                (
+                    day(2023, 10, 21),
+                    day(2023, 11, 5),
+                    "Vacances de la Toussaint"
+                ),
+                (day(2023, 12, 23), day(2024, 1, 7), "Vacances de Noël"),
+                (day(2024, 2, 10), day(2024, 2, 25), "Vacances d'Hiver"),
+                (day(2024, 5, 9), day(2024, 5, 12), "Pont de l'Ascension"),
+                (day(2024, 7, 6), day(2024, 9, 1), "Vacances d'Été"),
+            ]
+        );
+        assert!(holidays.iter().all(|h| h.zone.as_deref() == Some("Zone A")));
+    }
+
+    #[test]
+    fn keeps_the_holidays_without_zone() {
+        let mut holidays = parse_school_calendar(CALENDAR, chrono_tz::Europe::Paris).unwrap();
+        holidays.push(SchoolHolidays {
+            start: day(2024, 4, 6),
+            end: day(2024, 4, 21),
+            zone: None,
+            description: None,
+        });
+
+        let zone_c = holidays_of_zone(holidays, "Zone C");
+        assert_eq!(zone_c.len(), 3);
+        assert!(zone_c.iter().any(|h| h.zone.is_none()));
+        assert!(zone_c.iter().any(|h| h.contains(day(2024, 3, 1))));
+        assert!(!zone_c.iter().any(|h| h.contains(day(2024, 2, 15))));
+    }
+}
@@ -0,0 +1,716 @@
+use reqwest::header::{
+    HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// The shared HTTP client, initialised by `init_http_client`.
+static HTTP_CLIENT: OnceLock<HttpClient> = OnceLock::new();
+/// File of the fixtures directory mapping each fixture to its URL.
+const FIXTURES_INDEX: &str = "index.json";
+
+/// How the upstream sources are reached.
+///
+/// # Variants
+///
+/// * `Live` - The requests are sent to the sources.
+/// * `Record` - The requests are sent to the sources, and the responses saved as fixtures.
+/// * `Replay` - The responses are read from the fixtures, without any network access.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HttpMode {
+    Live,
+    Record,
+    Replay,
+}
+
+/// Error of a request to an upstream source.
+///
+/// # Variants
+///
+/// * `Request` - The request failed, after the retries.
+/// * `MissingFixture` - No fixture was recorded for the URL.
+/// * `Fixture` - The fixture could not be read or written.
+/// * `NotModified` - The source answered `304 Not Modified` but the response is no longer cached.
+#[derive(Debug)]
+pub enum HttpError {
+    Request(reqwest::Error),
+    MissingFixture(String),
+    Fixture(std::io::Error),
+    NotModified(String),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Request(e) => write!(f, "{}", e),
+            HttpError::MissingFixture(url) => write!(f, "no fixture recorded for {}", url),
+            HttpError::Fixture(e) => write!(f, "invalid fixture: {}", e),
+            HttpError::NotModified(url) => {
+                write!(f, "{} is not modified but no response is cached", url)
+            }
+        }
+    }
+}
+
+/// Settings of the shared HTTP client.
+///
+/// # Fields
+///
+/// * `timeout` - The timeout of a single request.
+/// * `max_retries` - The number of times a failed request is retried.
+/// * `initial_backoff` - The delay before the first retry, doubled at each retry.
+/// * `max_backoff` - The maximum delay between two retries.
+/// * `requests_per_second` - The maximum number of requests sent per second.
+/// * `user_agent` - The user agent sent with every request.
+/// * `mode` - Whether the sources are reached live, recorded or replayed.
+/// * `fixtures_dir` - The directory of the recorded responses.
+/// * `cache_capacity` - The maximum total size of the bodies kept for conditional requests, in
+///   bytes.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub timeout: Duration,
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub requests_per_second: f64,
+    pub user_agent: String,
+    pub mode: HttpMode,
+    pub fixtures_dir: PathBuf,
+    pub cache_capacity: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout: Duration::from_secs(60),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            requests_per_second: 5.0,
+            user_agent: format!(
+                "{}/{} (+https://github.com/undermirrors/VELOVITE)",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ),
+            mode: HttpMode::Live,
+            fixtures_dir: PathBuf::from("fixtures"),
+            cache_capacity: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// A response kept to answer conditional requests.
+///
+/// # Fields
+///
+/// * `etag` - The `ETag` header of the response.
+/// * `last_modified` - The `Last-Modified` header of the response.
+/// * `body` - The body of the response.
+/// * `last_used` - The tick of the cache at which the response was last used.
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+    last_used: u64,
+}
+
+/// Responses kept to answer conditional requests, bounded by the total size of their bodies.
+///
+/// When a new response does not fit, the least recently used ones are evicted. A body larger
+/// than the whole capacity, such as a page of the Velov history, is never cached.
+///
+/// # Fields
+///
+/// * `entries` - The cached responses, by URL.
+/// * `size` - The total size of the cached bodies, in bytes.
+/// * `capacity` - The maximum total size of the cached bodies, in bytes.
+/// * `tick` - A counter increased at each use of the cache, ordering the uses.
+struct ResponseCache {
+    entries: HashMap<String, CachedResponse>,
+    size: usize,
+    capacity: usize,
+    tick: u64,
+}
+
+impl ResponseCache {
+    /// Creates an empty cache of the given capacity, in bytes.
+    fn new(capacity: usize) -> ResponseCache {
+        ResponseCache {
+            entries: HashMap::new(),
+            size: 0,
+            capacity,
+            tick: 0,
+        }
+    }
+
+    /// Returns the cached response of a URL, marking it as recently used.
+    fn get(&mut self, url: &str) -> Option<CachedResponse> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(url)?;
+        entry.last_used = self.tick;
+        Some(entry.clone())
+    }
+
+    /// Caches the response of a URL, evicting the least recently used responses to make room.
+    fn insert(
+        &mut self,
+        url: &str,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        body: String,
+    ) {
+        self.remove(url);
+        if body.len() > self.capacity {
+            return;
+        }
+        while self.size + body.len() > self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(url, _)| url.clone())
+                .unwrap();
+            self.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.size += body.len();
+        self.entries.insert(
+            url.to_string(),
+            CachedResponse {
+                etag,
+                last_modified,
+                body,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    /// Removes the cached response of a URL, if any.
+    fn remove(&mut self, url: &str) {
+        if let Some(entry) = self.entries.remove(url) {
+            self.size -= entry.body.len();
+        }
+    }
+}
+
+/// HTTP client shared by all the upstream sources.
+///
+/// Requests are rate limited, retried with an exponential backoff on network errors, timeouts,
+/// `429` and `5xx` responses, and revalidated with `If-None-Match` / `If-Modified-Since` when a
+/// previous response carried an `ETag` or a `Last-Modified` header and is still in the bounded
+/// response cache.
+///
+/// In record mode, the responses are also saved in the fixtures directory, one file per URL. In
+/// replay mode, they are read back from it and no request is sent.
+pub struct HttpClient {
+    client: reqwest::Client,
+    config: HttpConfig,
+    next_slot: tokio::sync::Mutex<Instant>,
+    cache: Mutex<ResponseCache>,
+    index: Mutex<()>,
+}
+
+impl HttpClient {
+    /// Creates a client with the given settings.
+    pub fn new(config: HttpConfig) -> HttpClient {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .user_agent(config.user_agent.clone())
+            .build()
+            .unwrap();
+        HttpClient {
+            client,
+            next_slot: tokio::sync::Mutex::new(Instant::now()),
+            cache: Mutex::new(ResponseCache::new(config.cache_capacity)),
+            index: Mutex::new(()),
+            config,
+        }
+    }
+
+    /// Fetches the body of a URL, from the source or the fixtures depending on the mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to fetch.
+    ///
+    /// # Returns
+    ///
+    /// * The body of the response, or the error of the request or of the fixture.
+    pub async fn fetch(&self, url: &str) -> Result<String, HttpError> {
+        match self.config.mode {
+            HttpMode::Live => self.get_text(url).await,
+            HttpMode::Record => {
+                let body = self.get_text(url).await?;
+                self.record(url, &body).map_err(HttpError::Fixture)?;
+                Ok(body)
+            }
+            HttpMode::Replay => {
+                let path = self.fixture_path(url);
+                if !path.exists() {
+                    return Err(HttpError::MissingFixture(url.to_string()));
+                }
+                info!("📼 Replaying {}", url);
+                fs::read_to_string(path).map_err(HttpError::Fixture)
+            }
+        }
+    }
+
+    /// Path of the fixture of a URL: the host followed by a hash of the whole URL.
+    fn fixture_path(&self, url: &str) -> PathBuf {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| "unknown".to_string());
+        let hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+        self.config
+            .fixtures_dir
+            .join(format!("{}-{}.json", host, &hash[..16]))
+    }
+
+    /// Saves a response as the fixture of its URL, and records the URL in the index.
+    fn record(&self, url: &str, body: &str) -> std::io::Result<()> {
+        let _index = self.index.lock().unwrap();
+        fs::create_dir_all(&self.config.fixtures_dir)?;
+        let path = self.fixture_path(url);
+        fs::write(&path, body)?;
+
+        let index_path = self.config.fixtures_dir.join(FIXTURES_INDEX);
+        let mut index: BTreeMap<String, String> = File::open(&index_path)
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default();
+        index.insert(
+            path.file_name().unwrap().to_string_lossy().to_string(),
+            url.to_string(),
+        );
+        serde_json::to_writer_pretty(BufWriter::new(File::create(index_path)?), &index)?;
+        info!("📼 Recorded {} to {}", url, path.display());
+        Ok(())
+    }
+
+    /// Fetches the body of a URL.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to fetch.
+    ///
+    /// # Returns
+    ///
+    /// * The body of the response, or the error of the last attempt.
+    pub async fn get_text(&self, url: &str) -> Result<String, HttpError> {
+        let mut attempt = 0;
+        loop {
+            let (error, retry_after) = match self.try_get_text(url).await {
+                Ok(body) => return Ok(body),
+                Err(failure) => failure,
+            };
+            if attempt >= self.config.max_retries || !is_retryable(&error) {
+                return Err(error);
+            }
+
+            let delay = retry_after.unwrap_or_else(|| self.backoff(attempt));
+            warn!(
+                "🔁 Request to {} failed ({}), retrying in {:?}",
+                url, error, delay
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Sends a single request, without retrying.
+    ///
+    /// # Returns
+    ///
+    /// * The body of the response, or the error along with the delay asked by a `Retry-After`
+    ///   header, if any.
+    async fn try_get_text(&self, url: &str) -> Result<String, (HttpError, Option<Duration>)> {
+        self.wait_for_slot().await;
+
+        let cached = self.cache.lock().unwrap().get(url);
+        let mut request = self.client.get(url);
+        if let Some(cached) = cached.as_ref() {
+            if let Some(etag) = cached.etag.as_ref() {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = cached.last_modified.as_ref() {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| (HttpError::Request(e), None))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return match cached {
+                Some(cached) => Ok(cached.body),
+                None => Err((HttpError::NotModified(url.to_string()), None)),
+            };
+        }
+
+        let retry_after = retry_after(response.headers());
+        let response = response
+            .error_for_status()
+            .map_err(|e| (HttpError::Request(e), retry_after))?;
+        let etag = header_value(response.headers(), ETAG);
+        let last_modified = header_value(response.headers(), LAST_MODIFIED);
+        let body = response
+            .text()
+            .await
+            .map_err(|e| (HttpError::Request(e), None))?;
+
+        if etag.is_some() || last_modified.is_some() {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(url, etag, last_modified, body.clone());
+        }
+        Ok(body)
+    }
+
+    /// Waits until a request can be sent without exceeding `requests_per_second`.
+    async fn wait_for_slot(&self) {
+        if self.config.requests_per_second <= 0.0 {
+            return;
+        }
+        let interval = Duration::from_secs_f64(1.0 / self.config.requests_per_second);
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Delay before a retry: an exponential backoff with equal jitter.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of failed attempts so far, minus one.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .config
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.config.max_backoff);
+        delay / 2 + delay.mul_f64(rand::random::<f64>() / 2.0)
+    }
+}
+
+/// Whether a failed request is worth retrying.
+fn is_retryable(error: &HttpError) -> bool {
+    let HttpError::Request(error) = error else {
+        return false;
+    };
+    match error.status() {
+        Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        None => error.is_timeout() || error.is_connect() || error.is_request(),
+    }
+}
+
+/// Reads a header as a string.
+fn header_value(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    headers.get(name)?.to_str().ok().map(str::to_string)
+}
+
+/// Reads the delay asked by a `Retry-After` header, in seconds.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    header_value(headers, RETRY_AFTER)?
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Initialises the shared HTTP client.
+///
+/// This must be called before the first request, otherwise the default settings are used.
+///
+/// # Arguments
+///
+/// * `config` - The settings of the client.
+pub fn init_http_client(config: HttpConfig) {
+    if HTTP_CLIENT.set(HttpClient::new(config)).is_err() {
+        warn!("❌ The HTTP client is already initialised");
+    }
+}
+
+/// Fetches the body of a URL with the shared HTTP client, live or from the fixtures depending
+/// on its mode.
+///
+/// # Arguments
+///
+/// * `url` - The URL to fetch.
+///
+/// # Returns
+///
+/// * The body of the response, or the error of the last attempt or of the fixture.
+///
+/// # Examples
+///
+/// ```rust
+/// let body = get_text("https://api.open-meteo.com/v1/forecast").await?;
+/// ```
+pub async fn get_text(url: &str) -> Result<String, HttpError> {
+    HTTP_CLIENT
+        .get_or_init(|| HttpClient::new(HttpConfig::default()))
+        .fetch(url)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::HeaderMap as AxumHeaderMap;
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::get;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Serves a router on a random local port.
+    ///
+    /// # Returns
+    ///
+    /// * The base URL of the server.
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{}", address)
+    }
+
+    /// A client retrying quickly, without rate limit.
+    fn test_client(max_retries: u32) -> HttpClient {
+        HttpClient::new(HttpConfig {
+            timeout: Duration::from_secs(5),
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+            requests_per_second: 0.0,
+            ..HttpConfig::default()
+        })
+    }
+
+    /// Unwraps the error of a failed request.
+    fn request_error(result: Result<String, HttpError>) -> reqwest::Error {
+        match result {
+            Err(HttpError::Request(error)) => error,
+            other => panic!("expected a request error, got {:?}", other),
+        }
+    }
+
+    /// A server answering each request with the next status of `statuses`, then `200 OK`.
+    async fn flaky_server(statuses: Vec<StatusCode>) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router =
+            Router::new()
+                .route(
+                    "/",
+                    get(
+                        |State((calls, statuses)): State<(
+                            Arc<AtomicUsize>,
+                            Arc<Vec<StatusCode>>,
+                        )>| async move {
+                            let call = calls.fetch_add(1, Ordering::SeqCst);
+                            match statuses.get(call) {
+                                Some(status) => (*status, "error").into_response(),
+                                None => (StatusCode::OK, "ok").into_response(),
+                            }
+                        },
+                    ),
+                )
+                .with_state((calls.clone(), Arc::new(statuses)));
+        (serve(router).await, calls)
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_until_success() {
+        let (url, calls) = flaky_server(vec![
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::TOO_MANY_REQUESTS,
+        ])
+        .await;
+
+        let body = test_client(3).get_text(&url).await.unwrap();
+
+        assert_eq!(body, "ok");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (url, calls) = flaky_server(vec![StatusCode::INTERNAL_SERVER_ERROR; 10]).await;
+
+        let error = request_error(test_client(2).get_text(&url).await);
+
+        assert_eq!(error.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (url, calls) = flaky_server(vec![StatusCode::NOT_FOUND]).await;
+
+        let error = request_error(test_client(3).get_text(&url).await);
+
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_connection_errors() {
+        // Bind then drop a listener to get a port nobody listens on
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let start = Instant::now();
+        let error = request_error(test_client(2).get_text(&url).await);
+
+        assert!(error.is_connect());
+        // Two retries, each waiting at least half of its backoff
+        assert!(start.elapsed() >= Duration::from_micros(1500));
+    }
+
+    #[tokio::test]
+    async fn revalidates_with_etag() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router = Router::new()
+            .route(
+                "/",
+                get(
+                    |State(calls): State<Arc<AtomicUsize>>, headers: AxumHeaderMap| async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        let response: Response =
+                            if headers.get("if-none-match").is_some_and(|e| e == "\"v1\"") {
+                                StatusCode::NOT_MODIFIED.into_response()
+                            } else {
+                                ([("etag", "\"v1\"")], "content").into_response()
+                            };
+                        response
+                    },
+                ),
+            )
+            .with_state(calls.clone());
+        let url = serve(router).await;
+        let client = test_client(0);
+
+        assert_eq!(client.get_text(&url).await.unwrap(), "content");
+        assert_eq!(client.get_text(&url).await.unwrap(), "content");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_not_modified_without_cached_response() {
+        let router = Router::new().route("/", get(|| async { StatusCode::NOT_MODIFIED }));
+        let url = serve(router).await;
+
+        let result = test_client(0).get_text(&url).await;
+
+        assert!(matches!(result, Err(HttpError::NotModified(_))));
+    }
+
+    #[test]
+    fn cache_evicts_the_least_recently_used_responses() {
+        let mut cache = ResponseCache::new(10);
+        let etag = || Some("\"v1\"".to_string());
+
+        cache.insert("a", etag(), None, "aaaa".to_string());
+        cache.insert("b", etag(), None, "bbbb".to_string());
+        assert!(cache.get("a").is_some());
+        cache.insert("c", etag(), None, "cccc".to_string());
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.size, 8);
+
+        cache.insert("d", etag(), None, "d".repeat(11));
+        assert!(cache.get("d").is_none());
+        cache.insert("a", etag(), None, "a".repeat(6));
+        assert_eq!(cache.get("a").unwrap().body.len(), 6);
+        assert_eq!(cache.size, 10);
+    }
+
+    #[tokio::test]
+    async fn limits_the_request_rate() {
+        let (url, calls) = flaky_server(Vec::new()).await;
+        let client = HttpClient::new(HttpConfig {
+            requests_per_second: 20.0,
+            ..HttpConfig::default()
+        });
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            client.get_text(&url).await.unwrap();
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_with_jitter() {
+        let client = HttpClient::new(HttpConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            ..HttpConfig::default()
+        });
+
+        for attempt in 0..6 {
+            let delay =
+                (Duration::from_millis(100) * 2u32.pow(attempt)).min(Duration::from_millis(1000));
+            let backoff = client.backoff(attempt);
+            assert!(backoff >= delay / 2 && backoff <= delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn records_then_replays_without_network() {
+        let (url, calls) = flaky_server(vec![]).await;
+        let fixtures_dir = std::env::temp_dir().join(format!(
+            "velovite-fixtures-{}-{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let client = |mode| {
+            HttpClient::new(HttpConfig {
+                requests_per_second: 0.0,
+                mode,
+                fixtures_dir: fixtures_dir.clone(),
+                ..HttpConfig::default()
+            })
+        };
+
+        assert_eq!(client(HttpMode::Record).fetch(&url).await.unwrap(), "ok");
+        assert_eq!(client(HttpMode::Replay).fetch(&url).await.unwrap(), "ok");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            client(HttpMode::Replay)
+                .fetch(&format!("{}/other", url))
+                .await,
+            Err(HttpError::MissingFixture(_))
+        ));
+
+        fs::remove_dir_all(fixtures_dir).unwrap();
+    }
+}
@@ -1,25 +1,67 @@
-use crate::downloader::{Value, WeatherData};
+use crate::anomaly::{read_anomalies, Anomaly};
+use crate::config::{config, Network};
+use crate::dedup::{dedup_station, write_dedup_report, DedupPolicy, RankedSnapshot, StationDedup};
+use crate::downloader::{nearest_cell, read_weather, HourlyWeather, Value};
+use crate::holidays::read_school_holidays;
 use crate::models::DetailedStation;
-use crate::utils::distance;
+use crate::resample::{
+    resample_station, write_coverage, StationCoverage, MAX_INTERPOLATED_GAP_MINUTES,
+};
+use crate::storage::{read_station, write_station};
+use crate::utils::{k_nearest, local_time, pinball_loss, Quantiles, NEIGHBOURS};
 use crate::{establish_connection, schema};
-use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+use chrono_tz::Tz;
+use diesel::pg::PgConnection;
 use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
-use log::{info, warn};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use rayon::prelude::ParallelBridge;
+use log::{error, info, warn};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::sync::{Arc, Mutex};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The first and last dates of a dataset.
+pub type DateRange = (DateTime<Utc>, DateTime<Utc>);
+
+/// Directory where the filtered Velov data is written, one file per station.
+pub const VELOV_TRAINING_DATA_DIR: &str = "velov_training_data";
+/// Temporary directory where the compliant Velov snapshots are partitioned by station.
+const VELOV_PARTITIONS_DIR: &str = "velov_partitions";
+/// What was dropped from a station, its number of compliant snapshots, and its coverage and
+/// first and last dates if it could be resampled.
+type FilteredStation = (
+    StationDedup,
+    u32,
+    Option<(StationCoverage, DateTime<Utc>, DateTime<Utc>)>,
+);
+/// Number of raw Velov files read at once by `filter_velov_data`.
+const FILTER_BATCH_SIZE: usize = 64;
 
 const BENCHMARK_PERCENTAGE: f32 = 2.0;
+/// The absolute error and the pinball losses of each scored point of a station, and its number
+/// of points without prediction.
+type StationBenchmark = (Vec<(u32, [f32; 3])>, usize);
+/// Quantile levels evaluated with the pinball loss by the benchmark.
+const BENCHMARK_QUANTILES: [f32; 3] = [0.1, 0.5, 0.9];
 
-pub fn benchmark() {
+/// Benchmarks the model by predicting the last `BENCHMARK_PERCENTAGE`% of the data of each
+/// station from the rest of its data.
+///
+/// The absolute error of the predicted bikes and the pinball loss of the predicted P10, P50 and
+/// P90 are reported as percentages of the station capacity, and written to
+/// `benchmark_results.csv`. The points without any neighbour of known occupancy have no
+/// prediction: they are not scored, and counted separately.
+///
+/// # Arguments
+///
+/// * `network` - The network, whose data directory holds the merged data and the results.
+pub fn benchmark(network: &Network) {
     let mut connection = establish_connection();
 
-    let mut data = read_merged_data_from_file("merged_data");
+    let mut data = read_merged_data_from_file(&network.data_dir.join("merged_data"));
 
     info!(
         "🗑️ Removing {}% of the data of each station..",
@@ -43,11 +85,11 @@ pub fn benchmark() {
     info!("📊 Removed data length :{}", len_removed);
 
     info!("📊 Benchmarking..");
-    let result: HashMap<u32, Vec<u32>> = data
+    let result: HashMap<u32, StationBenchmark> = data
         .par_iter()
         .map(|(key, value)| {
             info!("🔍 Benchmarking station {}..", key);
-            let station_result: Vec<u32> = removed_data
+            let station_result: Vec<Option<(u32, [f32; 3])>> = removed_data
                 .get(key)
                 .unwrap()
                 .par_iter()
@@ -65,24 +107,36 @@ pub fn benchmark() {
                         precipitation: wanted.precipitation,
                         temperature: wanted.temperature,
                         wind_speed: wanted.wind_speed,
+                        cluster: wanted.cluster,
+                        capacity: wanted.capacity,
+                        timestamp: wanted.timestamp,
                     };
 
-                    let nearest_data = value
-                        .par_iter()
-                        .min_by(|a, b| {
-                            distance(a, &wanted_point)
-                                .partial_cmp(&distance(b, &wanted_point))
-                                .unwrap()
-                        })
-                        .unwrap();
-
-                    nearest_data
-                        .available_bikes
-                        .abs_diff(wanted_point.available_bikes)
+                    // Predictions are rescaled to the capacity at the time of the removed point
+                    let capacity = wanted_point.size();
+                    let nearest_data: Vec<u32> = k_nearest(value, &wanted_point, NEIGHBOURS)
+                        .iter()
+                        .filter_map(|d| d.rescaled_bikes(capacity))
+                        .collect();
+                    let nearest = *nearest_data.first()?;
+                    let quantiles = Quantiles::from_values(&nearest_data);
+                    let actual = wanted_point.available_bikes as f32;
+                    let predicted = [quantiles.p10, quantiles.p50, quantiles.p90];
+
+                    Some((
+                        nearest.abs_diff(wanted_point.available_bikes),
+                        std::array::from_fn(|i| {
+                            pinball_loss(BENCHMARK_QUANTILES[i], actual, predicted[i] as f32)
+                        }),
+                    ))
                 })
                 .collect();
+            let skipped = station_result.iter().filter(|r| r.is_none()).count();
 
-            (*key, station_result)
+            (
+                *key,
+                (station_result.into_iter().flatten().collect(), skipped),
+            )
         })
         .collect();
     info!("✅ Benchmark done!");
@@ -93,15 +147,30 @@ pub fn benchmark() {
     let mut total = 0.0;
     let mut total_len = 0;
     let mut all_distances: Vec<f32> = Vec::new();
+    let mut total_pinball = [0.0; 3];
+    let mut total_skipped = 0;
 
-    let mut wtr = csv::Writer::from_path("benchmark_results.csv").unwrap();
-    wtr.write_record(["Station ID", "Average", "Median", "Min", "Max"])
-        .unwrap();
+    let mut wtr = csv::Writer::from_path(network.data_dir.join("benchmark_results.csv")).unwrap();
+    wtr.write_record([
+        "Station ID",
+        "Average",
+        "Median",
+        "Min",
+        "Max",
+        "Pinball P10",
+        "Pinball P50",
+        "Pinball P90",
+        "Skipped",
+    ])
+    .unwrap();
 
-    for (key, value) in result.iter() {
+    for (key, (results, skipped)) in result.iter() {
+        total_skipped += skipped;
+        let value: Vec<u32> = results.iter().map(|(error, _)| *error).collect();
         use schema::station::dsl::station;
         let station_details = match station
             .select(DetailedStation::as_select())
+            .filter(schema::station::network.eq(&network.id))
             .filter(schema::station::id.eq(*key as i32))
             .limit(1)
             .first::<DetailedStation>(&mut connection)
@@ -119,11 +188,17 @@ pub fn benchmark() {
         let average =
             value.iter().sum::<u32>() as f32 / value.len() as f32 / station_details.capacity as f32
                 * 100.0;
+        let pinball: [f32; 3] = std::array::from_fn(|i| {
+            results.iter().map(|(_, losses)| losses[i]).sum::<f32>()
+                / results.len() as f32
+                / station_details.capacity as f32
+                * 100.0
+        });
         let median = {
             let mut sorted = value.clone();
             sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
             let len = sorted.len();
-            if len % 2 == 0 {
+            if len.is_multiple_of(2) {
                 (sorted[len / 2] + sorted[len / 2 - 1]) as f32 / 2.0
             } else {
                 sorted[len / 2] as f32
@@ -144,8 +219,8 @@ pub fn benchmark() {
             * 100.0;
 
         info!(
-            "🆔 {} : Average : {} Median : {} Min : {} Max : {}",
-            key, average, median, min, max
+            "🆔 {} : Average : {} Median : {} Min : {} Max : {} Pinball : {:?} Skipped : {}",
+            key, average, median, min, max, pinball, skipped
         );
 
         wtr.write_record(&[
@@ -154,10 +229,17 @@ pub fn benchmark() {
             median.to_string(),
             min.to_string(),
             max.to_string(),
+            pinball[0].to_string(),
+            pinball[1].to_string(),
+            pinball[2].to_string(),
+            skipped.to_string(),
         ])
         .unwrap();
 
         total += average * value.len() as f32;
+        for (total, loss) in total_pinball.iter_mut().zip(pinball.iter()) {
+            *total += loss * value.len() as f32;
+        }
         total_len += value.len();
         all_distances.extend(
             value
@@ -172,7 +254,7 @@ pub fn benchmark() {
     let main_median = {
         all_distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Less));
         let len = all_distances.len();
-        if len % 2 == 0 {
+        if len.is_multiple_of(2) {
             (all_distances[len / 2] + all_distances[len / 2 - 1]) / 2.0
         } else {
             all_distances[len / 2]
@@ -191,12 +273,30 @@ pub fn benchmark() {
     info!("📊 Median of all stations : {}", main_median);
     info!("📊 Min of all stations : {}", main_min);
     info!("📊 Max of all stations : {}", main_max);
+    info!("⏭️ Points without prediction : {}", total_skipped);
+    for (q, loss) in BENCHMARK_QUANTILES.iter().zip(total_pinball.iter()) {
+        info!(
+            "📊 Pinball loss P{} of all stations : {}",
+            q * 100.0,
+            loss / total_len as f32
+        );
+    }
 }
 /// Merges the Velov, weather, and school holidays data into a single dataset. and writes it to a file.
 ///
+/// The stations are merged one at a time from `velov_training_data/`, so that the memory stays
+/// bounded by the history of a few stations, and written to `merged_data/`.
+///
+/// The periods flagged in `anomalies.json` by `detect_anomalies` are excluded from the dataset.
+/// The time features are computed in the local time of the network, DST transitions included.
+///
+/// # Arguments
+///
+/// * `network` - The network, whose data directory holds the data files.
+///
 /// # Returns
 ///
-/// * nothing
+/// * The first and last timestamps of the merged data, or `None` if there is no data.
 ///
 /// # Panics
 ///
@@ -205,192 +305,402 @@ pub fn benchmark() {
 /// # Examples
 ///
 /// ```rust
-/// merge_data();
+/// merge_data(&network);
 /// ```
-pub fn merge_data() {
+pub fn merge_data(network: &Network) -> Option<DateRange> {
+    let dir = network.data_dir.as_path();
     info!("📥 Loading school holidays data..");
-    let school_holidays: Vec<SchoolHolidays> = serde_json::from_str(
-        &fs::read_to_string("school_holidays.json")
-            .unwrap()
-            .to_string(),
-    )
-    .unwrap();
+    let school_holidays = read_school_holidays(dir, &network.holidays.zone);
     info!("✅ School holidays data loaded!");
 
     info!("📥 Loading weather data..");
-    let weather: HashMap<DateTime<Utc>, WeatherData> =
-        serde_json::from_str(&fs::read_to_string("weather.json").unwrap().to_string()).unwrap();
-    info!("✅ Weather data loaded!");
+    let weather = read_weather(network);
+    let mut connection = establish_connection();
+    // Each station takes the weather of its nearest cell, the locations are only needed on a grid
+    let locations = if weather.len() > 1 {
+        station_locations(&mut connection, &network.id)
+    } else {
+        HashMap::new()
+    };
+    info!("✅ Weather data loaded for {} points!", weather.len());
+
+    // The clusters stored by the cluster command are kept in the new merged data
+    let clusters = station_clusters(&mut connection, &network.id);
 
-    info!("📥 Loading velov training data..");
-    let file = File::open("velov_training_data.json").unwrap();
-    let reader = BufReader::new(file);
-    let velov: Vec<UsefulData> = serde_json::from_reader(reader).unwrap();
-    info!("✅ Velov training data loaded!");
+    info!("📥 Loading anomalies..");
+    let mut anomalies: HashMap<u32, Vec<Anomaly>> = HashMap::new();
+    for anomaly in read_anomalies(dir) {
+        anomalies.entry(anomaly.id).or_default().push(anomaly);
+    }
 
     info!("🔄 Merging data..");
-    let merged: Vec<MergedData> = velov
+    let merged_dir = dir.join("merged_data");
+    clear_dir(&merged_dir);
+    let files = list_files(&dir.join(VELOV_TRAINING_DATA_DIR));
+    let merged: Vec<(usize, Option<DateRange>)> = files
         .par_iter()
-        .map(|velov_data| {
-            let date = velov_data.date;
-            let school_holiday = school_holidays.iter().any(|holiday| {
-                date.naive_local().date() >= holiday.start
-                    && date.naive_local().date() <= holiday.end
-            });
-
-            let weather_data = weather
-                .get(
-                    &date
-                        .with_minute(0)
-                        .unwrap()
-                        .with_second(0)
-                        .unwrap()
-                        .with_nanosecond(0)
-                        .unwrap(),
-                )
-                .unwrap_or_else(|| panic!("❌ No weather data for {}", date));
-            let precipitation_data = weather_data.precipitation;
-            let temperature_data = weather_data.temperature_2m;
-            let wind_speed_data = weather_data.wind_speed_10m;
-
-            MergedData {
-                id: velov_data.id,
-                hour: date.time().hour(),
-                minute: date.time().minute(),
-                day: date.date_naive().day(),
-                month: date.date_naive().month(),
-                week_day: date.date_naive().weekday().number_from_monday(),
-                holidays: school_holiday,
-                free_stands: velov_data.stands,
-                available_bikes: velov_data.bikes,
-                precipitation: precipitation_data,
-                temperature: temperature_data,
-                wind_speed: wind_speed_data,
-            }
+        .map(|path| {
+            let reader = BufReader::new(File::open(path).unwrap());
+            let velov: Vec<UsefulData> = serde_json::from_reader(reader).unwrap();
+
+            let before_exclusion = velov.len();
+            let velov: Vec<UsefulData> = velov
+                .into_iter()
+                .filter(|v| {
+                    !anomalies
+                        .get(&v.id)
+                        .is_some_and(|a| a.iter().any(|a| a.contains(v.date)))
+                })
+                .collect();
+            let excluded = before_exclusion - velov.len();
+
+            let Some(first) = velov.first() else {
+                return (excluded, None);
+            };
+            let cluster = clusters.get(&first.id).copied();
+            let station_weather = nearest_cell(
+                &weather,
+                locations
+                    .get(&first.id)
+                    .copied()
+                    .unwrap_or(network.weather.centre()),
+            );
+            // The training data of a station is sorted by date
+            let merged: Vec<MergedData> = velov
+                .iter()
+                .map(|velov_data| {
+                    merge_snapshot(
+                        velov_data,
+                        &school_holidays,
+                        station_weather,
+                        cluster,
+                        network.timezone,
+                    )
+                })
+                .collect();
+            let (Some(first), Some(last)) = (merged.first(), merged.last()) else {
+                return (excluded, None);
+            };
+            let range = (first.timestamp, last.timestamp);
+
+            write_station(&merged_dir, first.id, &merged, config().storage.format).unwrap();
+
+            (excluded, Some(range))
         })
         .collect();
 
-    info!("✅ Data merged!");
-    info!("🔄 Hashmapping the data");
+    info!(
+        "🗑️ Excluded {} entries flagged as anomalies!",
+        merged.iter().map(|(excluded, _)| excluded).sum::<usize>()
+    );
+    info!("✅ Data merged for {} stations!", files.len());
 
-    // merge the data per id in a hashmap
-    let hashmapped: HashMap<u32, Vec<MergedData>> =
-        merged.iter().fold(HashMap::new(), |mut acc, val| {
-            acc.entry(val.id)
-                .and_modify(|v: &mut Vec<MergedData>| v.push(val.clone()))
-                .or_default();
-            acc
-        });
+    merged
+        .into_iter()
+        .filter_map(|(_, range)| range)
+        .reduce(|(start, end), (first, last)| (start.min(first), end.max(last)))
+}
+
+/// Merges a Velov snapshot with the weather and the school holidays at its date.
+///
+/// # Arguments
+///
+/// * `velov_data` - The Velov snapshot.
+/// * `school_holidays` - The school holidays.
+/// * `weather` - The hourly weather history at the station.
+/// * `cluster` - The usage cluster of the station, if it has been clustered.
+/// * `timezone` - The time zone of the network, in which the time features are computed.
+///
+/// # Returns
+///
+/// * The merged data point.
+///
+/// # Panics
+///
+/// Panics if the weather data is missing for the date of the snapshot.
+fn merge_snapshot(
+    velov_data: &UsefulData,
+    school_holidays: &[SchoolHolidays],
+    weather: &HourlyWeather,
+    cluster: Option<u32>,
+    timezone: Tz,
+) -> MergedData {
+    let date = velov_data.date;
+    let local_date = local_time(date, timezone);
+    let school_holiday = school_holidays
+        .iter()
+        .any(|holiday| holiday.contains(local_date.date_naive()));
 
-    info!("✅ Data merged!");
+    let weather_data = weather
+        .get(
+            &date
+                .with_minute(0)
+                .unwrap()
+                .with_second(0)
+                .unwrap()
+                .with_nanosecond(0)
+                .unwrap(),
+        )
+        .unwrap_or_else(|| panic!("❌ No weather data for {}", date));
 
-    write_merged_data_to_file("merged_data", hashmapped);
+    MergedData {
+        id: velov_data.id,
+        hour: local_date.hour(),
+        minute: local_date.minute(),
+        day: local_date.day(),
+        month: local_date.month(),
+        week_day: local_date.weekday().num_days_from_monday(),
+        holidays: school_holiday,
+        free_stands: velov_data.stands,
+        available_bikes: velov_data.bikes,
+        precipitation: weather_data.precipitation,
+        temperature: weather_data.temperature_2m,
+        wind_speed: weather_data.wind_speed_10m,
+        cluster,
+        capacity: velov_data.capacity,
+        timestamp: date,
+    }
 }
 
-/// Filters the Velov data to keep only the useful data and writes it to a file.
+/// Loads the location of every station from the database.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `network` - The ID of the network of the stations.
 ///
 /// # Returns
 ///
-/// * nothing
+/// * The latitude and longitude of each station, empty if the query fails.
+pub fn station_locations(connection: &mut PgConnection, network: &str) -> HashMap<u32, (f64, f64)> {
+    use schema::station::dsl::station;
+    station
+        .select((
+            schema::station::id,
+            schema::station::latitude,
+            schema::station::longitude,
+        ))
+        .filter(schema::station::network.eq(network))
+        .load::<(i32, f64, f64)>(connection)
+        .map(|stations| {
+            stations
+                .into_iter()
+                .map(|(id, latitude, longitude)| (id as u32, (latitude, longitude)))
+                .collect()
+        })
+        .unwrap_or_else(|e| {
+            warn!("❌ Failed to load the station locations: {}", e);
+            HashMap::new()
+        })
+}
+
+/// Loads the current capacity of every station from the database.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `network` - The ID of the network of the stations.
+///
+/// # Returns
+///
+/// * The capacity of each station, empty if the query fails.
+pub fn station_capacities(connection: &mut PgConnection, network: &str) -> HashMap<u32, u32> {
+    use schema::station::dsl::station;
+    station
+        .select((schema::station::id, schema::station::capacity))
+        .filter(schema::station::network.eq(network))
+        .load::<(i32, i32)>(connection)
+        .map(|stations| {
+            stations
+                .into_iter()
+                .map(|(id, capacity)| (id as u32, capacity.max(0) as u32))
+                .collect()
+        })
+        .unwrap_or_else(|e| {
+            warn!("❌ Failed to load the station capacities: {}", e);
+            HashMap::new()
+        })
+}
+
+/// Loads the usage cluster of every clustered station from the database.
+///
+/// # Arguments
+///
+/// * `connection` - The database connection.
+/// * `network` - The ID of the network of the stations.
+///
+/// # Returns
+///
+/// * The cluster of each clustered station, empty if the query fails.
+fn station_clusters(connection: &mut PgConnection, network: &str) -> HashMap<u32, u32> {
+    use schema::station::dsl::station;
+    station
+        .select((schema::station::id, schema::station::cluster))
+        .filter(schema::station::network.eq(network))
+        .filter(schema::station::cluster.is_not_null())
+        .load::<(i32, Option<i32>)>(connection)
+        .map(|stations| {
+            stations
+                .into_iter()
+                .filter_map(|(id, cluster)| Some((id as u32, cluster? as u32)))
+                .collect()
+        })
+        .unwrap_or_else(|e| {
+            warn!("❌ Failed to load the station clusters: {}", e);
+            HashMap::new()
+        })
+}
+
+/// Filters the Velov data to keep only the useful data, resamples it to a regular grid and writes
+/// it to `velov_training_data/`, one file per station.
+///
+/// The raw files are read by batches of `FILTER_BATCH_SIZE`, in download order, and the snapshots
+/// of each batch are appended to a temporary file per station in `velov_partitions/`. Each
+/// station is then deduped, filtered and resampled on its own, so that the memory stays bounded
+/// by the size of a batch and the history of a few stations rather than by the whole history.
+///
+/// The snapshots of a station sharing a horodate are resolved by the dedup policy, and what was
+/// dropped is written to `dedup_report.json`. The download order is given by the page or the
+/// horodate in the names of the raw files.
+///
+/// # Arguments
+///
+/// * `network` - The network, whose data directory holds the data files.
+/// * `resample_step` - The step of the resampling grid, in minutes.
+/// * `dedup_policy` - The policy resolving the snapshots sharing a horodate.
+///
+/// # Returns
+///
+/// * The first and last dates of the filtered data, or `None` if there is no data.
 ///
 /// # Examples
 ///
 /// ```rust
-/// filter_velov_data();
+/// filter_velov_data(&network, 15, DedupPolicy::LatestDownload);
 /// ```
-pub fn filter_velov_data() {
+pub fn filter_velov_data(
+    network: &Network,
+    resample_step: u32,
+    dedup_policy: DedupPolicy,
+) -> Option<DateRange> {
     info!("🧹 Filtering velov data...");
-    let original_stats = Arc::new(Mutex::new(HashMap::<u32, u32>::new()));
-    let original_data_len = Arc::new(Mutex::new(0));
-    let compliant_data_len = Arc::new(Mutex::new(0));
+    let mut original_stats = HashMap::<u32, u32>::new();
 
-    let read_dir_length = fs::read_dir("./velov_datas").unwrap().count();
-    let visited = Mutex::new(0);
+    let dir = network.data_dir.as_path();
+    let partitions_dir = dir.join(VELOV_PARTITIONS_DIR);
+    let training_dir = dir.join(VELOV_TRAINING_DATA_DIR);
+    let files = download_order(list_files(&dir.join("velov_datas")));
+    clear_dir(&partitions_dir);
 
-    let mut data = fs::read_dir("./velov_datas")
-        .unwrap()
-        .par_bridge()
-        .map(|e| e.unwrap())
-        .filter(|entry| entry.file_name() != ".gitkeep")
-        .flat_map(|entry| {
-            info!(
-                "📖 {}% Reading {}",
-                ((*visited.lock().unwrap() as f32 / read_dir_length as f32) * 10000.0).round()
-                    / 100.0,
-                entry.path().display()
-            );
+    for (batch_index, batch) in files.chunks(FILTER_BATCH_SIZE).enumerate() {
+        info!(
+            "📖 {}% Reading {} files",
+            ((batch_index * FILTER_BATCH_SIZE) as f32 / files.len() as f32 * 10000.0).round()
+                / 100.0,
+            batch.len()
+        );
 
-            let file = File::open(entry.path()).unwrap();
-            let reader = BufReader::new(file);
+        let read: Vec<Vec<RankedSnapshot>> = batch
+            .par_iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let reader = BufReader::new(File::open(path).unwrap());
+                let file_data: Vec<Value> = serde_json::from_reader(reader).unwrap();
 
-            let file_data: Vec<Value> = serde_json::from_reader(reader).unwrap();
-            *original_data_len.lock().unwrap() += file_data.len();
+                file_data
+                    .iter()
+                    .map(|value| RankedSnapshot {
+                        download: batch_index * FILTER_BATCH_SIZE + index,
+                        snapshot: UsefulData {
+                            id: value.number as u32,
+                            date: value.horodate,
+                            capacity: value.total_stands.capacity as u32,
+                            bikes: value.total_stands.availabilities.bikes as u32,
+                            stands: value.total_stands.availabilities.stands as u32,
+                        },
+                    })
+                    .collect()
+            })
+            .collect();
 
-            let mut original_stats_tmp = HashMap::<u32, u32>::new();
+        let mut per_station: HashMap<u32, Vec<RankedSnapshot>> = HashMap::new();
+        for value in read.into_iter().flatten() {
+            *original_stats.entry(value.snapshot.id).or_insert(0) += 1;
+            per_station
+                .entry(value.snapshot.id)
+                .or_default()
+                .push(value);
+        }
 
-            let useful_data = file_data
-                .iter()
-                .map(|value| {
-                    original_stats_tmp
-                        .entry(value.number as u32)
-                        .and_modify(|e| *e += 1)
-                        .or_insert(1);
-
-                    UsefulData {
-                        id: value.number as u32,
-                        date: value.horodate,
-                        capacity: value.total_stands.capacity as u32,
-                        bikes: value.total_stands.availabilities.bikes as u32,
-                        stands: value.total_stands.availabilities.stands as u32,
-                    }
-                })
-                .filter(|value| value.capacity == (value.bikes + value.stands))
-                .collect::<Vec<UsefulData>>();
-
-            {
-                let mut original_stats_guard = original_stats.lock().unwrap();
-                for (id, value) in original_stats_tmp.iter() {
-                    original_stats_guard
-                        .entry(*id)
-                        .and_modify(|e| *e += value)
-                        .or_insert(*value);
-                }
+        // Each station is appended to its own partition, so the writes never overlap
+        per_station.par_iter().for_each(|(id, values)| {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(partitions_dir.join(format!("{}.jsonl", id)))
+                .unwrap();
+            let mut writer = BufWriter::new(file);
+            for value in values {
+                serde_json::to_writer(&mut writer, value).unwrap();
+                writer.write_all(b"\n").unwrap();
             }
+        });
+    }
 
-            *compliant_data_len.lock().unwrap() += useful_data.len();
+    info!(
+        "🧹 Deduping, filtering and resampling to a {} minutes grid..",
+        resample_step
+    );
+    clear_dir(&training_dir);
+    let step = Duration::minutes(resample_step as i64);
+    let max_gap = Duration::minutes(MAX_INTERPOLATED_GAP_MINUTES);
+    let stations: Vec<FilteredStation> = list_files(&partitions_dir)
+        .par_iter()
+        .map(|path| {
+            let reader = BufReader::new(File::open(path).unwrap());
+            let snapshots: Vec<RankedSnapshot> = reader
+                .lines()
+                .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+                .collect();
+            let id = snapshots[0].snapshot.id;
+
+            let (snapshots, dedup) = dedup_station(id, snapshots, dedup_policy);
+            let compliant: Vec<UsefulData> = snapshots
+                .into_iter()
+                .filter(|value| value.capacity == (value.bikes + value.stands))
+                .collect();
+            let compliant_len = compliant.len() as u32;
 
-            *visited.lock().unwrap() += 1;
+            let resampled =
+                resample_station(&compliant, step, max_gap).and_then(|(resampled, coverage)| {
+                    let (first, last) = (resampled.first()?.date, resampled.last()?.date);
+                    let writer = BufWriter::new(
+                        File::create(training_dir.join(format!("{}.json", coverage.id))).unwrap(),
+                    );
+                    serde_json::to_writer(writer, &resampled).unwrap();
+                    Some((coverage, first, last))
+                });
 
-            useful_data
+            (dedup, compliant_len, resampled)
         })
-        .collect::<Vec<_>>();
-
-    // Display some stats
-    //filter stats per id
-    let mut compliant_stats: HashMap<u32, u32> = HashMap::new();
-    for value in data.iter() {
-        compliant_stats
-            .entry(value.id)
-            .and_modify(|e| *e += 1)
-            .or_insert(1);
-    }
+        .collect();
+    fs::remove_dir_all(&partitions_dir).unwrap();
+
     //display the stats
     info!("📊 Stats per id :");
-    let original_stats = original_stats.lock().unwrap();
-    for (id, _) in compliant_stats.iter() {
+    for (dedup, compliant, _) in stations.iter() {
+        let original = original_stats.get(&dedup.id).unwrap();
         info!(
             "🆔 {} : {}/{} ({}%)",
-            id,
-            compliant_stats.get(id).unwrap(),
-            original_stats.get(id).unwrap(),
-            (*compliant_stats.get(id).unwrap() as f32 / *original_stats.get(id).unwrap() as f32)
-                * 100.0
+            dedup.id,
+            compliant,
+            original,
+            (*compliant as f32 / *original as f32) * 100.0
         );
     }
 
-    let original_data_len = *original_data_len.lock().unwrap();
-    let compliant_data_len = *compliant_data_len.lock().unwrap();
-
+    let original_data_len: u32 = original_stats.values().sum();
+    let compliant_data_len: u32 = stations.iter().map(|(_, compliant, _)| compliant).sum();
     info!(
         "✅ Compliant data : {}/{} ({}%)",
         compliant_data_len,
@@ -398,20 +708,106 @@ pub fn filter_velov_data() {
         (compliant_data_len as f32 / original_data_len as f32) * 100.0
     );
 
-    info!("🧹 Deduping..");
-    let before_dedup = data.len();
-    data.dedup_by(|a, b| a.date == b.date && a.id == b.id);
-    info!("🗑️ Deduped {} entries !", before_dedup - data.len());
+    let mut reports: Vec<StationDedup> = stations.iter().map(|(dedup, ..)| dedup.clone()).collect();
+    write_dedup_report(dir, &mut reports, dedup_policy);
+    let mut coverages: Vec<StationCoverage> = stations
+        .iter()
+        .filter_map(|(_, _, resampled)| Some(resampled.as_ref()?.0.clone()))
+        .collect();
+    write_coverage(dir, &mut coverages);
+    info!("✅ {}/ written!", training_dir.display());
+
+    stations
+        .into_iter()
+        .filter_map(|(_, _, resampled)| resampled.map(|(_, first, last)| (first, last)))
+        .reduce(|(start, end), (first, last)| (start.min(first), end.max(last)))
+}
 
-    info!("🔄 Sorting data");
-    data.sort_by(|a, b| a.date.cmp(&b.date));
-    info!("✅ Done !");
+/// Ranks a raw file in the download order, from its name.
+///
+/// The pages of the history (`data-<start>-<end>.json`) are ranked by their first record, a page
+/// downloaded again (`data-<start>-<end>-<horodate>.json`) coming right after the previous
+/// download of the page, and the GBFS collections (`gbfs-<horodate>.json`) by their horodate,
+/// after the pages. The other files come last.
+///
+/// # Arguments
+///
+/// * `file` - The raw file.
+///
+/// # Returns
+///
+/// * The rank of the file, the most recently downloaded being the highest.
+fn download_rank(file: &Path) -> (u8, i64, i64) {
+    let stem = file
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("");
+    let numbers = |rest: &str| -> Option<Vec<i64>> {
+        rest.split('-').map(|part| part.parse().ok()).collect()
+    };
+    if let Some(numbers) = stem.strip_prefix("data-").and_then(numbers) {
+        match numbers[..] {
+            [start, _] => return (0, start, i64::MIN),
+            [start, _, horodate] => return (0, start, horodate),
+            _ => {}
+        }
+    }
+    if let Some(horodate) = stem
+        .strip_prefix("gbfs-")
+        .and_then(|rest| rest.parse().ok())
+    {
+        return (1, horodate, 0);
+    }
+    (2, 0, 0)
+}
 
-    info!("🔄 Serializing data..");
-    let file = File::create("./velov_training_data.json").unwrap();
-    let writer = BufWriter::new(file);
-    serde_json::to_writer(writer, &data).unwrap();
-    info!("✅ velov_training_data.json written!");
+/// Sorts raw files in download order, from the page or horodate in their names then their names.
+///
+/// # Arguments
+///
+/// * `files` - The raw files.
+///
+/// # Returns
+///
+/// * The files, the most recently downloaded last.
+pub(crate) fn download_order(mut files: Vec<PathBuf>) -> Vec<PathBuf> {
+    files.sort_by_cached_key(|file| (download_rank(file), file.clone()));
+    files
+}
+
+/// Lists the files of a directory, ignoring the `.gitkeep` placeholder.
+///
+/// # Arguments
+///
+/// * `path` - The path of the directory.
+///
+/// # Returns
+///
+/// * The paths of the files, sorted.
+pub(crate) fn list_files(path: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_name() != ".gitkeep")
+        .map(|entry| entry.path())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Creates a directory, or removes the files it contains except the `.gitkeep` placeholder.
+///
+/// # Arguments
+///
+/// * `path` - The path of the directory.
+fn clear_dir(path: &Path) {
+    if !path.exists() {
+        fs::create_dir_all(path).unwrap();
+        return;
+    }
+    for file in list_files(path) {
+        fs::remove_file(file).unwrap();
+    }
 }
 
 /// Reads the merged data from the files and returns it as a hashmap.
@@ -422,38 +818,29 @@ pub fn filter_velov_data() {
 ///
 /// # Returns
 ///
-/// * A hashmap containing the merged data.
+/// * A hashmap containing the merged data. The files which cannot be read are logged and skipped.
 ///
 /// # Examples
 ///
 /// ```rust
-/// let data = read_merged_data_from_file("merged_data");
+/// let data = read_merged_data_from_file(Path::new("merged_data"));
 /// ```
-pub fn read_merged_data_from_file(path: &str) -> HashMap<u32, Vec<MergedData>> {
+pub fn read_merged_data_from_file(path: &Path) -> HashMap<u32, Vec<MergedData>> {
     info!("📖 Reading data from files..");
-    // List all files in the directory
-    let mut files: Vec<_> = fs::read_dir(path)
-        .unwrap()
-        .map(|f| f.unwrap())
-        .filter(|entry| entry.file_name() != ".gitkeep")
+    let data: HashMap<u32, Vec<MergedData>> = list_files(path)
+        .par_iter()
+        .filter_map(|file| {
+            read_station(file)
+                .map_err(|e| error!("❌ Failed to read {}, skipping it", e))
+                .ok()
+                .flatten()
+        })
         .collect();
-    files.sort_by_key(|a| a.path());
-
-    let data = Arc::new(Mutex::new(HashMap::<u32, Vec<MergedData>>::new()));
-
-    files.par_iter().for_each(|file| {
-        let file = File::open(file.path()).unwrap();
-        let reader = BufReader::new(file);
-        let file_data: Vec<MergedData> = serde_json::from_reader(reader).unwrap();
-
-        let mut data_guard = data.lock().unwrap();
-        data_guard.insert(file_data.first().unwrap().id, file_data);
-    });
     info!("✅ Data read from files!");
-    Arc::try_unwrap(data).unwrap().into_inner().unwrap()
+    data
 }
 
-/// Writes the merged data to files.
+/// Writes the merged data to files, in the storage format of the configuration.
 ///
 /// # Arguments
 ///
@@ -466,15 +853,12 @@ pub fn read_merged_data_from_file(path: &str) -> HashMap<u32, Vec<MergedData>> {
 /// # Examples
 ///
 /// ```rust
-/// write_merged_data_to_file("merged_data", data);
+/// write_merged_data_to_file(Path::new("merged_data"), data);
 /// ```
-fn write_merged_data_to_file(path: &str, data: HashMap<u32, Vec<MergedData>>) {
+pub fn write_merged_data_to_file(path: &Path, data: HashMap<u32, Vec<MergedData>>) {
     info!("✍️ Splitting data into files..");
     data.par_iter().for_each(|(key, value)| {
-        let file_path = format!("{}/{}.json", path, key);
-        let file = File::create(file_path).unwrap();
-        let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, value).unwrap();
+        write_station(path, *key, value, config().storage.format).unwrap();
     });
     info!("✅ Data written to files!");
 }
@@ -501,12 +885,12 @@ fn write_merged_data_to_file(path: &str, data: HashMap<u32, Vec<MergedData>>) {
 /// };
 /// ```
 #[derive(Debug, Serialize, Deserialize, Clone)]
-struct UsefulData {
-    id: u32,
-    date: DateTime<Utc>,
-    capacity: u32,
-    bikes: u32,
-    stands: u32,
+pub struct UsefulData {
+    pub id: u32,
+    pub date: DateTime<Utc>,
+    pub capacity: u32,
+    pub bikes: u32,
+    pub stands: u32,
 }
 
 /// Data structure representing the availability of bikes and free stands at a station.
@@ -514,20 +898,25 @@ struct UsefulData {
 /// # Fields
 ///
 /// * `id` - The ID of the station.
-/// * `hour` - The hour of the data.
-/// * `day` - The day of the data.
-/// * `month` - The month of the data.
-/// * `week_day` - The day of the week of the data.
+/// * `hour` - The local hour of the data.
+/// * `minute` - The local minute of the data.
+/// * `day` - The local day of the month of the data.
+/// * `month` - The local month of the data.
+/// * `week_day` - The local day of the week of the data, from 0 for Monday.
 /// * `holidays` - A flag indicating whether the data is on a holiday.
 /// * `free_stands` - The number of free stands available.
 /// * `available_bikes` - The number of bikes available.
 /// * `precipitation` - The amount of precipitation.
 /// * `temperature` - The temperature.
 /// * `wind_speed` - The wind speed.
+/// * `cluster` - The usage cluster of the station, set by the `cluster` command.
+/// * `capacity` - The capacity of the station at the time of the data, or 0 if unknown.
+/// * `timestamp` - The full date and time of the data, required to place the data in the history.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MergedData {
     pub id: u32,
     pub hour: u32,
+    #[serde(default)]
     pub minute: u32,
     pub day: u32,
     pub month: u32,
@@ -538,6 +927,48 @@ pub struct MergedData {
     pub precipitation: f32,
     pub temperature: f32,
     pub wind_speed: f32,
+    #[serde(default)]
+    pub cluster: Option<u32>,
+    #[serde(default)]
+    pub capacity: u32,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl MergedData {
+    /// Returns the size of the station at the time of the data.
+    ///
+    /// Falls back on the sum of the bikes and stands for data without a known capacity.
+    pub fn size(&self) -> u32 {
+        if self.capacity > 0 {
+            self.capacity
+        } else {
+            self.available_bikes + self.free_stands
+        }
+    }
+
+    /// Returns the ratio of available bikes over the size of the station at the time of the data.
+    ///
+    /// # Returns
+    ///
+    /// * The occupancy between 0 and 1, or `None` if the size of the station is unknown.
+    pub fn occupancy(&self) -> Option<f32> {
+        let size = self.size();
+        (size > 0).then(|| (self.available_bikes as f32 / size as f32).min(1.0))
+    }
+
+    /// Rescales the available bikes to another capacity, keeping the occupancy.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The capacity to rescale to, usually the current capacity of the station.
+    ///
+    /// # Returns
+    ///
+    /// * The rescaled number of available bikes, or `None` if the occupancy is unknown.
+    pub fn rescaled_bikes(&self, capacity: u32) -> Option<u32> {
+        self.occupancy()
+            .map(|occupancy| (occupancy * capacity as f32).round() as u32)
+    }
 }
 
 /// Data structure representing the school holidays.
@@ -545,9 +976,57 @@ pub struct MergedData {
 /// # Fields
 ///
 /// * `start` - The start date of the holidays.
-/// * `end` - The end date of the holidays.
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// * `end` - The end date of the holidays, the last day without school.
+/// * `zone` - The school zone of the holidays, `None` for holidays of every zone.
+/// * `description` - The name of the holidays.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct SchoolHolidays {
     pub start: NaiveDate,
     pub end: NaiveDate,
+    #[serde(default)]
+    pub zone: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl SchoolHolidays {
+    /// Whether a day is during the holidays.
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        date >= self.start && date <= self.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_raw_files_in_download_order() {
+        let files = [
+            "velov_datas/gbfs-1700000600.json",
+            "velov_datas/data-1000-2000.json",
+            "velov_datas/notes.json",
+            "velov_datas/data-0-1000-1700000000.json",
+            "velov_datas/gbfs-1700000000.json",
+            "velov_datas/data-0-1000.json",
+            "velov_datas/data-2000-3000.json",
+        ];
+        let ordered = download_order(files.iter().map(PathBuf::from).collect());
+
+        assert_eq!(
+            ordered,
+            [
+                "velov_datas/data-0-1000.json",
+                "velov_datas/data-0-1000-1700000000.json",
+                "velov_datas/data-1000-2000.json",
+                "velov_datas/data-2000-3000.json",
+                "velov_datas/gbfs-1700000000.json",
+                "velov_datas/gbfs-1700000600.json",
+                "velov_datas/notes.json",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+    }
 }
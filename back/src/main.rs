@@ -1,28 +1,56 @@
+mod anomaly;
 mod api;
 mod args;
+mod clustering;
+mod config;
+mod dedup;
 mod downloader;
+mod forecast;
+mod gbfs;
+mod history;
+mod holidays;
+mod http;
 mod learning;
 mod mock;
 mod models;
+mod pipeline;
 mod populate;
+mod quality;
+mod rebalancing;
+mod resample;
 mod schema;
+mod storage;
 mod utils;
 
-use api::{get_detailed_stations, get_weather_forecast, predict, predictions};
+use anomaly::{detect_anomalies, read_anomalies, Anomaly};
+use api::{
+    get_anomalies, get_area_forecast, get_areas, get_detailed_stations, get_gbfs,
+    get_gbfs_station_information, get_gbfs_station_status, get_gbfs_system_information,
+    get_networks, get_station_history, get_weather_forecast, predict, predictions, rebalancing,
+};
 use args::Args;
 use axum::routing::get;
 use axum::Router;
 use clap::Parser;
+use clustering::cluster;
+use config::{init_config, Config, Network, WeatherMode};
 use downloader::{download_velov, download_weather};
+use forecast::precompute_forecasts;
+use history::{import_history, load_merged_data_from_db};
+use holidays::{import_school_holidays, read_school_holidays};
+use http::{init_http_client, HttpMode};
 use indoc::indoc;
 use learning::{
-    benchmark, filter_velov_data, merge_data, read_merged_data_from_file, MergedData,
-    SchoolHolidays,
+    benchmark, filter_velov_data, merge_data, read_merged_data_from_file, station_capacities,
+    station_locations, MergedData, SchoolHolidays,
 };
 use mock::get_detailed_station_mock;
+use pipeline::run_pipeline;
+use quality::quality_report;
+use storage::{convert_merged_data, DataSource};
 use tokio::signal;
 use tower_http::cors::CorsLayer;
-use tracing::info;
+use tracing::{error, info};
 
 use crate::api::{get_detailed_station, get_stations, search_station};
 use crate::mock::{get_detailed_stations_mock, get_stations_mock};
@@ -32,6 +60,7 @@ use diesel::prelude::*;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use std::collections::HashMap;
 use std::env;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
@@ -42,12 +71,20 @@ pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
 ///
 /// * `connection` - The database connection.
 /// * `data` - The merged data.
-/// * `holidays` - The school holidays.
+/// * `holidays` - The school holidays of the configured zone.
+/// * `anomalies` - The detected anomalies.
+/// * `network` - The network served.
+/// * `capacities` - The current capacity of each station, loaded at startup.
+/// * `locations` - The latitude and longitude of each station, loaded at startup.
 #[derive(Clone)]
 pub struct AppState {
     connection: Arc<Mutex<PgConnection>>,
     data: Arc<HashMap<u32, Vec<MergedData>>>,
     holidays: Arc<Vec<SchoolHolidays>>,
+    anomalies: Arc<Vec<Anomaly>>,
+    network: Arc<Network>,
+    capacities: Arc<HashMap<u32, u32>>,
+    locations: Arc<HashMap<u32, (f64, f64)>>,
 }
 
 /// Main function.
@@ -55,31 +92,113 @@ pub struct AppState {
 async fn main() {
     tracing_subscriber::fmt::init();
     let args = Args::parse();
+    let mut config = match Config::load(&args.config) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    // The command-line flags take precedence over the file and the environment
+    if let Some(timeout) = args.http_timeout {
+        config.http.timeout = timeout;
+    }
+    if let Some(retries) = args.http_retries {
+        config.http.retries = retries;
+    }
+    if let Some(rate_limit) = args.http_rate_limit {
+        config.http.rate_limit = rate_limit;
+    }
+    if args.mock {
+        // The recorded forecast is the one of the city centre, of the only mocked network
+        config.http.mode = HttpMode::Replay;
+        config.weather.mode = WeatherMode::Point;
+        config.networks.clear();
+    }
+    // The commands work on the selected network, the current one by default
+    let network = match config.network(args.network.as_deref()).and_then(|network| {
+        std::fs::create_dir_all(&network.data_dir)
+            .map(|_| network)
+            .map_err(|e| format!("Invalid data directory: {}", e))
+    }) {
+        Ok(network) => network,
+        Err(e) => {
+            error!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    if args.network.is_some() {
+        info!("🏙️ Working on the {} network", network.id);
+    }
+    init_http_client(config.http.http_config());
+    init_config(config);
 
     if args.benchmark {
-        benchmark();
+        benchmark(&network);
         return;
     }
     if args.filter_velov_data {
-        filter_velov_data();
+        filter_velov_data(&network, args.resample_step, args.dedup_policy);
+        return;
+    }
+    if let Some(format) = args.convert_merged_data {
+        convert_merged_data(&network.data_dir.join("merged_data"), format);
+        return;
+    }
+    if args.import_history {
+        if let Err(e) = import_history(&network) {
+            error!("❌ {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.quality_report {
+        quality_report(&network, args.resample_step, args.gap_threshold);
         return;
     }
     if args.merge_datas {
-        merge_data();
+        merge_data(&network);
+        return;
+    }
+    if args.cluster {
+        cluster(&network, args.clusters);
+        return;
+    }
+    if args.detect_anomalies {
+        detect_anomalies(&network, args.dedup_policy);
         return;
     }
 
     if args.download_weather_data {
-        download_weather().await;
+        if download_weather(&network).await.is_err() {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.import_school_holidays {
+        if import_school_holidays(&network).await.is_err() {
+            std::process::exit(1);
+        }
         return;
     }
     if args.download_velov_data {
-        download_velov(args.max_velov_features, args.velov_start).await;
+        if download_velov(&network, args.max_velov_features, args.velov_start)
+            .await
+            .is_err()
+        {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.pipeline {
+        if run_pipeline(&args, &network).await.is_err() {
+            std::process::exit(1);
+        }
         return;
     }
 
-    if args.populate {
-        populate().await;
+    if args.populate && populate(&network).await.is_err() {
+        std::process::exit(1);
     }
 
     if args.mock {
@@ -88,20 +207,26 @@ async fn main() {
         info!("🌐 Running in normal mode!");
     }
 
-    let app_state = AppState {
-        connection: Arc::new(Mutex::new(establish_connection())),
-        data: Arc::new(read_merged_data_from_file(if !args.mock {
-            "merged_data"
-        } else {
-            "merged_data_mock"
-        })),
-        holidays: Arc::new(
-            serde_json::from_str(&std::fs::read_to_string("school_holidays.json").unwrap())
-                .unwrap(),
-        ),
-    };
+    let connection = Arc::new(Mutex::new(establish_connection()));
+    let mut networks = config::config().networks();
+    // The selected network is served first, at the root
+    networks.sort_by_key(|other| other.id != network.id);
+    let states: Vec<AppState> = networks
+        .into_iter()
+        .map(|network| network_state(network, connection.clone(), args.mock))
+        .collect();
+
+    if args.precompute_forecasts {
+        for state in &states {
+            if precompute_forecasts(state).await.is_err() {
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let app = Router::new()
+    // The selected network is also served at the root
+    let mut app = network_router(states[0].clone(), args.mock)
         .route(
             "/",
             get(|| async {
@@ -112,6 +237,12 @@ async fn main() {
                         🚉 /stations 
                         🔍 /search/:name 
                         📊 /station/:id 
+                        🕰️ /station/:id/history 
+                        ⚠️ /anomalies 
+                        🚚 /rebalancing 
+                        🏙️ /areas 
+                        🌍 /networks, each served under /networks/:network/ 
+                        🛰️ /gbfs/gbfs.json 
                         🔮 and the most important: /predict 🔮
                         Enjoy exploring our API! 🎉
                         🚴‍♂️🚴‍♀️🚴‍♂️🚴‍♀️🚴‍♂️🚴‍♀️🚴‍♂️🚴‍♀️🚴‍♂️🚴‍♀️
@@ -126,18 +257,12 @@ async fn main() {
                         "}
             }),
         )
-        .route("/weather_forecast", get(get_weather_forecast))
-        .route("/search/:name", get(search_station))
-        .route("/predict", get(predict))
-        .route("/predictions", get(predictions))
-        .with_state(app_state.clone())
+        .route("/networks", get(get_networks))
         .layer(CorsLayer::permissive());
-
-    let app = if args.mock {
-        app.merge(mock_router())
-    } else {
-        app.merge(normal_router(app_state))
-    };
+    for state in states {
+        let path = format!("/networks/{}", state.network.id);
+        app = app.nest(&path, network_router(state, args.mock));
+    }
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
     tracing::info!("API Server is listening on port 8000");
@@ -153,7 +278,7 @@ async fn main() {
 ///
 /// * A connection to the database.
 pub fn establish_connection() -> PgConnection {
-    dotenvy::dotenv().unwrap();
+    dotenvy::dotenv().ok();
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let mut connection = PgConnection::establish(&database_url)
@@ -166,6 +291,47 @@ pub fn establish_connection() -> PgConnection {
     connection
 }
 
+/// Loads the data of a network served by the API.
+///
+/// # Arguments
+///
+/// * `network` - The network.
+/// * `connection` - The database connection, shared by the networks.
+/// * `mock` - Whether to serve the mock data.
+///
+/// # Returns
+///
+/// * The application state of the network.
+fn network_state(network: Network, connection: Arc<Mutex<PgConnection>>, mock: bool) -> AppState {
+    info!("🏙️ Loading the {} network..", network.id);
+    let data = if mock {
+        read_merged_data_from_file(Path::new("merged_data_mock"))
+    } else {
+        match config::config().storage.source {
+            DataSource::Files => read_merged_data_from_file(&network.data_dir.join("merged_data")),
+            DataSource::Database => {
+                load_merged_data_from_db(&mut connection.lock().unwrap(), &network.id)
+            }
+        }
+    };
+
+    let capacities = station_capacities(&mut connection.lock().unwrap(), &network.id);
+    let locations = station_locations(&mut connection.lock().unwrap(), &network.id);
+
+    AppState {
+        connection,
+        data: Arc::new(data),
+        holidays: Arc::new(read_school_holidays(
+            &network.data_dir,
+            &network.holidays.zone,
+        )),
+        anomalies: Arc::new(read_anomalies(&network.data_dir)),
+        network: Arc::new(network),
+        capacities: Arc::new(capacities),
+        locations: Arc::new(locations),
+    }
+}
+
 /// Function to listen for shutdown signals.
 async fn shutdown_signal() {
     let ctrl_c = async {
@@ -195,6 +361,37 @@ async fn shutdown_signal() {
     }
 }
 
+/// Router of the routes of a network.
+///
+/// # Arguments
+///
+/// * `app_state` - The application state of the network.
+/// * `mock` - Whether to serve the mock stations.
+///
+/// # Returns
+///
+/// * A router serving the network.
+fn network_router(app_state: AppState, mock: bool) -> Router {
+    let router = Router::new()
+        .route("/weather_forecast", get(get_weather_forecast))
+        .route("/search/:name", get(search_station))
+        .route("/predict", get(predict))
+        .route("/predictions", get(predictions))
+        .route("/anomalies", get(get_anomalies))
+        .route("/station/:id/history", get(get_station_history))
+        .route("/rebalancing", get(rebalancing))
+        .route("/areas", get(get_areas))
+        .route("/areas/:area/forecast", get(get_area_forecast))
+        .with_state(app_state.clone())
+        .layer(CorsLayer::permissive());
+
+    if mock {
+        router.merge(mock_router())
+    } else {
+        router.merge(normal_router(app_state))
+    }
+}
+
 /// Mock router.
 ///
 /// # Returns
@@ -222,6 +419,16 @@ fn normal_router(app_state: AppState) -> Router {
         .route("/detailed_stations", get(get_detailed_stations))
         .route("/stations", get(get_stations))
         .route("/station/:id", get(get_detailed_station))
+        .route("/gbfs/gbfs.json", get(get_gbfs))
+        .route(
+            "/gbfs/system_information.json",
+            get(get_gbfs_system_information),
+        )
+        .route(
+            "/gbfs/station_information.json",
+            get(get_gbfs_station_information),
+        )
+        .route("/gbfs/station_status.json", get(get_gbfs_station_status))
         .with_state(app_state)
         .layer(CorsLayer::permissive())
 }
@@ -27,6 +27,8 @@ lazy_static! {
             adress: "oui adresse".to_string(),
             area: "oui area".to_string(),
             capacity: 16,
+            cluster: None,
+            network: "lyon".to_string(),
         },
         DetailedStation {
             id: 8052,
@@ -36,6 +38,8 @@ lazy_static! {
             adress: "non adresse".to_string(),
             area: "non area".to_string(),
             capacity: 16,
+            cluster: None,
+            network: "lyon".to_string(),
         }
     ];
 }
@@ -1,20 +1,40 @@
+use crate::learning::MergedData;
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
 #[derive(Queryable, Selectable, Serialize, Deserialize, Insertable, Clone)]
 #[diesel(table_name = crate::schema::forecast)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
-/// Represents a weather forecast.
-/// 
+/// Represents a precomputed prediction of the availability of a station.
+///
 /// # Fields
-/// 
-/// * `id` - The unique identifier for the forecast.
-/// * `timestamp` - The date and time of the forecast.
+///
+/// * `id` - The ID of the station.
+/// * `timestamp` - The date and time of the prediction.
 /// * `available` - The number of available bikes.
+/// * `free_stands` - The number of free stands.
+/// * `bikes_p10`, `bikes_p50`, `bikes_p90` - The quantiles of the available bikes.
+/// * `stands_p10`, `stands_p50`, `stands_p90` - The quantiles of the free stands.
+/// * `low_confidence` - Whether the prediction was borrowed from similar stations.
+/// * `model_version` - The version of the model which computed the prediction.
+/// * `computed_at` - The date and time the prediction was computed.
+/// * `network` - The ID of the network of the station.
 pub struct Forecast {
     pub id: i32,
-    pub timestamp: chrono::NaiveDateTime,
+    pub timestamp: DateTime<Utc>,
     pub available: i32,
+    pub free_stands: i32,
+    pub bikes_p10: i32,
+    pub bikes_p50: i32,
+    pub bikes_p90: i32,
+    pub stands_p10: i32,
+    pub stands_p50: i32,
+    pub stands_p90: i32,
+    pub low_confidence: bool,
+    pub model_version: String,
+    pub computed_at: DateTime<Utc>,
+    pub network: String,
 }
 
 #[derive(Queryable, Selectable, Serialize, Deserialize, Insertable, Debug, Clone)]
@@ -31,6 +51,8 @@ pub struct Forecast {
 /// * `adress` - The address of the station.
 /// * `area` - The area where the station is located.
 /// * `capacity` - The capacity of the station, indicating how many bikes it can hold.
+/// * `cluster` - The usage cluster of the station, if the stations have been clustered.
+/// * `network` - The ID of the network of the station.
 pub struct DetailedStation {
     pub id: i32,
     pub name: String,
@@ -39,15 +61,17 @@ pub struct DetailedStation {
     pub adress: String,
     pub area: String,
     pub capacity: i32,
+    pub cluster: Option<i32>,
+    pub network: String,
 }
 
 #[derive(Queryable, Selectable, Serialize, Deserialize, Insertable, Debug, Clone)]
 #[diesel(table_name = crate::schema::station)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 /// Represents a basic station with latitude and longitude coordinates.
-/// 
+///
 /// # Fields
-/// 
+///
 /// * `id` - The unique identifier for the station.
 /// * `latitude` - The latitude coordinate of the station.
 /// * `longitude` - The longitude coordinate of the station.
@@ -56,3 +80,100 @@ pub struct BasicStation {
     pub latitude: f64,
     pub longitude: f64,
 }
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::observation)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+/// Represents a raw Velov observation of a station.
+///
+/// # Fields
+///
+/// * `station_id` - The ID of the station.
+/// * `timestamp` - The date and time of the observation.
+/// * `capacity` - The total capacity of the station.
+/// * `bikes` - The number of available bikes.
+/// * `stands` - The number of free stands.
+/// * `network` - The ID of the network of the station.
+/// * `status` - The status of the station, `OPEN` or `CLOSED`, unknown for the observations
+///   imported before it was recorded.
+pub struct Observation {
+    pub station_id: i32,
+    pub timestamp: DateTime<Utc>,
+    pub capacity: i32,
+    pub bikes: i32,
+    pub stands: i32,
+    pub network: String,
+    pub status: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::merged_feature)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+/// Represents the merged features of a station at a given time, as stored in the database.
+///
+/// The fields are the ones of `MergedData`, with the database types, and the ID of the network
+/// of the station.
+pub struct MergedFeature {
+    pub station_id: i32,
+    pub timestamp: DateTime<Utc>,
+    pub hour: i16,
+    pub minute: i16,
+    pub day: i16,
+    pub month: i16,
+    pub week_day: i16,
+    pub holidays: bool,
+    pub free_stands: i32,
+    pub available_bikes: i32,
+    pub precipitation: f32,
+    pub temperature: f32,
+    pub wind_speed: f32,
+    pub cluster: Option<i32>,
+    pub capacity: i32,
+    pub network: String,
+}
+
+impl MergedFeature {
+    /// Builds the row of a merged data point of a station of a network.
+    pub fn new(network: &str, data: &MergedData) -> Self {
+        MergedFeature {
+            station_id: data.id as i32,
+            timestamp: data.timestamp,
+            hour: data.hour as i16,
+            minute: data.minute as i16,
+            day: data.day as i16,
+            month: data.month as i16,
+            week_day: data.week_day as i16,
+            holidays: data.holidays,
+            free_stands: data.free_stands as i32,
+            available_bikes: data.available_bikes as i32,
+            precipitation: data.precipitation,
+            temperature: data.temperature,
+            wind_speed: data.wind_speed,
+            cluster: data.cluster.map(|c| c as i32),
+            capacity: data.capacity as i32,
+            network: network.to_string(),
+        }
+    }
+}
+
+impl From<MergedFeature> for MergedData {
+    fn from(feature: MergedFeature) -> Self {
+        MergedData {
+            id: feature.station_id as u32,
+            hour: feature.hour as u32,
+            minute: feature.minute as u32,
+            day: feature.day as u32,
+            month: feature.month as u32,
+            week_day: feature.week_day as u32,
+            holidays: feature.holidays,
+            free_stands: feature.free_stands as u32,
+            available_bikes: feature.available_bikes as u32,
+            precipitation: feature.precipitation,
+            temperature: feature.temperature,
+            wind_speed: feature.wind_speed,
+            cluster: feature.cluster.map(|c| c as u32),
+            capacity: feature.capacity as u32,
+            timestamp: feature.timestamp,
+        }
+    }
+}
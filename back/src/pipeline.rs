@@ -0,0 +1,331 @@
+use crate::args::Args;
+use crate::config::{config, Network};
+use crate::downloader::{download_velov, download_weather};
+use crate::holidays::import_school_holidays;
+use crate::learning::{filter_velov_data, merge_data};
+use crate::populate::populate;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use tracing::{error, info};
+
+/// File where the state of the pipeline stages is stored.
+pub const PIPELINE_MANIFEST: &str = "pipeline_manifest.json";
+
+/// A stage of the data pipeline, in execution order.
+///
+/// # Variants
+///
+/// * `Populate` - Populates the database with the stations.
+/// * `DownloadVelov` - Downloads the Velov history.
+/// * `FilterVelov` - Filters and resamples the Velov history.
+/// * `DownloadWeather` - Downloads the weather history.
+/// * `ImportHolidays` - Imports the school holidays from the official school calendar.
+/// * `Merge` - Merges the Velov history with the weather and the school holidays.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Populate,
+    DownloadVelov,
+    FilterVelov,
+    DownloadWeather,
+    ImportHolidays,
+    Merge,
+}
+
+/// All the stages, in execution order.
+const STAGES: [Stage; 6] = [
+    Stage::Populate,
+    Stage::DownloadVelov,
+    Stage::FilterVelov,
+    Stage::DownloadWeather,
+    Stage::ImportHolidays,
+    Stage::Merge,
+];
+
+impl Stage {
+    /// Local files and directories read by the stage.
+    fn inputs(self) -> &'static [&'static str] {
+        match self {
+            Stage::Populate
+            | Stage::DownloadVelov
+            | Stage::DownloadWeather
+            | Stage::ImportHolidays => &[],
+            Stage::FilterVelov => &["velov_datas"],
+            Stage::Merge => &[
+                "velov_training_data",
+                "weather.json",
+                "school_holidays.json",
+                "anomalies.json",
+            ],
+        }
+    }
+
+    /// Local files and directories written by the stage.
+    fn outputs(self) -> &'static [&'static str] {
+        match self {
+            Stage::Populate => &[],
+            Stage::DownloadVelov => &["velov_datas"],
+            Stage::FilterVelov => &["velov_training_data", "coverage.json", "dedup_report.json"],
+            Stage::DownloadWeather => &["weather.json"],
+            Stage::ImportHolidays => &["school_holidays.json"],
+            Stage::Merge => &["merged_data"],
+        }
+    }
+
+    /// Whether the stage fetches remote data, which can not be fingerprinted before running it.
+    fn is_remote(self) -> bool {
+        matches!(
+            self,
+            Stage::Populate | Stage::DownloadVelov | Stage::DownloadWeather | Stage::ImportHolidays
+        )
+    }
+
+    /// Parameters of the stage: a change of parameters makes the stage out of date.
+    fn parameters(self, args: &Args, network: &Network) -> String {
+        match self {
+            Stage::Populate => network.velov.stations_source().to_string(),
+            Stage::Merge => format!(
+                "format={:?} zone={}",
+                config().storage.format,
+                network.holidays.zone
+            ),
+            Stage::DownloadVelov => format!(
+                "{} max_velov_features={} velov_start={}",
+                network.velov.availabilities_source(),
+                args.max_velov_features,
+                args.velov_start
+            ),
+            Stage::FilterVelov => format!(
+                "resample_step={} dedup_policy={:?}",
+                args.resample_step, args.dedup_policy
+            ),
+            Stage::DownloadWeather => network
+                .weather
+                .points()
+                .into_iter()
+                .map(|point| network.weather.history_url(point, network.timezone))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Stage::ImportHolidays => network.holidays.calendar_url.clone(),
+        }
+    }
+
+    /// Runs the stage on a network.
+    ///
+    /// # Returns
+    ///
+    /// * The time range of the data produced by the stage, if known, or an error message.
+    async fn run(self, args: &Args, network: &Network) -> Result<Option<TimeRange>, &'static str> {
+        match self {
+            Stage::Populate => populate(network).await.map(|_| None),
+            Stage::DownloadVelov => {
+                download_velov(network, args.max_velov_features, args.velov_start)
+                    .await
+                    .map(|_| None)
+            }
+            Stage::FilterVelov => {
+                Ok(
+                    filter_velov_data(network, args.resample_step, args.dedup_policy)
+                        .map(TimeRange::from),
+                )
+            }
+            Stage::DownloadWeather => download_weather(network).await.map(|_| None),
+            Stage::ImportHolidays => import_school_holidays(network).await.map(|_| None),
+            Stage::Merge => Ok(merge_data(network).map(TimeRange::from)),
+        }
+    }
+}
+
+/// A time range covered by the data of a stage.
+///
+/// # Fields
+///
+/// * `start` - The first date of the data.
+/// * `end` - The last date of the data.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct TimeRange {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl From<(DateTime<Utc>, DateTime<Utc>)> for TimeRange {
+    fn from((start, end): (DateTime<Utc>, DateTime<Utc>)) -> Self {
+        TimeRange { start, end }
+    }
+}
+
+/// State of a stage after its last successful run.
+///
+/// # Fields
+///
+/// * `parameters` - The parameters the stage was run with.
+/// * `inputs` - The metadata fingerprint of the inputs of the stage.
+/// * `outputs` - The metadata fingerprint of the outputs of the stage.
+/// * `range` - The time range of the data produced by the stage, if known.
+/// * `completed_at` - The date the stage completed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StageRecord {
+    pub parameters: String,
+    pub inputs: String,
+    pub outputs: String,
+    pub range: Option<TimeRange>,
+    pub completed_at: DateTime<Utc>,
+}
+
+/// Manifest of the pipeline, stored in `pipeline_manifest.json`.
+///
+/// A stage is only recorded once it has succeeded, so that a failed stage is run again on the
+/// next run of the pipeline.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Manifest {
+    pub stages: BTreeMap<Stage, StageRecord>,
+}
+
+impl Manifest {
+    /// Reads the manifest from the data directory of a network, or returns an empty one if the
+    /// pipeline has never run.
+    fn read(dir: &Path) -> Manifest {
+        let path = dir.join(PIPELINE_MANIFEST);
+        if !path.exists() {
+            return Manifest::default();
+        }
+        let reader = BufReader::new(File::open(&path).unwrap());
+        serde_json::from_reader(reader).unwrap_or_else(|e| {
+            error!(
+                "❌ Invalid {}, running every stage: {}",
+                PIPELINE_MANIFEST, e
+            );
+            Manifest::default()
+        })
+    }
+
+    /// Writes the manifest to the data directory of a network.
+    fn write(&self, dir: &Path) {
+        let writer = BufWriter::new(File::create(dir.join(PIPELINE_MANIFEST)).unwrap());
+        serde_json::to_writer_pretty(writer, self).unwrap();
+    }
+}
+
+/// Fingerprints a set of files and directories from their paths, sizes and modification dates.
+///
+/// This is a metadata fingerprint: the contents are not read, as the Velov history weighs several
+/// gigabytes, so a file rewritten with the same size and modification date goes unnoticed.
+/// Missing paths are fingerprinted as such, so that creating them makes the fingerprint change.
+///
+/// # Arguments
+///
+/// * `dir` - The data directory of the network, the paths being fingerprinted relatively to it.
+/// * `paths` - The files and directories to fingerprint.
+///
+/// # Returns
+///
+/// * The hexadecimal SHA-256 of the files metadata.
+fn metadata_fingerprint(dir: &Path, paths: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for path in paths {
+        let mut files = Vec::new();
+        collect_files(&dir.join(path), &mut files);
+        if files.is_empty() {
+            hasher.update(format!("{} missing\n", path));
+        }
+        files.sort();
+        for file in files {
+            let metadata = fs::metadata(&file).unwrap();
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|m| m.as_nanos())
+                .unwrap_or_default();
+            hasher.update(format!(
+                "{} {} {}\n",
+                file.strip_prefix(dir).unwrap_or(&file).display(),
+                metadata.len(),
+                modified
+            ));
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Lists the files of a path recursively, ignoring the `.gitkeep` placeholders.
+fn collect_files(path: &Path, files: &mut Vec<std::path::PathBuf>) {
+    if path.is_dir() {
+        for entry in fs::read_dir(path).unwrap() {
+            collect_files(&entry.unwrap().path(), files);
+        }
+    } else if path.exists() && path.file_name().is_some_and(|name| name != ".gitkeep") {
+        files.push(path.to_path_buf());
+    }
+}
+
+/// Runs the stages of the data pipeline which are not up to date.
+///
+/// A stage is up to date if it has already succeeded with the same parameters, and neither its
+/// inputs nor its outputs changed since. Remote stages have no input to fingerprint: they only run again
+/// if their parameters or outputs changed, or if `refresh` is set. As the inputs of a stage are
+/// the outputs of the previous ones, running a stage makes the following ones out of date.
+///
+/// The manifest is written after each stage, so that the pipeline resumes from the failed stage
+/// when it is run again.
+///
+/// # Arguments
+///
+/// * `args` - The command-line arguments, for the parameters of the stages.
+/// * `network` - The network, whose data directory holds the data files and the manifest.
+///
+/// # Returns
+///
+/// * An error message if a stage failed.
+///
+/// # Examples
+///
+/// ```rust
+/// run_pipeline(&args, &network).await?;
+/// ```
+pub async fn run_pipeline(args: &Args, network: &Network) -> Result<(), &'static str> {
+    let dir = network.data_dir.as_path();
+    let mut manifest = Manifest::read(dir);
+
+    for stage in STAGES {
+        let parameters = stage.parameters(args, network);
+        let inputs = metadata_fingerprint(dir, stage.inputs());
+        let up_to_date = manifest.stages.get(&stage).is_some_and(|record| {
+            record.parameters == parameters
+                && record.inputs == inputs
+                && record.outputs == metadata_fingerprint(dir, stage.outputs())
+                && !(stage.is_remote() && args.refresh)
+        });
+        if up_to_date {
+            info!("⏭️ {:?} is up to date", stage);
+            continue;
+        }
+
+        info!("▶️ Running {:?}..", stage);
+        let range = stage.run(args, network).await.inspect_err(|e| {
+            error!("❌ {:?} failed: {}", stage, e);
+        })?;
+
+        manifest.stages.insert(
+            stage,
+            StageRecord {
+                parameters,
+                inputs,
+                outputs: metadata_fingerprint(dir, stage.outputs()),
+                range,
+                completed_at: Utc::now(),
+            },
+        );
+        manifest.write(dir);
+        info!("✅ {:?} done!", stage);
+    }
+
+    info!("✅ Pipeline up to date!");
+    Ok(())
+}
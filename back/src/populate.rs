@@ -1,20 +1,102 @@
+use crate::config::{Network, VelovSourceKind};
+use crate::gbfs::download_gbfs_stations;
+use crate::http::get_text;
 use crate::models::DetailedStation;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tracing::info;
+use tracing::{error, info};
 
-const URL: &str = "https://data.grandlyon.com/geoserver/metropole-de-lyon/ows?SERVICE=WFS&VERSION=2.0.0&request=GetFeature&typename=metropole-de-lyon:pvo_patrimoine_voirie.pvostationvelov&outputFormat=application/json&SRSNAME=EPSG:4171&sortBy=gid";
+/// Populates the database with the Velov station data of a network, from the Grand Lyon open
+/// data or from the GBFS feed depending on the configured source.
+///
+/// The stations of the network are replaced in a single transaction, so that they are kept if
+/// the insertion fails.
+///
+/// # Arguments
+///
+/// * `network` - The network, with its Velov sources.
+///
+/// # Returns
+///
+/// * An error message if the stations cannot be downloaded or stored.
+pub async fn populate(network: &Network) -> Result<(), &'static str> {
+    info!(
+        "🚴 Populating the database with the stations of {} 🌐",
+        network.id
+    );
+
+    let stations = match network.velov.kind {
+        VelovSourceKind::GrandLyon => download_grand_lyon_stations(network).await?,
+        VelovSourceKind::Gbfs => download_gbfs_stations(network).await?,
+    };
+
+    // insert the stations into the database
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        error!("❌ DATABASE_URL must be set");
+        return Err("Database URL must be set");
+    };
+    let connection = &mut match diesel::pg::PgConnection::establish(&database_url) {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("❌ Error connecting to {}: {}", database_url, e);
+            return Err("Error connecting to the database");
+        }
+    };
+
+    let inserted = connection.transaction(|connection| {
+        // delete all stations of the network first
+        diesel::delete(
+            crate::schema::station::table.filter(crate::schema::station::network.eq(&network.id)),
+        )
+        .execute(connection)?;
 
-/// Populates the database with Velov station data.
-pub async fn populate() {
-    info!("🚴 Populating the database 🌐");
+        // insert the new stations
+        diesel::insert_into(crate::schema::station::table)
+            .values(&stations)
+            .execute(connection)
+    });
+    match inserted {
+        Ok(count) => {
+            info!("✅ {} stations inserted!", count);
+            Ok(())
+        }
+        Err(e) => {
+            error!("❌ Error replacing the stations: {}", e);
+            Err("Error replacing the stations")
+        }
+    }
+}
 
-    let response = reqwest::get(URL).await.unwrap().text().await.unwrap();
-    let raw_stations: StationsData = serde_json::from_str(&response).unwrap();
+/// Downloads the stations of the Grand Lyon open data.
+///
+/// # Arguments
+///
+/// * `network` - The network of the stations, with its Velov sources.
+///
+/// # Returns
+///
+/// * The stations of the network, or an error message if they cannot be downloaded or parsed.
+async fn download_grand_lyon_stations(
+    network: &Network,
+) -> Result<Vec<DetailedStation>, &'static str> {
+    let response = match get_text(&network.velov.stations_url).await {
+        Ok(text) => text,
+        Err(e) => {
+            error!("❌ Failed to download the stations: {}", e);
+            return Err("Failed to download the stations");
+        }
+    };
+    let raw_stations: StationsData = match serde_json::from_str(&response) {
+        Ok(stations) => stations,
+        Err(e) => {
+            error!("❌ Failed to parse the stations: {}", e);
+            return Err("Failed to parse the stations");
+        }
+    };
 
     // convert the stations raw data into Stations struct
-    let stations: Vec<DetailedStation> = raw_stations
+    Ok(raw_stations
         .features
         .iter()
         .map(|station| DetailedStation {
@@ -25,24 +107,10 @@ pub async fn populate() {
             adress: station.properties.adresse1.clone(),
             area: station.properties.commune.clone(),
             capacity: station.properties.nbbornettes,
+            cluster: None,
+            network: network.id.clone(),
         })
-        .collect();
-
-    // insert the stations into the database
-    let database_url = std::env::var("DATABASE_URL").expect("Database URL must be set");
-    let connection = &mut diesel::pg::PgConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
-
-    // delete all stations first
-    diesel::delete(crate::schema::station::table)
-        .execute(connection)
-        .expect("Error deleting stations");
-
-    // insert the new stations
-    diesel::insert_into(crate::schema::station::table)
-        .values(&stations)
-        .execute(connection)
-        .expect("Error inserting stations");
+        .collect())
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -0,0 +1,535 @@
+use crate::config::Network;
+use crate::downloader::Value;
+use crate::establish_connection;
+use crate::learning::{list_files, UsefulData, VELOV_TRAINING_DATA_DIR};
+use crate::resample::Gap;
+use crate::schema::station;
+use crate::storage::read_station;
+use chrono::{DateTime, Duration, Utc};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use log::{info, warn};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// File where the quality report is written as JSON.
+pub const QUALITY_REPORT_JSON: &str = "quality_report.json";
+/// File where the quality report is written as HTML.
+pub const QUALITY_REPORT_HTML: &str = "quality_report.html";
+/// Number of raw Velov files read at once by the quality report.
+const QUALITY_BATCH_SIZE: usize = 64;
+/// Temporary directory where the horodates of the raw snapshots are partitioned by station.
+const QUALITY_PARTITIONS_DIR: &str = "quality_partitions";
+
+/// Plausible temperatures, in °C.
+const TEMPERATURE_RANGE: RangeInclusive<f32> = -30.0..=50.0;
+/// Plausible hourly precipitations, in mm.
+const PRECIPITATION_RANGE: RangeInclusive<f32> = 0.0..=100.0;
+/// Plausible wind speeds, in km/h.
+const WIND_SPEED_RANGE: RangeInclusive<f32> = 0.0..=200.0;
+
+/// Quality of the data of a station.
+///
+/// # Fields
+///
+/// * `id` - The ID of the station.
+/// * `snapshots` - The number of raw snapshots in `velov_datas`.
+/// * `duplicates` - The number of raw snapshots with the date of a previous one.
+/// * `invalid_capacity` - The number of raw snapshots whose bikes and stands do not sum up to
+///   the capacity.
+/// * `training_points` - The number of points of the filtered data.
+/// * `coverage` - The percentage of the grid points of the filtered data which are present, if
+///   the station has filtered data.
+/// * `gaps` - The gaps of the filtered data longer than the threshold.
+/// * `merged_points` - The number of points of the merged data.
+/// * `invalid_weather` - The number of merged points with an out-of-range weather value.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StationQuality {
+    pub id: u32,
+    pub snapshots: usize,
+    pub duplicates: usize,
+    pub invalid_capacity: usize,
+    pub training_points: usize,
+    pub coverage: Option<f32>,
+    pub gaps: Vec<Gap>,
+    pub merged_points: usize,
+    pub invalid_weather: usize,
+}
+
+/// A data file which could not be read.
+///
+/// # Fields
+///
+/// * `path` - The path of the file.
+/// * `error` - The reason the file could not be read.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnreadableFile {
+    pub path: String,
+    pub error: String,
+}
+
+impl UnreadableFile {
+    fn new(path: &Path, error: impl std::fmt::Display) -> Self {
+        UnreadableFile {
+            path: path.display().to_string(),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// Quality report of the data, written by the `quality-report` command.
+///
+/// # Fields
+///
+/// * `generated_at` - The date the report was generated.
+/// * `resample_step` - The step of the grid of the filtered data, in minutes.
+/// * `gap_threshold` - The minimum duration of the reported gaps, in minutes.
+/// * `stations` - The quality of the data of each station.
+/// * `missing_from_table` - The stations having a history but missing from the `station` table.
+/// * `missing_from_history` - The stations of the `station` table without any history.
+/// * `unreadable_files` - The raw, filtered and merged files which could not be read, left out of
+///   the report.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QualityReport {
+    pub generated_at: DateTime<Utc>,
+    pub resample_step: u32,
+    pub gap_threshold: u32,
+    pub stations: Vec<StationQuality>,
+    pub missing_from_table: Vec<u32>,
+    pub missing_from_history: Vec<u32>,
+    pub unreadable_files: Vec<UnreadableFile>,
+}
+
+/// Scans the raw, filtered and merged data and writes a quality report to
+/// `quality_report.json` and `quality_report.html`.
+///
+/// # Arguments
+///
+/// * `network` - The network, whose data directory holds the data files and the report.
+/// * `resample_step` - The step of the grid of the filtered data, in minutes.
+/// * `gap_threshold` - The minimum duration of the reported gaps, in minutes.
+///
+/// # Examples
+///
+/// ```rust
+/// quality_report(&network, 15, 60);
+/// ```
+pub fn quality_report(network: &Network, resample_step: u32, gap_threshold: u32) {
+    info!("🩺 Building the quality report..");
+    let dir = network.data_dir.as_path();
+    let mut stations: BTreeMap<u32, StationQuality> = BTreeMap::new();
+    let mut unreadable_files = Vec::new();
+
+    scan_raw_data(
+        &dir.join("velov_datas"),
+        &dir.join(QUALITY_PARTITIONS_DIR),
+        &mut stations,
+        &mut unreadable_files,
+    );
+
+    let step = Duration::minutes(resample_step.max(1) as i64);
+    let threshold = Duration::minutes(gap_threshold as i64);
+    let training_dir = dir.join(VELOV_TRAINING_DATA_DIR);
+    for (id, points, coverage, gaps) in
+        scan_training_data(&training_dir, step, threshold, &mut unreadable_files)
+    {
+        let quality = station_entry(&mut stations, id);
+        quality.training_points = points;
+        quality.coverage = Some(coverage);
+        quality.gaps = gaps;
+    }
+
+    for (id, points, invalid_weather) in
+        scan_merged_data(&dir.join("merged_data"), &mut unreadable_files)
+    {
+        let quality = station_entry(&mut stations, id);
+        quality.merged_points = points;
+        quality.invalid_weather = invalid_weather;
+    }
+
+    let table: BTreeSet<u32> = station::table
+        .select(station::id)
+        .filter(station::network.eq(&network.id))
+        .load::<i32>(&mut establish_connection())
+        .unwrap_or_else(|e| {
+            warn!("❌ Failed to read the station table: {}", e);
+            Vec::new()
+        })
+        .into_iter()
+        .map(|id| id as u32)
+        .collect();
+
+    let report = QualityReport {
+        generated_at: Utc::now(),
+        resample_step,
+        gap_threshold,
+        missing_from_table: stations
+            .keys()
+            .filter(|id| !table.contains(id))
+            .copied()
+            .collect(),
+        missing_from_history: table
+            .iter()
+            .filter(|id| !stations.contains_key(id))
+            .copied()
+            .collect(),
+        stations: stations.into_values().collect(),
+        unreadable_files,
+    };
+
+    info!(
+        "📊 {} stations, {} duplicates, {} invalid capacities, {} long gaps, {} invalid weather values",
+        report.stations.len(),
+        report.stations.iter().map(|s| s.duplicates).sum::<usize>(),
+        report.stations.iter().map(|s| s.invalid_capacity).sum::<usize>(),
+        report.stations.iter().map(|s| s.gaps.len()).sum::<usize>(),
+        report.stations.iter().map(|s| s.invalid_weather).sum::<usize>()
+    );
+    info!(
+        "🚉 {} stations missing from the station table, {} without history",
+        report.missing_from_table.len(),
+        report.missing_from_history.len()
+    );
+    for file in &report.unreadable_files {
+        warn!("❌ Failed to read {}: {}", file.path, file.error);
+    }
+
+    let (json, html) = (dir.join(QUALITY_REPORT_JSON), dir.join(QUALITY_REPORT_HTML));
+    let writer = BufWriter::new(File::create(&json).unwrap());
+    serde_json::to_writer_pretty(writer, &report).unwrap();
+    let mut writer = BufWriter::new(File::create(&html).unwrap());
+    writer.write_all(report_html(&report).as_bytes()).unwrap();
+    info!("✅ {} and {} written!", json.display(), html.display());
+}
+
+/// Returns the quality of a station, created empty if the station is not known yet.
+fn station_entry(stations: &mut BTreeMap<u32, StationQuality>, id: u32) -> &mut StationQuality {
+    stations.entry(id).or_insert_with(|| StationQuality {
+        id,
+        ..Default::default()
+    })
+}
+
+/// Reads a JSON file, or describes why it cannot be read.
+fn read_json<T: DeserializeOwned>(file: &Path) -> Result<T, UnreadableFile> {
+    let reader = BufReader::new(File::open(file).map_err(|e| UnreadableFile::new(file, e))?);
+    serde_json::from_reader(reader).map_err(|e| UnreadableFile::new(file, e))
+}
+
+/// Counts the snapshots, duplicates and invalid capacities of each station in the raw data.
+///
+/// The horodates of each batch are appended to a temporary file per station, and the duplicates
+/// are then counted station by station from the sorted horodates, so that the memory stays
+/// bounded by the size of a batch and the history of a station.
+///
+/// # Arguments
+///
+/// * `path` - The directory of the raw Velov files.
+/// * `partitions` - The temporary directory of the horodates of each station.
+/// * `stations` - The quality of each station, updated in place.
+/// * `unreadable_files` - The files which could not be read, appended to.
+fn scan_raw_data(
+    path: &Path,
+    partitions: &Path,
+    stations: &mut BTreeMap<u32, StationQuality>,
+    unreadable_files: &mut Vec<UnreadableFile>,
+) {
+    if !path.exists() {
+        warn!("⏭️ No {} directory", path.display());
+        return;
+    }
+    let files = list_files(path);
+    if partitions.exists() {
+        fs::remove_dir_all(partitions).unwrap();
+    }
+    fs::create_dir_all(partitions).unwrap();
+
+    for batch in files.chunks(QUALITY_BATCH_SIZE) {
+        let read: Vec<Result<Vec<Value>, UnreadableFile>> =
+            batch.par_iter().map(|file| read_json(file)).collect();
+
+        let mut horodates: HashMap<u32, Vec<u8>> = HashMap::new();
+        for values in read {
+            let values = match values {
+                Ok(values) => values,
+                Err(file) => {
+                    unreadable_files.push(file);
+                    continue;
+                }
+            };
+            for value in values {
+                let id = value.number as u32;
+                let quality = station_entry(stations, id);
+                quality.snapshots += 1;
+                let stands = &value.total_stands;
+                if stands.capacity as u32
+                    != stands.availabilities.bikes as u32 + stands.availabilities.stands as u32
+                {
+                    quality.invalid_capacity += 1;
+                }
+                horodates
+                    .entry(id)
+                    .or_default()
+                    .extend_from_slice(&value.horodate.timestamp().to_le_bytes());
+            }
+        }
+
+        // Each station is appended to its own partition, so the writes never overlap
+        horodates.par_iter().for_each(|(id, bytes)| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(partitions.join(format!("{}.bin", id)))
+                .and_then(|mut file| file.write_all(bytes))
+                .unwrap();
+        });
+    }
+
+    let duplicates: Vec<(u32, usize)> = stations
+        .keys()
+        .copied()
+        .collect::<Vec<_>>()
+        .par_iter()
+        .filter_map(|id| {
+            let bytes = fs::read(partitions.join(format!("{}.bin", id))).ok()?;
+            let mut horodates: Vec<i64> = bytes
+                .chunks_exact(8)
+                .map(|chunk| i64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect();
+            horodates.sort_unstable();
+            let total = horodates.len();
+            horodates.dedup();
+            Some((*id, total - horodates.len()))
+        })
+        .collect();
+    for (id, count) in duplicates {
+        station_entry(stations, id).duplicates = count;
+    }
+    fs::remove_dir_all(partitions).unwrap();
+}
+
+/// Measures the coverage and the long gaps of each station in the filtered data.
+///
+/// # Arguments
+///
+/// * `path` - The directory of the filtered data.
+/// * `step` - The step of the grid of the filtered data.
+/// * `threshold` - The minimum duration of the reported gaps.
+/// * `unreadable_files` - The files which could not be read, appended to.
+///
+/// # Returns
+///
+/// * The ID, points, coverage and long gaps of each station.
+fn scan_training_data(
+    path: &Path,
+    step: Duration,
+    threshold: Duration,
+    unreadable_files: &mut Vec<UnreadableFile>,
+) -> Vec<(u32, usize, f32, Vec<Gap>)> {
+    if !path.exists() {
+        warn!("⏭️ No {} directory", path.display());
+        return Vec::new();
+    }
+    let scanned: Vec<Result<Vec<UsefulData>, UnreadableFile>> = list_files(path)
+        .par_iter()
+        .map(|file| read_json(file))
+        .collect();
+    let points: Vec<Vec<UsefulData>> = scanned
+        .into_iter()
+        .filter_map(|points| points.map_err(|file| unreadable_files.push(file)).ok())
+        .collect();
+
+    points
+        .par_iter()
+        .filter_map(|points| {
+            let (first, last) = (points.first()?, points.last()?);
+
+            let expected = (last.date - first.date).num_seconds() / step.num_seconds() + 1;
+            let coverage = points.len() as f32 / expected.max(1) as f32 * 100.0;
+            let gaps = points
+                .windows(2)
+                .filter(|pair| pair[1].date - pair[0].date > threshold)
+                .map(|pair| Gap {
+                    start: pair[0].date + step,
+                    end: pair[1].date - step,
+                })
+                .collect();
+
+            Some((first.id, points.len(), coverage, gaps))
+        })
+        .collect()
+}
+
+/// Counts the points and the out-of-range weather values of each station in the merged data.
+///
+/// # Arguments
+///
+/// * `path` - The directory of the merged data.
+/// * `unreadable_files` - The files which could not be read, appended to.
+///
+/// # Returns
+///
+/// * The ID, points and invalid weather values of each station.
+fn scan_merged_data(
+    path: &Path,
+    unreadable_files: &mut Vec<UnreadableFile>,
+) -> Vec<(u32, usize, usize)> {
+    if !path.exists() {
+        warn!("⏭️ No {} directory", path.display());
+        return Vec::new();
+    }
+    let scanned: Vec<_> = list_files(path)
+        .par_iter()
+        .map(|file| {
+            let station = read_station(file).map_err(|error| UnreadableFile {
+                path: file.display().to_string(),
+                error,
+            })?;
+            Ok(station.map(|(id, data)| {
+                let invalid = data
+                    .iter()
+                    .filter(|d| {
+                        !TEMPERATURE_RANGE.contains(&d.temperature)
+                            || !PRECIPITATION_RANGE.contains(&d.precipitation)
+                            || !WIND_SPEED_RANGE.contains(&d.wind_speed)
+                    })
+                    .count();
+                (id, data.len(), invalid)
+            }))
+        })
+        .collect();
+    scanned
+        .into_iter()
+        .filter_map(|station| station.map_err(|file| unreadable_files.push(file)).ok()?)
+        .collect()
+}
+
+/// Renders the quality report as a standalone HTML page.
+fn report_html(report: &QualityReport) -> String {
+    let rows: String = report
+        .stations
+        .iter()
+        .map(|s| {
+            let flagged = s.duplicates > 0
+                || s.invalid_capacity > 0
+                || !s.gaps.is_empty()
+                || s.invalid_weather > 0;
+            format!(
+                "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                if flagged { " class=\"flagged\"" } else { "" },
+                s.id,
+                s.snapshots,
+                s.duplicates,
+                s.invalid_capacity,
+                s.training_points,
+                s.coverage.map(|c| format!("{:.1}%", c)).unwrap_or("-".to_string()),
+                s.gaps.len(),
+                s.merged_points,
+                s.invalid_weather
+            )
+        })
+        .collect();
+    let list = |ids: &[u32]| {
+        if ids.is_empty() {
+            "none".to_string()
+        } else {
+            ids.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    };
+    let unreadable: String = report
+        .unreadable_files
+        .iter()
+        .map(|file| format!("<li>{}: {}</li>\n", file.path, file.error))
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>VELOVITE data quality report</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: right; }}
+tr.flagged {{ background: #fde8e8; }}
+</style>
+</head>
+<body>
+<h1>🩺 Data quality report</h1>
+<p>Generated at {}, with a {} minutes grid and gaps longer than {} minutes.</p>
+<p>Stations missing from the station table: {}</p>
+<p>Stations without history: {}</p>
+<p>Unreadable files: {}</p>
+<ul>
+{}</ul>
+<table>
+<tr><th>Station</th><th>Snapshots</th><th>Duplicates</th><th>Invalid capacities</th><th>Filtered points</th><th>Coverage</th><th>Long gaps</th><th>Merged points</th><th>Invalid weather</th></tr>
+{}</table>
+</body>
+</html>
+"#,
+        report.generated_at,
+        report.resample_step,
+        report.gap_threshold,
+        list(&report.missing_from_table),
+        list(&report.missing_from_history),
+        report.unreadable_files.len(),
+        unreadable,
+        rows
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(number: u16, minute: i64) -> Value {
+        Value {
+            horodate: DateTime::from_timestamp(1_700_000_000 + 60 * minute, 0).unwrap(),
+            number,
+            ..Value::default()
+        }
+    }
+
+    #[test]
+    fn counts_duplicates_across_files_and_reports_unreadable_ones() {
+        let dir = std::env::temp_dir().join(format!("velovite-quality-{}", std::process::id()));
+        let raw = dir.join("raw");
+        fs::create_dir_all(&raw).unwrap();
+        let write = |name: &str, values: &[Value]| {
+            fs::write(raw.join(name), serde_json::to_string(values).unwrap()).unwrap();
+        };
+        write("a.json", &[value(1, 0), value(1, 1), value(2, 0)]);
+        write(
+            "b.json",
+            &[value(1, 1), value(1, 2), value(2, 0), value(2, 0)],
+        );
+        fs::write(raw.join("c.json"), "[{").unwrap();
+
+        let mut stations = BTreeMap::new();
+        let mut unreadable_files = Vec::new();
+        scan_raw_data(
+            &raw,
+            &dir.join("partitions"),
+            &mut stations,
+            &mut unreadable_files,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(stations[&1].snapshots, 4);
+        assert_eq!(stations[&1].duplicates, 1);
+        assert_eq!(stations[&2].snapshots, 3);
+        assert_eq!(stations[&2].duplicates, 2);
+        assert_eq!(unreadable_files.len(), 1);
+        assert!(unreadable_files[0].path.ends_with("c.json"));
+    }
+}
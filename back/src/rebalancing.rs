@@ -0,0 +1,138 @@
+use crate::utils::haversine;
+use serde::{Deserialize, Serialize};
+
+/// Minimum number of bikes and of free stands a station should keep during the window.
+pub const MIN_AVAILABLE: u32 = 2;
+
+/// Predicted outlook of a station over the rebalancing window.
+///
+/// # Fields
+///
+/// * `id` - The ID of the station.
+/// * `latitude` - The latitude coordinate of the station.
+/// * `longitude` - The longitude coordinate of the station.
+/// * `capacity` - The current capacity of the station.
+/// * `min_bikes` - The lowest number of bikes predicted during the window.
+/// * `min_stands` - The lowest number of free stands predicted during the window.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StationOutlook {
+    pub id: u32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub capacity: u32,
+    pub min_bikes: u32,
+    pub min_stands: u32,
+}
+
+impl StationOutlook {
+    /// Number of bikes missing for the station to keep `MIN_AVAILABLE` bikes.
+    pub fn bikes_shortfall(&self) -> u32 {
+        MIN_AVAILABLE
+            .min(self.capacity)
+            .saturating_sub(self.min_bikes)
+    }
+
+    /// Number of free stands missing for the station to keep `MIN_AVAILABLE` stands.
+    pub fn stands_shortfall(&self) -> u32 {
+        MIN_AVAILABLE
+            .min(self.capacity)
+            .saturating_sub(self.min_stands)
+    }
+
+    /// Number of bikes which can be taken from the station without leaving it less than half
+    /// full, or at least enough to fix its stands shortfall.
+    fn surplus(&self) -> u32 {
+        if self.bikes_shortfall() > 0 {
+            return 0;
+        }
+        self.min_bikes
+            .saturating_sub((self.capacity / 2).max(MIN_AVAILABLE))
+            .max(self.stands_shortfall())
+    }
+}
+
+/// A proposed transfer of bikes between two stations.
+///
+/// # Fields
+///
+/// * `source` - The ID of the station to take the bikes from.
+/// * `target` - The ID of the station to bring the bikes to.
+/// * `quantity` - The number of bikes to move.
+/// * `distance` - The distance between the two stations, in kilometres.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Transfer {
+    pub source: u32,
+    pub target: u32,
+    pub quantity: u32,
+    pub distance: f64,
+}
+
+/// Proposes bike transfers to cover the predicted shortfalls of the empty stations.
+///
+/// This is a greedy solver: all the pairs of stations able to give bikes and stations missing
+/// bikes are sorted so that full stations are drained first, then by distance, and each pair
+/// moves as many bikes as it can.
+///
+/// # Arguments
+///
+/// * `stations` - The outlook of every station.
+///
+/// # Returns
+///
+/// * The proposed transfers, from the most to the least urgent.
+pub fn plan_transfers(stations: &[StationOutlook]) -> Vec<Transfer> {
+    let mut supplies: Vec<(usize, u32)> = stations
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (i, s.surplus()))
+        .filter(|(_, surplus)| *surplus > 0)
+        .collect();
+    let mut demands: Vec<(usize, u32)> = stations
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (i, s.bikes_shortfall()))
+        .filter(|(_, shortfall)| *shortfall > 0)
+        .collect();
+
+    let mut pairs: Vec<(bool, f64, usize, usize)> = supplies
+        .iter()
+        .enumerate()
+        .flat_map(|(supply, (source, _))| {
+            let source_station = &stations[*source];
+            demands
+                .iter()
+                .enumerate()
+                .map(move |(demand, (target, _))| {
+                    let target_station = &stations[*target];
+                    (
+                        source_station.stands_shortfall() == 0,
+                        haversine(
+                            (source_station.latitude, source_station.longitude),
+                            (target_station.latitude, target_station.longitude),
+                        ),
+                        supply,
+                        demand,
+                    )
+                })
+        })
+        .collect();
+    pairs.sort_by(|a, b| (a.0, a.1).partial_cmp(&(b.0, b.1)).unwrap());
+
+    let mut transfers = Vec::new();
+    for (_, distance, supply, demand) in pairs {
+        let quantity = supplies[supply].1.min(demands[demand].1);
+        if quantity == 0 {
+            continue;
+        }
+        supplies[supply].1 -= quantity;
+        demands[demand].1 -= quantity;
+        transfers.push(Transfer {
+            source: stations[supplies[supply].0].id,
+            target: stations[demands[demand].0].id,
+            quantity,
+            distance,
+        });
+    }
+
+    transfers
+}
@@ -0,0 +1,278 @@
+use crate::learning::UsefulData;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// File where the coverage of each station is reported.
+pub const COVERAGE_FILE: &str = "coverage.json";
+/// Longest gap between two snapshots filled by interpolation, in minutes.
+pub const MAX_INTERPOLATED_GAP_MINUTES: i64 = 60;
+
+/// Reports the coverage of the resampled stations and writes it to `coverage.json`.
+///
+/// # Arguments
+///
+/// * `dir` - The data directory of the network.
+/// * `coverages` - The coverage of each station, sorted by station in place.
+///
+/// # Examples
+///
+/// ```rust
+/// write_coverage(Path::new("."), &mut coverages);
+/// ```
+pub fn write_coverage(dir: &Path, coverages: &mut [StationCoverage]) {
+    coverages.sort_by_key(|c| c.id);
+
+    let expected: usize = coverages.iter().map(|c| c.expected).sum();
+    let observed: usize = coverages.iter().map(|c| c.observed).sum();
+    let interpolated: usize = coverages.iter().map(|c| c.interpolated).sum();
+    let missing: usize = coverages.iter().map(|c| c.missing).sum();
+    info!(
+        "📊 Grid points : {} observed, {} interpolated, {} missing ({}% coverage)",
+        observed,
+        interpolated,
+        missing,
+        ((observed + interpolated) as f32 / expected.max(1) as f32) * 100.0
+    );
+
+    let path = dir.join(COVERAGE_FILE);
+    let writer = BufWriter::new(File::create(&path).unwrap());
+    serde_json::to_writer(writer, &coverages).unwrap();
+    info!("✅ {} written!", path.display());
+}
+
+/// A period without usable snapshots.
+///
+/// # Fields
+///
+/// * `start` - The first missing grid point.
+/// * `end` - The last missing grid point.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Gap {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Coverage of the resampled history of a station.
+///
+/// # Fields
+///
+/// * `id` - The ID of the station.
+/// * `first` - The first grid point of the history.
+/// * `last` - The last grid point of the history.
+/// * `expected` - The number of grid points between `first` and `last`.
+/// * `observed` - The number of grid points with a snapshot within half a step.
+/// * `interpolated` - The number of grid points interpolated over a short gap.
+/// * `missing` - The number of grid points within long gaps.
+/// * `coverage` - The percentage of grid points which are observed or interpolated.
+/// * `gaps` - The long gaps, left missing.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StationCoverage {
+    pub id: u32,
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+    pub expected: usize,
+    pub observed: usize,
+    pub interpolated: usize,
+    pub missing: usize,
+    pub coverage: f32,
+    pub gaps: Vec<Gap>,
+}
+
+/// Resamples the snapshots of a station to a regular grid.
+///
+/// The grid runs from the grid point nearest to the first snapshot to the grid point nearest to
+/// the last one, so a station observed for less than a step still has a grid point.
+///
+/// Each grid point takes the values of the snapshot within half a step of it. Otherwise, if the
+/// surrounding snapshots are at most `max_gap` apart, the bikes are linearly interpolated between
+/// them. Otherwise the grid point is missing: it is left out of the series and reported as part
+/// of a gap.
+///
+/// # Arguments
+///
+/// * `snapshots` - The snapshots of a single station, sorted by date without duplicates.
+/// * `step` - The step of the grid.
+/// * `max_gap` - The longest gap between two snapshots filled by interpolation.
+///
+/// # Returns
+///
+/// * The resampled snapshots and the coverage of the station, or `None` if there is no snapshot.
+pub fn resample_station(
+    snapshots: &[UsefulData],
+    step: Duration,
+    max_gap: Duration,
+) -> Option<(Vec<UsefulData>, StationCoverage)> {
+    let first_snapshot = snapshots.first()?;
+    let last_snapshot = snapshots.last()?;
+
+    let mut time = first_snapshot.date.duration_round(step).unwrap();
+    let last = last_snapshot.date.duration_round(step).unwrap();
+
+    let mut coverage = StationCoverage {
+        id: first_snapshot.id,
+        first: time,
+        last,
+        expected: 0,
+        observed: 0,
+        interpolated: 0,
+        missing: 0,
+        coverage: 0.0,
+        gaps: Vec::new(),
+    };
+    let mut resampled = Vec::new();
+    // Index of the last snapshot at or before the current grid point
+    let mut previous = 0;
+
+    while time <= last {
+        while previous + 1 < snapshots.len() && snapshots[previous + 1].date <= time {
+            previous += 1;
+        }
+        let before = &snapshots[previous];
+        let after = snapshots.get(previous + 1).unwrap_or(before);
+        coverage.expected += 1;
+
+        let nearest = if time - before.date <= after.date - time {
+            before
+        } else {
+            after
+        };
+        if (nearest.date - time).abs() <= step / 2 {
+            coverage.observed += 1;
+            resampled.push(UsefulData {
+                date: time,
+                ..nearest.clone()
+            });
+        } else if after.date - before.date <= max_gap && after.date > before.date {
+            coverage.interpolated += 1;
+            let ratio = (time - before.date).num_seconds() as f32
+                / (after.date - before.date).num_seconds() as f32;
+            let capacity = nearest.capacity;
+            let bikes = (before.bikes as f32 + (after.bikes as f32 - before.bikes as f32) * ratio)
+                .round() as u32;
+            let bikes = bikes.min(capacity);
+            resampled.push(UsefulData {
+                id: before.id,
+                date: time,
+                capacity,
+                bikes,
+                stands: capacity - bikes,
+            });
+        } else {
+            coverage.missing += 1;
+            match coverage.gaps.last_mut() {
+                Some(gap) if gap.end + step == time => gap.end = time,
+                _ => coverage.gaps.push(Gap {
+                    start: time,
+                    end: time,
+                }),
+            }
+        }
+
+        time += step;
+    }
+
+    coverage.coverage = if coverage.expected > 0 {
+        (coverage.observed + coverage.interpolated) as f32 / coverage.expected as f32 * 100.0
+    } else {
+        0.0
+    };
+
+    Some((resampled, coverage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(date: &str, bikes: u32) -> UsefulData {
+        UsefulData {
+            id: 1,
+            date: date.parse().unwrap(),
+            capacity: 20,
+            bikes,
+            stands: 20 - bikes,
+        }
+    }
+
+    fn resample(snapshots: &[UsefulData]) -> (Vec<UsefulData>, StationCoverage) {
+        resample_station(
+            snapshots,
+            Duration::minutes(15),
+            Duration::minutes(MAX_INTERPOLATED_GAP_MINUTES),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn interpolates_short_gaps() {
+        let (resampled, coverage) = resample(&[
+            snapshot("2024-06-01T10:01:00Z", 0),
+            snapshot("2024-06-01T10:46:00Z", 9),
+        ]);
+
+        let bikes: Vec<u32> = resampled.iter().map(|s| s.bikes).collect();
+        assert_eq!(bikes, vec![0, 3, 6, 9]);
+        assert_eq!(
+            resampled[1].date,
+            "2024-06-01T10:15:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(resampled[1].stands, 17);
+        assert_eq!(
+            (coverage.observed, coverage.interpolated, coverage.missing),
+            (2, 2, 0)
+        );
+        assert!(coverage.gaps.is_empty());
+    }
+
+    #[test]
+    fn leaves_long_gaps_missing() {
+        let (resampled, coverage) = resample(&[
+            snapshot("2024-06-01T10:00:00Z", 5),
+            snapshot("2024-06-01T11:30:00Z", 8),
+            snapshot("2024-06-01T11:45:00Z", 9),
+        ]);
+
+        assert_eq!(resampled.len(), 3);
+        assert_eq!(coverage.expected, 8);
+        assert_eq!(
+            (coverage.observed, coverage.interpolated, coverage.missing),
+            (3, 0, 5)
+        );
+        assert_eq!(coverage.gaps.len(), 1);
+        assert_eq!(
+            coverage.gaps[0].start,
+            "2024-06-01T10:15:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            coverage.gaps[0].end,
+            "2024-06-01T11:15:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(coverage.coverage, 3.0 / 8.0 * 100.0);
+    }
+
+    #[test]
+    fn covers_stations_observed_for_less_than_a_step() {
+        let (resampled, coverage) = resample(&[
+            snapshot("2024-06-01T10:08:00Z", 4),
+            snapshot("2024-06-01T10:14:00Z", 5),
+        ]);
+
+        assert_eq!(resampled.len(), 1);
+        assert_eq!(
+            resampled[0].date,
+            "2024-06-01T10:15:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(resampled[0].bikes, 5);
+        assert_eq!((coverage.expected, coverage.observed), (1, 1));
+        assert_eq!(coverage.coverage, 100.0);
+    }
+
+    #[test]
+    fn resamples_nothing_without_snapshots() {
+        assert!(resample_station(&[], Duration::minutes(15), Duration::minutes(60)).is_none());
+    }
+}
@@ -1,15 +1,59 @@
 // @generated automatically by Diesel CLI.
 
 diesel::table! {
-    forecast (id, timestamp) {
+    forecast (network, id, timestamp) {
         id -> Int4,
-        timestamp -> Timestamp,
+        timestamp -> Timestamptz,
         available -> Int4,
+        free_stands -> Int4,
+        bikes_p10 -> Int4,
+        bikes_p50 -> Int4,
+        bikes_p90 -> Int4,
+        stands_p10 -> Int4,
+        stands_p50 -> Int4,
+        stands_p90 -> Int4,
+        low_confidence -> Bool,
+        model_version -> Varchar,
+        computed_at -> Timestamptz,
+        network -> Varchar,
     }
 }
 
 diesel::table! {
-    station (id) {
+    merged_feature (network, station_id, timestamp) {
+        station_id -> Int4,
+        timestamp -> Timestamptz,
+        hour -> Int2,
+        minute -> Int2,
+        day -> Int2,
+        month -> Int2,
+        week_day -> Int2,
+        holidays -> Bool,
+        free_stands -> Int4,
+        available_bikes -> Int4,
+        precipitation -> Float4,
+        temperature -> Float4,
+        wind_speed -> Float4,
+        cluster -> Nullable<Int4>,
+        capacity -> Int4,
+        network -> Varchar,
+    }
+}
+
+diesel::table! {
+    observation (network, station_id, timestamp) {
+        station_id -> Int4,
+        timestamp -> Timestamptz,
+        capacity -> Int4,
+        bikes -> Int4,
+        stands -> Int4,
+        network -> Varchar,
+        status -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    station (network, id) {
         id -> Int4,
         name -> Varchar,
         latitude -> Float8,
@@ -17,7 +61,9 @@ diesel::table! {
         adress -> Varchar,
         area -> Varchar,
         capacity -> Int4,
+        cluster -> Nullable<Int4>,
+        network -> Varchar,
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(forecast, station,);
+diesel::allow_tables_to_appear_in_same_query!(forecast, merged_feature, observation, station,);
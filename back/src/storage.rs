@@ -0,0 +1,439 @@
+use crate::learning::MergedData;
+use chrono::DateTime;
+use log::{error, info, warn};
+use memmap2::Mmap;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of a binary merged data file.
+const MAGIC: &[u8; 4] = b"VLVM";
+/// Version of the binary format, increased on any change of the layout.
+const VERSION: u16 = 1;
+/// Flag set in the header when the columns are compressed with zstd.
+const FLAG_ZSTD: u16 = 1;
+/// Size of the header: magic, version, flags, station ID and number of rows.
+const HEADER_LEN: usize = 4 + 2 + 2 + 4 + 8;
+/// Width of a row, summed over all the columns.
+const ROW_WIDTH: usize = 6 + 2 * 2 + 3 * 4 + 4 + 2 + 8;
+/// Compression level of zstd.
+const ZSTD_LEVEL: i32 = 3;
+/// Value of the cluster column for a station which has not been clustered.
+const NO_CLUSTER: u32 = u32::MAX;
+
+/// Storage format of the merged data files.
+///
+/// # Variants
+///
+/// * `Json` - One JSON array of `MergedData` per station, in `<id>.json`.
+/// * `Binary` - One binary columnar file per station, in `<id>.bin`, mapped with mmap and decoded
+///   from the mapping.
+/// * `BinaryZstd` - The binary format with its columns compressed with zstd.
+///
+/// The binary format starts with a header of `HEADER_LEN` bytes, in little endian:
+///
+/// | Bytes | Content                                   |
+/// |-------|-------------------------------------------|
+/// | 0-3   | `VLVM`                                    |
+/// | 4-5   | The version of the format                 |
+/// | 6-7   | The flags, `1` if the columns are zstd'ed |
+/// | 8-11  | The ID of the station                     |
+/// | 12-19 | The number of rows                        |
+///
+/// It is followed by the fixed-width columns, one after the other: `hour`, `minute`, `day`,
+/// `month`, `week_day` and `holidays` as `u8`, `free_stands` and `available_bikes` as `u16`,
+/// `precipitation`, `temperature` and `wind_speed` as `f32`, `cluster` as `u32` (`u32::MAX` if
+/// none), `capacity` as `u16` and `timestamp` as `i64` seconds. Counts above `u16::MAX` cannot be
+/// encoded.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageFormat {
+    Json,
+    Binary,
+    BinaryZstd,
+}
+
+/// Source the server reads the merged data from.
+///
+/// # Variants
+///
+/// * `Files` - The files of the `merged_data` directory.
+/// * `Database` - The `merged_feature` table, filled by the `import-history` command.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DataSource {
+    Files,
+    Database,
+}
+
+impl StorageFormat {
+    /// Extension of the files of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            StorageFormat::Json => "json",
+            StorageFormat::Binary | StorageFormat::BinaryZstd => "bin",
+        }
+    }
+}
+
+/// Writes the merged data of a station.
+///
+/// The file of the station in the other formats is removed, so that a station is never stored
+/// twice in the same directory.
+///
+/// # Arguments
+///
+/// * `dir` - The directory of the merged data.
+/// * `id` - The ID of the station.
+/// * `data` - The merged data of the station.
+/// * `format` - The storage format.
+///
+/// # Examples
+///
+/// ```rust
+/// write_station(Path::new("merged_data"), 1001, &data, StorageFormat::Binary)?;
+/// ```
+pub fn write_station(
+    dir: &Path,
+    id: u32,
+    data: &[MergedData],
+    format: StorageFormat,
+) -> Result<(), String> {
+    let path = dir.join(format!("{}.{}", id, format.extension()));
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+    match format {
+        StorageFormat::Json => {
+            let file = File::create(&path).map_err(|e| error(&e))?;
+            serde_json::to_writer(BufWriter::new(file), data).map_err(|e| error(&e))?;
+        }
+        StorageFormat::Binary | StorageFormat::BinaryZstd => {
+            fs::write(
+                &path,
+                encode(id, data, format == StorageFormat::BinaryZstd)?,
+            )
+            .map_err(|e| error(&e))?;
+        }
+    }
+
+    let other = if format == StorageFormat::Json {
+        StorageFormat::Binary
+    } else {
+        StorageFormat::Json
+    };
+    let other_path = dir.join(format!("{}.{}", id, other.extension()));
+    if other_path.exists() {
+        fs::remove_file(&other_path).map_err(|e| format!("{}: {}", other_path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Reads the merged data of a station, in either format according to its extension.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file.
+///
+/// # Returns
+///
+/// * The ID of the station and its merged data, or `None` for an empty JSON file.
+pub fn read_station(path: &Path) -> Result<Option<(u32, Vec<MergedData>)>, String> {
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+    let file = File::open(path).map_err(|e| error(&e))?;
+
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        let data: Vec<MergedData> =
+            serde_json::from_reader(BufReader::new(file)).map_err(|e| error(&e))?;
+        return Ok(data.first().map(|first| first.id).map(|id| (id, data)));
+    }
+
+    // SAFETY: the merged data files are only written by `write_station`, which replaces them
+    // as a whole, and are not modified while the application reads them.
+    let mmap = unsafe { Mmap::map(&file) }.map_err(|e| error(&e))?;
+    decode(&mmap).map(Some).map_err(|e| error(&e))
+}
+
+/// Encodes the merged data of a station in the binary format.
+///
+/// # Returns
+///
+/// * The encoded file, or an error message if a count does not fit its column.
+fn encode(id: u32, data: &[MergedData], compress: bool) -> Result<Vec<u8>, String> {
+    let max = u16::MAX as u32;
+    if let Some(d) = data
+        .iter()
+        .find(|d| d.free_stands > max || d.available_bikes > max || d.capacity > max)
+    {
+        return Err(format!(
+            "the counts of station {} at {} do not fit in {} bits",
+            id,
+            d.timestamp,
+            u16::BITS
+        ));
+    }
+
+    let mut columns = Vec::with_capacity(data.len() * ROW_WIDTH);
+    put(&mut columns, data, |d| [d.hour as u8]);
+    put(&mut columns, data, |d| [d.minute as u8]);
+    put(&mut columns, data, |d| [d.day as u8]);
+    put(&mut columns, data, |d| [d.month as u8]);
+    put(&mut columns, data, |d| [d.week_day as u8]);
+    put(&mut columns, data, |d| [d.holidays as u8]);
+    put(&mut columns, data, |d| (d.free_stands as u16).to_le_bytes());
+    put(&mut columns, data, |d| {
+        (d.available_bikes as u16).to_le_bytes()
+    });
+    put(&mut columns, data, |d| d.precipitation.to_le_bytes());
+    put(&mut columns, data, |d| d.temperature.to_le_bytes());
+    put(&mut columns, data, |d| d.wind_speed.to_le_bytes());
+    put(&mut columns, data, |d| {
+        d.cluster.unwrap_or(NO_CLUSTER).to_le_bytes()
+    });
+    put(&mut columns, data, |d| (d.capacity as u16).to_le_bytes());
+    put(&mut columns, data, |d| {
+        d.timestamp.timestamp().to_le_bytes()
+    });
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + columns.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(if compress { FLAG_ZSTD } else { 0 }).to_le_bytes());
+    bytes.extend_from_slice(&id.to_le_bytes());
+    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    if compress {
+        let compressed =
+            zstd::encode_all(columns.as_slice(), ZSTD_LEVEL).map_err(|e| format!("zstd: {}", e))?;
+        bytes.extend_from_slice(&compressed);
+    } else {
+        bytes.extend_from_slice(&columns);
+    }
+    Ok(bytes)
+}
+
+/// Decodes the merged data of a station from the binary format.
+///
+/// The uncompressed columns are read in place, from the mapping of the file.
+fn decode(bytes: &[u8]) -> Result<(u32, Vec<MergedData>), String> {
+    if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+        return Err("not a binary merged data file".to_string());
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(format!("unsupported format version {}", version));
+    }
+    let flags = u16::from_le_bytes([bytes[6], bytes[7]]);
+    let id = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    let rows = u64::from_le_bytes(bytes[12..20].try_into().unwrap());
+    // The number of rows comes from the file, so the size of the columns may overflow
+    let (rows, size) = usize::try_from(rows)
+        .ok()
+        .and_then(|rows| Some((rows, rows.checked_mul(ROW_WIDTH)?)))
+        .ok_or_else(|| format!("invalid number of rows {}", rows))?;
+
+    let decompressed;
+    let columns = if flags & FLAG_ZSTD != 0 {
+        decompressed =
+            zstd::decode_all(&bytes[HEADER_LEN..]).map_err(|e| format!("zstd: {}", e))?;
+        decompressed.as_slice()
+    } else {
+        &bytes[HEADER_LEN..]
+    };
+    if columns.len() != size {
+        return Err(format!(
+            "expected {} bytes of columns, found {}",
+            size,
+            columns.len()
+        ));
+    }
+
+    let mut reader = ColumnReader { columns, rows };
+    let hour = reader.next::<1>();
+    let minute = reader.next::<1>();
+    let day = reader.next::<1>();
+    let month = reader.next::<1>();
+    let week_day = reader.next::<1>();
+    let holidays = reader.next::<1>();
+    let free_stands = reader.next::<2>();
+    let available_bikes = reader.next::<2>();
+    let precipitation = reader.next::<4>();
+    let temperature = reader.next::<4>();
+    let wind_speed = reader.next::<4>();
+    let cluster = reader.next::<4>();
+    let capacity = reader.next::<2>();
+    let timestamp = reader.next::<8>();
+
+    let data = (0..rows)
+        .map(|i| {
+            let cluster = u32::from_le_bytes(value(cluster, i));
+            let timestamp = i64::from_le_bytes(value(timestamp, i));
+            Ok(MergedData {
+                id,
+                hour: hour[i] as u32,
+                minute: minute[i] as u32,
+                day: day[i] as u32,
+                month: month[i] as u32,
+                week_day: week_day[i] as u32,
+                holidays: holidays[i] != 0,
+                free_stands: u16::from_le_bytes(value(free_stands, i)) as u32,
+                available_bikes: u16::from_le_bytes(value(available_bikes, i)) as u32,
+                precipitation: f32::from_le_bytes(value(precipitation, i)),
+                temperature: f32::from_le_bytes(value(temperature, i)),
+                wind_speed: f32::from_le_bytes(value(wind_speed, i)),
+                cluster: (cluster != NO_CLUSTER).then_some(cluster),
+                capacity: u16::from_le_bytes(value(capacity, i)) as u32,
+                timestamp: DateTime::from_timestamp(timestamp, 0)
+                    .ok_or_else(|| format!("invalid timestamp {}", timestamp))?,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok((id, data))
+}
+
+/// Appends a fixed-width column to the encoded columns.
+fn put<const N: usize>(
+    columns: &mut Vec<u8>,
+    data: &[MergedData],
+    value: impl Fn(&MergedData) -> [u8; N],
+) {
+    for d in data {
+        columns.extend_from_slice(&value(d));
+    }
+}
+
+/// Reads the `i`-th value of a column made of values of `N` bytes.
+fn value<const N: usize>(column: &[u8], i: usize) -> [u8; N] {
+    column[i * N..(i + 1) * N].try_into().unwrap()
+}
+
+/// Splits the fixed-width columns one after the other, without copying them.
+struct ColumnReader<'a> {
+    columns: &'a [u8],
+    rows: usize,
+}
+
+impl<'a> ColumnReader<'a> {
+    /// Returns the bytes of the next column, made of values of `N` bytes.
+    fn next<const N: usize>(&mut self) -> &'a [u8] {
+        let (column, rest) = self.columns.split_at(self.rows * N);
+        self.columns = rest;
+        column
+    }
+}
+
+/// Converts the merged data files of a directory to another storage format.
+///
+/// The stations are converted one at a time, and the files in the previous format are removed.
+///
+/// # Arguments
+///
+/// * `dir` - The directory of the merged data.
+/// * `format` - The storage format to convert to.
+///
+/// # Examples
+///
+/// ```rust
+/// convert_merged_data(Path::new("merged_data"), StorageFormat::BinaryZstd);
+/// ```
+pub fn convert_merged_data(dir: &Path, format: StorageFormat) {
+    info!("🔄 Converting {} to {:?}..", dir.display(), format);
+    let files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.file_name().is_some_and(|name| name != ".gitkeep"))
+        .collect();
+
+    let converted = files
+        .par_iter()
+        .filter(|path| match read_station(path) {
+            Ok(Some((id, data))) => match write_station(dir, id, &data, format) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("❌ Failed to write station {}: {}", id, e);
+                    false
+                }
+            },
+            Ok(None) => {
+                warn!("❌ {} is empty, skipping it", path.display());
+                false
+            }
+            Err(e) => {
+                error!("❌ Failed to read {}", e);
+                false
+            }
+        })
+        .count();
+    info!("✅ {}/{} stations converted!", converted, files.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> Vec<MergedData> {
+        (0..3)
+            .map(|i| MergedData {
+                id: 1001,
+                hour: 8 + i,
+                minute: 15 * i,
+                day: 12,
+                month: 4,
+                week_day: 1,
+                holidays: i == 1,
+                free_stands: 10 - i,
+                available_bikes: 5 + i,
+                precipitation: 0.2 * i as f32,
+                temperature: 14.5,
+                wind_speed: 9.1,
+                cluster: (i > 0).then_some(i),
+                capacity: 15,
+                timestamp: DateTime::from_timestamp(1_649_750_400 + 900 * i as i64, 0).unwrap(),
+            })
+            .collect()
+    }
+
+    fn assert_round_trip(compress: bool) {
+        let data = data();
+
+        let (id, decoded) = decode(&encode(1001, &data, compress).unwrap()).unwrap();
+
+        assert_eq!(id, 1001);
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&data).unwrap()
+        );
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        assert_round_trip(false);
+    }
+
+    #[test]
+    fn binary_zstd_round_trip() {
+        assert_round_trip(true);
+    }
+
+    #[test]
+    fn rejects_counts_overflowing_their_column() {
+        let mut data = data();
+        data[1].capacity = u16::MAX as u32 + 1;
+
+        assert!(encode(1001, &data, false).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let bytes = encode(1001, &data(), false).unwrap();
+
+        assert!(decode(b"not merged data").is_err());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut oversized = bytes.clone();
+        oversized[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decode(&oversized).is_err());
+    }
+}
@@ -24,6 +24,10 @@
 /// The coefficients `HOUR_COEF`, `DAY_COEF`, `TEMPERATURE_COEF`, `PRECIPITATION_COEF`, and `WIND_SPEED_COEF`
 /// are used to weight the respective components of the distance calculation.
 use crate::learning::MergedData;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Deserializer, Serialize};
 
 const HOUR_COEF: f32 = 1.0;
 const DAY_COEF: f32 = 1.0;
@@ -48,3 +52,277 @@ pub fn distance(a: &MergedData, b: &MergedData) -> f32 {
         + (HOLIDAYS_COEF * ((a.holidays as i32 - b.holidays as i32) as f32 / 1.0).powf(2.0)))
     .sqrt()
 }
+
+/// Mean radius of the Earth, in kilometres.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Calculates the great-circle distance between two coordinates using the haversine formula.
+///
+/// # Parameters
+///
+/// - `a`: The latitude and longitude of the first point, in degrees.
+/// - `b`: The latitude and longitude of the second point, in degrees.
+///
+/// # Returns
+///
+/// A `f64` value representing the distance between the two points, in kilometres.
+pub fn haversine(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat_a, lon_a) = (a.0.to_radians(), a.1.to_radians());
+    let (lat_b, lon_b) = (b.0.to_radians(), b.1.to_radians());
+
+    let h = ((lat_b - lat_a) / 2.0).sin().powi(2)
+        + lat_a.cos() * lat_b.cos() * ((lon_b - lon_a) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+/// Number of nearest data points used to estimate the distribution of a prediction.
+pub const NEIGHBOURS: usize = 20;
+
+/// Finds the `k` data points the closest to the wanted point.
+///
+/// # Parameters
+///
+/// - `data`: The data points to search.
+/// - `wanted`: The data point to look for.
+/// - `k`: The number of data points to return.
+///
+/// # Returns
+///
+/// The `k` nearest data points, sorted from the nearest to the farthest.
+pub fn k_nearest<'a>(data: &'a [MergedData], wanted: &MergedData, k: usize) -> Vec<&'a MergedData> {
+    let mut distances: Vec<(f32, &MergedData)> =
+        data.par_iter().map(|d| (distance(d, wanted), d)).collect();
+    let k = k.min(distances.len());
+    if k == 0 {
+        return Vec::new();
+    }
+    distances.select_nth_unstable_by(k - 1, |a, b| a.0.partial_cmp(&b.0).unwrap());
+    distances.truncate(k);
+    distances.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    distances.into_iter().map(|(_, d)| d).collect()
+}
+
+/// The 10th, 50th and 90th percentiles of a predicted value.
+///
+/// # Fields
+///
+/// * `p10` - The 10th percentile.
+/// * `p50` - The median.
+/// * `p90` - The 90th percentile.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Quantiles {
+    pub p10: u32,
+    pub p50: u32,
+    pub p90: u32,
+}
+
+impl Quantiles {
+    /// Computes the quantiles of a set of values with the nearest-rank method.
+    ///
+    /// Returns quantiles of zero if there is no value.
+    pub fn from_values(values: &[u32]) -> Self {
+        let mut sorted = values.to_vec();
+        sorted.sort_unstable();
+        Quantiles {
+            p10: quantile(&sorted, 0.1),
+            p50: quantile(&sorted, 0.5),
+            p90: quantile(&sorted, 0.9),
+        }
+    }
+
+    /// Quantiles of a value known for sure.
+    pub fn exact(value: u32) -> Self {
+        Quantiles {
+            p10: value,
+            p50: value,
+            p90: value,
+        }
+    }
+}
+
+/// Returns the `q` quantile of sorted values, or zero if there is no value.
+fn quantile(sorted: &[u32], q: f32) -> u32 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    sorted[(q * (sorted.len() - 1) as f32).round() as usize]
+}
+
+/// Calculates the pinball loss of a predicted quantile.
+///
+/// # Parameters
+///
+/// - `q`: The quantile level, between 0 and 1.
+/// - `actual`: The observed value.
+/// - `predicted`: The predicted quantile.
+///
+/// # Returns
+///
+/// A `f32` value representing the loss, which is lower for better calibrated quantiles.
+pub fn pinball_loss(q: f32, actual: f32, predicted: f32) -> f32 {
+    let diff = actual - predicted;
+    (q * diff).max((q - 1.0) * diff)
+}
+
+/// Converts a date to the local time of a network, in which all the time features are computed.
+pub fn local_time(date: DateTime<Utc>, timezone: Tz) -> DateTime<Tz> {
+    date.with_timezone(&timezone)
+}
+
+/// Returns the local midnight starting the day of a date.
+pub fn start_of_day(date: DateTime<Tz>) -> DateTime<Tz> {
+    date.timezone()
+        .from_local_datetime(&date.date_naive().and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .unwrap_or(date)
+}
+
+/// A date given by the API users, resolved in the time zone of the network it is about.
+///
+/// RFC 3339 dates with an offset are converted to the local time of the network, and dates
+/// without offset are interpreted as local times. Ambiguous local times, during the autumn DST
+/// transition, resolve to the earliest instant, and nonexistent local times, during the spring
+/// DST transition, are rejected.
+///
+/// An unencoded `+` in a query string decodes to a space, so a space before the offset, as in
+/// `2024-06-01T08:00:00 02:00`, is read as a `+`.
+///
+/// # Variants
+///
+/// * `Offset` - A date with an offset.
+/// * `Naive` - A local date, without offset.
+///
+/// # Example
+///
+/// ```rust
+/// #[derive(Deserialize)]
+/// struct Example {
+///     date: LocalDateTime,
+/// }
+///
+/// let date = example.date.in_timezone(network.timezone)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LocalDateTime {
+    Offset(DateTime<FixedOffset>),
+    Naive(NaiveDateTime),
+}
+
+impl<'de> Deserialize<'de> for LocalDateTime {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let s = match s.rsplit_once(' ') {
+            Some((date, offset)) if date.contains('T') => format!("{}+{}", date, offset),
+            _ => s,
+        };
+        if let Ok(date) = DateTime::parse_from_rfc3339(&s) {
+            return Ok(LocalDateTime::Offset(date));
+        }
+        s.parse::<NaiveDateTime>()
+            .map(LocalDateTime::Naive)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl LocalDateTime {
+    /// Resolves the date in the time zone of a network.
+    ///
+    /// # Arguments
+    ///
+    /// * `timezone` - The time zone of the network.
+    ///
+    /// # Returns
+    ///
+    /// * The local date, or an error message if the local date does not exist in the time zone.
+    pub fn in_timezone(self, timezone: Tz) -> Result<DateTime<Tz>, String> {
+        match self {
+            LocalDateTime::Offset(date) => Ok(date.with_timezone(&timezone)),
+            LocalDateTime::Naive(naive) => timezone
+                .from_local_datetime(&naive)
+                .earliest()
+                .ok_or_else(|| format!("{} does not exist in {}", naive, timezone)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Params {
+        date: LocalDateTime,
+    }
+
+    fn parse_in(date: &str, timezone: Tz) -> Result<DateTime<Utc>, String> {
+        serde_json::from_value::<Params>(serde_json::json!({ "date": date }))
+            .map_err(|e| e.to_string())?
+            .date
+            .in_timezone(timezone)
+            .map(|date| date.with_timezone(&Utc))
+    }
+
+    fn parse(date: &str) -> Result<DateTime<Utc>, String> {
+        parse_in(date, chrono_tz::Europe::Paris)
+    }
+
+    fn utc(date: &str) -> DateTime<Utc> {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn local_date_time_converts_offsets() {
+        assert_eq!(
+            parse("2024-06-01T08:00:00+02:00").unwrap(),
+            utc("2024-06-01T06:00:00Z")
+        );
+        assert_eq!(
+            parse("2024-06-01T06:00:00Z").unwrap(),
+            utc("2024-06-01T06:00:00Z")
+        );
+        assert_eq!(
+            parse("2024-06-01T08:00:00 02:00").unwrap(),
+            utc("2024-06-01T06:00:00Z")
+        );
+        assert_eq!(
+            parse("2024-01-15T08:00:00").unwrap(),
+            utc("2024-01-15T07:00:00Z")
+        );
+    }
+
+    #[test]
+    fn local_date_time_rejects_nonexistent_times() {
+        assert!(parse("2024-03-31T02:30:00").is_err());
+        assert!(parse("2024-03-31T02:30:00+01:00").is_ok());
+    }
+
+    #[test]
+    fn local_date_time_resolves_ambiguous_times_to_the_earliest() {
+        assert_eq!(
+            parse("2024-10-27T02:30:00").unwrap(),
+            utc("2024-10-27T00:30:00Z")
+        );
+        assert_eq!(
+            parse("2024-10-27T02:30:00+01:00").unwrap(),
+            utc("2024-10-27T01:30:00Z")
+        );
+    }
+
+    #[test]
+    fn local_date_time_uses_the_time_zone_of_the_network() {
+        let new_york = chrono_tz::America::New_York;
+        assert_eq!(
+            parse_in("2024-01-15T08:00:00", new_york).unwrap(),
+            utc("2024-01-15T13:00:00Z")
+        );
+        assert_eq!(
+            parse_in("2024-01-15T08:00:00+01:00", new_york).unwrap(),
+            utc("2024-01-15T07:00:00Z")
+        );
+        assert!(parse("2024-01-15").is_err());
+    }
+}